@@ -0,0 +1,481 @@
+use nibble_8_core::args::{parse_number, parse_platform, parse_quirks, parse_tickrate};
+use nibble_8_core::audio::{BeeperConfig, Waveform};
+use nibble_8_core::capture::CaptureFormat;
+use nibble_8_core::trace::{TraceFilter, parse_pc_range};
+use nibble_8_core::{Platform, Quirks};
+
+pub const USAGE: &str = "\
+Usage: nibble-8-cli <ROM> [options]
+
+Runs a ROM without a window and prints the final framebuffer.
+
+Options:
+  --platform <chip8|schip|xochip>  Machine to emulate (default: from the ROM
+                                   database, else chip8)
+  --quirks <preset>                vip, chip48, schip, xochip or modern
+                                   (default: from the ROM database, else the
+                                   platform's usual quirks)
+  --frames <N>                     Number of 60 Hz frames to run (default: 600)
+  --ipf <N>                        Instructions per frame (default: from the ROM
+                                   database, else 10)
+  --rom-db <FILE>                  JSON file of local ROM database overrides
+  --vip-timing                     Run each frame by COSMAC VIP machine cycles
+                                   instead of --ipf
+  --until-pc <ADDR>                Stop as soon as PC reaches ADDR
+  --seed <N>                       Seed for the random number generator
+                                   (default: a random seed)
+  --key <FRAME:KEY[:DURATION]>     Hold keypad KEY (0-F) from FRAME for DURATION
+                                   frames (default: 5), can be repeated
+  --format <ascii|pbm>             Framebuffer output format (default: ascii)
+  --output <FILE>                  Write the framebuffer to FILE instead of stdout
+  --audio <FILE>                   Write the buzzer output to FILE as WAV, the
+                                   XO-CHIP audio pattern included
+  --sample-rate <N>                Sample rate of --audio in Hz (default: 44100)
+  --waveform <square|sine|saw>     Waveform of the --audio tone (default: square)
+  --volume <N>                     Volume of --audio in percent, 0-100 (default: 25)
+  --pitch <HZ>                     Frequency of the --audio tone, 20-20000
+                                   (default: 440)
+  --capture <FILE>                 Save the display to FILE: a still of the last
+                                   frame for .png, an animation for .gif or .apng
+  --capture-seconds <N>            Length of animated captures, counted back from
+                                   the end of the run (default: 10)
+  --capture-scale <N>              Image pixels per CHIP-8 pixel, 1-32 (default: 4)
+  --trace <FILE>                   Log every executed instruction to FILE
+  --trace-format <text|binary>     Trace log format (default: text)
+  --trace-pc <START-END>           Only trace instructions in this address range
+  --trace-only <NAME,...>          Only trace these instructions, e.g. Draw,Call
+  --record-movie <FILE>            Record the keypad input of the run to FILE
+  --play-movie <FILE>              Replay a recorded movie, replaces --platform,
+                                   --quirks, --ipf, --frames and --key
+  -h, --help                       Print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Ascii,
+    Pbm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub duration: u64,
+}
+
+impl KeyPress {
+    pub fn is_held(&self, frame: u64) -> bool {
+        (self.frame..self.frame.saturating_add(self.duration)).contains(&frame)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub frames: u64,
+    pub instructions_per_frame: Option<u32>,
+    pub rom_db: Option<String>,
+    pub vip_timing: bool,
+    pub until_pc: Option<u16>,
+    pub seed: Option<u64>,
+    pub key_presses: Vec<KeyPress>,
+    pub format: OutputFormat,
+    pub output: Option<String>,
+    pub audio: Option<String>,
+    pub sample_rate: u32,
+    pub beeper: BeeperConfig,
+    pub capture: Option<(String, CaptureFormat)>,
+    pub capture_seconds: u32,
+    pub capture_scale: u32,
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+}
+
+/// Parses the command line, `Ok(None)` means help was requested.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut frames = 600;
+    let mut instructions_per_frame = None;
+    let mut rom_db = None;
+    let mut vip_timing = false;
+    let mut until_pc = None;
+    let mut seed = None;
+    let mut key_presses = Vec::new();
+    let mut format = OutputFormat::Ascii;
+    let mut output = None;
+    let mut audio = None;
+    let mut sample_rate = 44100;
+    let mut beeper = BeeperConfig::default();
+    let mut capture = None;
+    let mut capture_seconds = 10;
+    let mut capture_scale = 4;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+    let mut record_movie = None;
+    let mut play_movie = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--platform" => platform = Some(parse_platform(&value("--platform")?)?),
+            "--quirks" => quirks = Some(parse_quirks(&value("--quirks")?)?),
+            "--frames" => frames = parse_number(&value("--frames")?)?,
+            "--ipf" => instructions_per_frame = Some(parse_tickrate(&value("--ipf")?)?),
+            "--rom-db" => rom_db = Some(value("--rom-db")?),
+            "--vip-timing" => vip_timing = true,
+            "--until-pc" => until_pc = Some(parse_number(&value("--until-pc")?)?),
+            "--seed" => seed = Some(parse_number(&value("--seed")?)?),
+            "--key" => key_presses.push(parse_key_press(&value("--key")?)?),
+            "--format" => {
+                format = match value("--format")?.as_str() {
+                    "ascii" => OutputFormat::Ascii,
+                    "pbm" => OutputFormat::Pbm,
+                    other => return Err(format!("Unknown output format '{}'", other)),
+                }
+            }
+            "--output" => output = Some(value("--output")?),
+            "--audio" => audio = Some(value("--audio")?),
+            "--sample-rate" => {
+                let text = value("--sample-rate")?;
+                sample_rate = parse_number(&text)?;
+                if !(8000..=192000).contains(&sample_rate) {
+                    return Err(format!(
+                        "Invalid sample rate '{}', expected 8000-192000",
+                        text
+                    ));
+                }
+            }
+            "--waveform" => {
+                let name = value("--waveform")?;
+                beeper.waveform = Waveform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown waveform '{}'", name))?;
+            }
+            "--volume" => {
+                let text = value("--volume")?;
+                let percent: u32 = parse_number(&text)?;
+                if percent > 100 {
+                    return Err(format!("Invalid volume '{}', expected 0-100", text));
+                }
+                beeper.volume = percent as f32 / 100.0;
+            }
+            "--pitch" => {
+                let text = value("--pitch")?;
+                let hertz: u32 = parse_number(&text)?;
+                if !(20..=20000).contains(&hertz) {
+                    return Err(format!("Invalid pitch '{}', expected 20-20000", text));
+                }
+                beeper.frequency = hertz as f32;
+            }
+            "--capture" => {
+                let path = value("--capture")?;
+                let format = CaptureFormat::from_path(&path).ok_or_else(|| {
+                    format!(
+                        "Unknown capture format '{}', expected .png, .gif or .apng",
+                        path
+                    )
+                })?;
+                capture = Some((path, format));
+            }
+            "--capture-seconds" => {
+                let text = value("--capture-seconds")?;
+                capture_seconds = parse_number(&text)?;
+                if !(1..=600).contains(&capture_seconds) {
+                    return Err(format!("Invalid capture length '{}', expected 1-600", text));
+                }
+            }
+            "--capture-scale" => {
+                let text = value("--capture-scale")?;
+                capture_scale = parse_number(&text)?;
+                if !(1..=32).contains(&capture_scale) {
+                    return Err(format!("Invalid scale '{}', expected 1-32", text));
+                }
+            }
+            "--trace" => trace = Some(value("--trace")?),
+            "--trace-format" => {
+                trace_format = match value("--trace-format")?.as_str() {
+                    "text" => TraceFormat::Text,
+                    "binary" => TraceFormat::Binary,
+                    other => return Err(format!("Unknown trace format '{}'", other)),
+                }
+            }
+            "--trace-pc" => trace_filter.pc_range = Some(parse_pc_range(&value("--trace-pc")?)?),
+            "--trace-only" => trace_filter.instructions.extend(
+                value("--trace-only")?
+                    .split(',')
+                    .map(|name| name.trim().to_string()),
+            ),
+            "--record-movie" => record_movie = Some(value("--record-movie")?),
+            "--play-movie" => play_movie = Some(value("--play-movie")?),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if rom_path.replace(path.to_string()).is_some() {
+                    return Err("Only one ROM can be given".to_string());
+                }
+            }
+        }
+    }
+
+    if record_movie.is_some() && play_movie.is_some() {
+        return Err("--record-movie and --play-movie can't be combined".to_string());
+    }
+
+    Ok(Some(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        platform,
+        quirks,
+        frames,
+        instructions_per_frame,
+        rom_db,
+        vip_timing,
+        until_pc,
+        seed,
+        key_presses,
+        format,
+        output,
+        audio,
+        sample_rate,
+        beeper,
+        capture,
+        capture_seconds,
+        capture_scale,
+        trace,
+        trace_format,
+        trace_filter,
+        record_movie,
+        play_movie,
+    }))
+}
+
+fn parse_key_press(text: &str) -> Result<KeyPress, String> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if !(2..=3).contains(&parts.len()) {
+        return Err(format!(
+            "Invalid key press '{}', expected FRAME:KEY[:DURATION]",
+            text
+        ));
+    }
+
+    let key = u8::from_str_radix(parts[1], 16)
+        .ok()
+        .filter(|&key| key < 16)
+        .ok_or_else(|| format!("Invalid key '{}', expected 0-F", parts[1]))?;
+
+    Ok(KeyPress {
+        frame: parse_number(parts[0])?,
+        key,
+        duration: parts
+            .get(2)
+            .map_or(Ok(5), |duration| parse_number(duration))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Option<Options>, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse_args(&["test.ch8"]).unwrap().unwrap();
+
+        assert_eq!(options.rom_path, "test.ch8");
+        assert_eq!(options.platform, None);
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.instructions_per_frame, None);
+        assert_eq!(options.frames, 600);
+        assert_eq!(options.format, OutputFormat::Ascii);
+        assert_eq!(options.sample_rate, 44100);
+        assert_eq!(options.beeper, BeeperConfig::default());
+        assert_eq!(options.capture, None);
+        assert_eq!(options.capture_seconds, 10);
+        assert_eq!(options.capture_scale, 4);
+    }
+
+    #[test]
+    fn test_all_options() {
+        let options = parse_args(&[
+            "--platform",
+            "schip",
+            "--frames",
+            "120",
+            "--ipf",
+            "30",
+            "--vip-timing",
+            "--until-pc",
+            "0x3DC",
+            "--seed",
+            "42",
+            "--key",
+            "10:a",
+            "--key",
+            "20:F:2",
+            "--format",
+            "pbm",
+            "--audio",
+            "beep.wav",
+            "--sample-rate",
+            "22050",
+            "--waveform",
+            "saw",
+            "--volume",
+            "80",
+            "--pitch",
+            "0x200",
+            "--capture",
+            "clip.apng",
+            "--capture-seconds",
+            "3",
+            "--capture-scale",
+            "8",
+            "test.ch8",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(options.platform, Some(Platform::SuperChip));
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.instructions_per_frame, Some(30));
+        assert_eq!(options.frames, 120);
+        assert!(options.vip_timing);
+        assert_eq!(options.until_pc, Some(0x3DC));
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(
+            options.key_presses,
+            vec![
+                KeyPress {
+                    frame: 10,
+                    key: 0xA,
+                    duration: 5
+                },
+                KeyPress {
+                    frame: 20,
+                    key: 0xF,
+                    duration: 2
+                },
+            ]
+        );
+        assert_eq!(options.format, OutputFormat::Pbm);
+        assert_eq!(options.audio.as_deref(), Some("beep.wav"));
+        assert_eq!(options.sample_rate, 22050);
+        assert_eq!(
+            options.beeper,
+            BeeperConfig {
+                waveform: Waveform::Sawtooth,
+                volume: 0.8,
+                frequency: 512.0,
+                ..BeeperConfig::default()
+            }
+        );
+        assert_eq!(
+            options.capture,
+            Some(("clip.apng".to_string(), CaptureFormat::Apng))
+        );
+        assert_eq!(options.capture_seconds, 3);
+        assert_eq!(options.capture_scale, 8);
+    }
+
+    #[test]
+    fn test_trace_options() {
+        let options = parse_args(&[
+            "--trace",
+            "out.trace",
+            "--trace-format",
+            "binary",
+            "--trace-pc",
+            "0x200-0x2FF",
+            "--trace-only",
+            "Draw,Call",
+            "test.ch8",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(options.trace.as_deref(), Some("out.trace"));
+        assert_eq!(options.trace_format, TraceFormat::Binary);
+        assert_eq!(options.trace_filter.pc_range, Some(0x200..=0x2FF));
+        assert_eq!(options.trace_filter.instructions, vec!["Draw", "Call"]);
+    }
+
+    #[test]
+    fn test_movie_options() {
+        let options = parse_args(&["--play-movie", "bug.n8m", "test.ch8"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.play_movie.as_deref(), Some("bug.n8m"));
+        assert_eq!(options.record_movie, None);
+
+        assert!(parse_args(&["--play-movie", "a", "--record-movie", "b", "test.ch8"]).is_err());
+    }
+
+    #[test]
+    fn test_key_press_held() {
+        let press = parse_key_press("10:a:3").unwrap();
+        assert!(!press.is_held(9));
+        assert!(press.is_held(12));
+        assert!(!press.is_held(13));
+
+        // Held until the end of time rather than overflowing
+        let press = parse_key_press("1:a:18446744073709551615").unwrap();
+        assert!(press.is_held(u64::MAX - 1));
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert_eq!(parse_args(&[]), Err("Missing ROM path".to_string()));
+        assert_eq!(
+            parse_args(&["a.ch8", "--frames"]),
+            Err("Missing value for --frames".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--key", "1:G"]),
+            Err("Invalid key 'G', expected 0-F".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--capture", "shot.bmp"]),
+            Err("Unknown capture format 'shot.bmp', expected .png, .gif or .apng".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--waveform", "noise"]),
+            Err("Unknown waveform 'noise'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--volume", "101"]),
+            Err("Invalid volume '101', expected 0-100".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--pitch", "10"]),
+            Err("Invalid pitch '10', expected 20-20000".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--ipf", "1000001"]),
+            Err("Invalid instructions per frame '1000001', expected 1-1000000".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--capture-seconds", "0"]),
+            Err("Invalid capture length '0', expected 1-600".to_string())
+        );
+        assert_eq!(parse_args(&["--help"]), Ok(None));
+    }
+}
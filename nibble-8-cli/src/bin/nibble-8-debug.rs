@@ -0,0 +1,638 @@
+use nibble_8_core::condition::Condition;
+use nibble_8_core::cpu::ThreadRngSource;
+use nibble_8_core::debugger::{Debugger, Stop};
+use nibble_8_core::disasm::decode_at;
+use nibble_8_core::memory::{Access, ROM_START, Watchpoint};
+use nibble_8_core::{Bus, Cpu, Platform, Quirks};
+use std::fs::read;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: nibble-8-debug <ROM> [options]
+
+Loads a ROM paused at its first instruction and reads debugger commands from stdin.
+
+Options:
+  --platform <chip8|schip|xochip>  Machine to emulate (default: chip8)
+  --quirks <preset>                vip, chip48, schip, xochip or modern
+                                   (default: the platform's usual quirks)
+  --ipf <N>                        Instructions per 60 Hz timer tick (default: 10)
+  -h, --help                       Print this help";
+
+const COMMANDS: &str = "\
+Commands (an empty line repeats the previous one):
+  break <loc> [if <cond>]  b  Set a breakpoint, optionally conditional
+  cond <loc> [<cond>]         Change or remove a breakpoint's condition
+  delete <loc>             d  Remove a breakpoint
+  watch <rwx> <loc> [<end>]   Stop after reads, writes or instruction fetches
+                              between <loc> and <end>
+  unwatch <N>                 Remove watchpoint number N
+  info                        List breakpoints and watchpoints
+  step [N]                 s  Execute N instructions (default: 1)
+  next                     n  Step, running calls until they return
+  finish                   f  Run until the current subroutine returns
+  until <loc>              u  Run until PC reaches <loc>
+  continue                 c  Run until a breakpoint
+  regs                     r  Show registers, stack and timers
+  x/N <loc>                   Dump N bytes of memory (default: 16)
+  key <K> <up|down>           Release or press keypad key K (0-F)
+  help                     h  Show this help
+  quit                     q  Leave the debugger
+<loc> is a number (decimal or 0x hex), PC or I. <cond> is an expression like
+`V3 == 0x10 && I > 0x300` over V0-VF, I, PC, SP, DT, ST, hits and [<addr>].";
+
+// Keeps `continue` from hanging on a ROM that never reaches a breakpoint
+const RUN_LIMIT: usize = 10_000_000;
+
+struct Options {
+    rom_path: String,
+    platform: Platform,
+    quirks: Option<Quirks>,
+    instructions_per_frame: usize,
+}
+
+fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom_path = None;
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut instructions_per_frame = 10;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--platform" => {
+                let name = args.next().ok_or("Missing value for --platform")?;
+                platform = Platform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown platform '{}'", name))?;
+            }
+            "--quirks" => {
+                let name = args.next().ok_or("Missing value for --quirks")?;
+                quirks = Some(
+                    Quirks::from_preset(&name)
+                        .ok_or_else(|| format!("Unknown quirks preset '{}'", name))?,
+                );
+            }
+            "--ipf" => {
+                let value = args.next().ok_or("Missing value for --ipf")?;
+                instructions_per_frame = value
+                    .parse()
+                    .ok()
+                    .filter(|&ipf| ipf > 0)
+                    .ok_or_else(|| format!("Invalid number '{}'", value))?;
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if rom_path.replace(path.to_string()).is_some() {
+                    return Err("Only one ROM can be given".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        platform,
+        quirks,
+        instructions_per_frame,
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Addr(u16),
+    Pc,
+    I,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Break(Location, Option<Condition>),
+    Condition(Location, Option<Condition>),
+    Delete(Location),
+    Watch(Watchpoint, Location, Option<Location>),
+    Unwatch(usize),
+    Info,
+    Step(usize),
+    Next,
+    Finish,
+    Until(Location),
+    Continue,
+    Regs,
+    Examine(usize, Location),
+    Key(u8, bool),
+    Help,
+    Quit,
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+fn parse_location(text: Option<&str>) -> Result<Location, String> {
+    let text = text.ok_or("Missing location")?;
+    match text.to_ascii_uppercase().as_str() {
+        "PC" => Ok(Location::Pc),
+        "I" => Ok(Location::I),
+        _ => u16::try_from(parse_number(text)?)
+            .map(Location::Addr)
+            .map_err(|_| format!("Address '{}' is out of range", text)),
+    }
+}
+
+fn parse_condition(text: &str) -> Result<Option<Condition>, String> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    Condition::parse(text).map(Some)
+}
+
+// The start and end are filled in from the locations once they can be resolved
+fn parse_access(text: Option<&str>) -> Result<Watchpoint, String> {
+    let text = text.ok_or("Missing access, expected a mix of r, w and x")?;
+    if text.is_empty() || !text.chars().all(|c| "rwx".contains(c)) {
+        return Err(format!(
+            "Invalid access '{}', expected a mix of r, w and x",
+            text
+        ));
+    }
+    Ok(Watchpoint {
+        start: 0,
+        end: 0,
+        read: text.contains('r'),
+        write: text.contains('w'),
+        execute: text.contains('x'),
+    })
+}
+
+/// Parses a command line, `Ok(None)` for an empty one.
+fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+
+    let command = match name {
+        "break" | "b" => {
+            let location = parse_location(words.next())?;
+            let condition = match words.next() {
+                Some("if") => Some(Condition::parse(
+                    &words.by_ref().collect::<Vec<_>>().join(" "),
+                )?),
+                Some(other) => return Err(format!("Expected 'if', found '{}'", other)),
+                None => None,
+            };
+            Command::Break(location, condition)
+        }
+        "cond" => {
+            let location = parse_location(words.next())?;
+            let condition = parse_condition(&words.by_ref().collect::<Vec<_>>().join(" "))?;
+            Command::Condition(location, condition)
+        }
+        "delete" | "d" => Command::Delete(parse_location(words.next())?),
+        "watch" => {
+            let watchpoint = parse_access(words.next())?;
+            let start = parse_location(words.next())?;
+            let end = words
+                .next()
+                .map(|end| parse_location(Some(end)))
+                .transpose()?;
+            Command::Watch(watchpoint, start, end)
+        }
+        "unwatch" => Command::Unwatch(parse_number(words.next().ok_or("Missing number")?)?),
+        "info" => Command::Info,
+        "step" | "s" => Command::Step(words.next().map_or(Ok(1), parse_number)?),
+        "next" | "n" => Command::Next,
+        "finish" | "f" => Command::Finish,
+        "until" | "u" => Command::Until(parse_location(words.next())?),
+        "continue" | "c" => Command::Continue,
+        "regs" | "r" => Command::Regs,
+        "help" | "h" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        "key" => {
+            let key = words
+                .next()
+                .and_then(|key| u8::from_str_radix(key, 16).ok())
+                .filter(|&key| key < 16)
+                .ok_or("Expected a key from 0 to F")?;
+            let pressed = match words.next() {
+                Some("down") => true,
+                Some("up") => false,
+                _ => return Err("Expected 'up' or 'down'".to_string()),
+            };
+            Command::Key(key, pressed)
+        }
+        examine if examine == "x" || examine.starts_with("x/") => {
+            let count = match examine.strip_prefix("x/") {
+                Some(count) => parse_number(count)?,
+                None => 16,
+            };
+            Command::Examine(count, parse_location(words.next())?)
+        }
+        other => return Err(format!("Unknown command '{}', try 'help'", other)),
+    };
+
+    match words.next() {
+        Some(extra) => Err(format!("Unexpected argument '{}'", extra)),
+        None => Ok(Some(command)),
+    }
+}
+
+struct Session {
+    cpu: Cpu,
+    bus: Bus,
+    debugger: Debugger,
+    instructions_per_frame: usize,
+    // Instructions executed since the timers last ticked
+    frame_progress: usize,
+}
+
+impl Session {
+    fn resolve(&self, location: Location) -> u16 {
+        match location {
+            Location::Addr(addr) => addr,
+            Location::Pc => self.cpu.pc(),
+            Location::I => self.cpu.i(),
+        }
+    }
+
+    /// Runs the current debugger command to completion, reporting anything but `Stop::Done`.
+    fn advance(&mut self) -> Option<Stop> {
+        let mut executed = 0;
+        while self.debugger.is_running() {
+            if executed >= RUN_LIMIT {
+                self.debugger.pause();
+                println!("Paused after {} instructions", executed);
+                return None;
+            }
+
+            let budget = self.instructions_per_frame - self.frame_progress;
+            let outcome = self.debugger.run(&mut self.cpu, &mut self.bus, budget);
+            executed += outcome.instructions;
+            self.frame_progress += outcome.instructions;
+            if self.frame_progress == self.instructions_per_frame {
+                self.cpu.decrease_timers();
+                self.frame_progress = 0;
+            }
+
+            match outcome.stop {
+                Some(Stop::Breakpoint(addr)) => println!("Breakpoint at {:#05X}", addr),
+                Some(Stop::Watchpoint { pc, hit }) => {
+                    let access = match hit.access {
+                        Access::Read => "read",
+                        Access::Write => "wrote",
+                        Access::Execute => "fetched",
+                    };
+                    println!(
+                        "Watchpoint: {:#05X} {} {:#04X} at {:#05X}",
+                        pc, access, hit.value, hit.addr
+                    );
+                }
+                Some(Stop::Halted) => println!("ROM exited"),
+                Some(Stop::Fault(err)) => println!("Fault: {}", err),
+                Some(Stop::Done) | None => {}
+            }
+            if outcome.stop.is_some() {
+                return outcome.stop;
+            }
+        }
+        None
+    }
+
+    fn run(&mut self) {
+        self.advance();
+        self.print_current();
+    }
+
+    fn print_current(&self) {
+        let pc = self.cpu.pc();
+        let rom = &self.bus.memory()[ROM_START as usize..];
+        match decode_at(rom, pc, self.bus.platform()) {
+            Some((instruction, _)) => println!("{:#05X}: {}", pc, instruction),
+            None => println!("{:#05X}: ???", pc),
+        }
+    }
+
+    fn print_registers(&self) {
+        for (row, values) in self.cpu.v_registers().chunks(8).enumerate() {
+            let line: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(offset, value)| format!("V{:X}={:02X}", row * 8 + offset, value))
+                .collect();
+            println!("{}", line.join(" "));
+        }
+        println!(
+            "I={:#05X} PC={:#05X} SP={} DT={} ST={}",
+            self.cpu.i(),
+            self.cpu.pc(),
+            self.cpu.sp(),
+            self.cpu.delay_timer(),
+            self.cpu.sound_timer()
+        );
+        let stack: Vec<String> = self
+            .cpu
+            .stack()
+            .iter()
+            .map(|addr| format!("{:#05X}", addr))
+            .collect();
+        println!("Stack: [{}]", stack.join(", "));
+    }
+
+    fn examine(&self, count: usize, addr: u16) {
+        match dump_memory(self.bus.memory(), addr, count) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Returns `false` once the user wants to leave.
+    fn execute(&mut self, command: Command) -> bool {
+        match command {
+            Command::Break(location, condition) => {
+                let addr = self.resolve(location);
+                if !self.debugger.add_breakpoint(addr) {
+                    println!("There already is a breakpoint at {:#05X}", addr);
+                    return true;
+                }
+                match &condition {
+                    Some(condition) => println!("Breakpoint set at {:#05X} if {}", addr, condition),
+                    None => println!("Breakpoint set at {:#05X}", addr),
+                }
+                self.debugger.set_condition(addr, condition);
+            }
+            Command::Condition(location, condition) => {
+                let addr = self.resolve(location);
+                if !self.debugger.set_condition(addr, condition) {
+                    println!("No breakpoint at {:#05X}", addr);
+                }
+            }
+            Command::Watch(mut watchpoint, start, end) => {
+                watchpoint.start = self.resolve(start) as usize;
+                watchpoint.end = end.map_or(watchpoint.start, |end| self.resolve(end) as usize);
+                if watchpoint.end < watchpoint.start {
+                    println!("The end of the range comes before its start");
+                    return true;
+                }
+                self.bus.add_watchpoint(watchpoint);
+                println!("Watchpoint {} set", self.bus.watchpoints().len() - 1);
+            }
+            Command::Unwatch(index) => {
+                if self.bus.remove_watchpoint(index).is_none() {
+                    println!("No watchpoint {}", index);
+                }
+            }
+            Command::Delete(location) => {
+                let addr = self.resolve(location);
+                if self.debugger.remove_breakpoint(addr) {
+                    println!("Breakpoint at {:#05X} removed", addr);
+                } else {
+                    println!("No breakpoint at {:#05X}", addr);
+                }
+            }
+            Command::Info => {
+                if self.debugger.breakpoints().is_empty() && self.bus.watchpoints().is_empty() {
+                    println!("No breakpoints or watchpoints");
+                }
+                for (addr, breakpoint) in self.debugger.breakpoints() {
+                    print!("Breakpoint at {:#05X}, hit {} times", addr, breakpoint.hits);
+                    match &breakpoint.condition {
+                        Some(condition) => println!(", if {}", condition),
+                        None => println!(),
+                    }
+                }
+                for (index, watchpoint) in self.bus.watchpoints().iter().enumerate() {
+                    let access: String = [
+                        (watchpoint.read, 'r'),
+                        (watchpoint.write, 'w'),
+                        (watchpoint.execute, 'x'),
+                    ]
+                    .iter()
+                    .filter(|(enabled, _)| *enabled)
+                    .map(|(_, c)| c)
+                    .collect();
+                    println!(
+                        "Watchpoint {}: {} {:#05X}-{:#05X}",
+                        index, access, watchpoint.start, watchpoint.end
+                    );
+                }
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    self.debugger.step();
+                    if self.advance() != Some(Stop::Done) {
+                        break;
+                    }
+                }
+                self.print_current();
+            }
+            Command::Next => {
+                self.debugger.step_over(&self.cpu, &self.bus);
+                self.run();
+            }
+            Command::Finish => {
+                self.debugger.step_out(&self.cpu);
+                self.run();
+            }
+            Command::Until(location) => {
+                self.debugger.run_to(self.resolve(location));
+                self.run();
+            }
+            Command::Continue => {
+                self.debugger.resume();
+                self.run();
+            }
+            Command::Regs => self.print_registers(),
+            Command::Examine(count, location) => self.examine(count, self.resolve(location)),
+            Command::Key(key, pressed) => self.bus.set_key(key, pressed),
+            Command::Help => println!("{}", COMMANDS),
+            Command::Quit => return false,
+        }
+        true
+    }
+}
+
+/// Up to `count` bytes from `addr` on, eight per line. Stops at the end of `memory`.
+fn dump_memory(memory: &[u8], addr: u16, count: usize) -> Result<Vec<String>, String> {
+    let start = addr as usize;
+    if start >= memory.len() {
+        return Err(format!(
+            "Address {:#05X} out of range, memory ends at {:#05X}",
+            addr,
+            memory.len() - 1
+        ));
+    }
+    let end = start.saturating_add(count).min(memory.len());
+    let lines = memory[start..end]
+        .chunks(8)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:#05X}: {}", start + row * 8, hex.join(" "))
+        })
+        .collect();
+    Ok(lines)
+}
+
+pub fn main() -> ExitCode {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.rom_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut bus = Bus::with_platform(options.platform);
+    if let Err(err) = bus.load_rom(&rom) {
+        eprintln!("error: {}", err);
+        return ExitCode::FAILURE;
+    }
+    let quirks = options
+        .quirks
+        .unwrap_or_else(|| options.platform.default_quirks());
+
+    let mut session = Session {
+        cpu: Cpu::new(Box::new(ThreadRngSource::new()), quirks),
+        bus,
+        debugger: Debugger::new(),
+        instructions_per_frame: options.instructions_per_frame,
+        frame_progress: 0,
+    };
+    session.print_current();
+
+    let mut last_command: Option<Command> = None;
+    let stdin = io::stdin();
+    loop {
+        print!("(nibble-8) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+
+        let command = match parse_command(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => match &last_command {
+                Some(command) => command.clone(),
+                None => continue,
+            },
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+        last_command = Some(command.clone());
+
+        if !session.execute(command) {
+            break;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse_command("break 0x2a4"),
+            Ok(Some(Command::Break(Location::Addr(0x2A4), None)))
+        );
+        assert_eq!(
+            parse_command("b 0x2a4 if V3 == 0x10 && I > 0x300"),
+            Ok(Some(Command::Break(
+                Location::Addr(0x2A4),
+                Some(Condition::parse("V3 == 0x10 && I > 0x300").unwrap())
+            )))
+        );
+        assert_eq!(
+            parse_command("cond 0x2a4"),
+            Ok(Some(Command::Condition(Location::Addr(0x2A4), None)))
+        );
+        assert_eq!(
+            parse_command("watch rw 0x300 0x30f"),
+            Ok(Some(Command::Watch(
+                Watchpoint {
+                    start: 0,
+                    end: 0,
+                    read: true,
+                    write: true,
+                    execute: false
+                },
+                Location::Addr(0x300),
+                Some(Location::Addr(0x30F))
+            )))
+        );
+        assert_eq!(parse_command("step"), Ok(Some(Command::Step(1))));
+        assert_eq!(parse_command("s 5"), Ok(Some(Command::Step(5))));
+        assert_eq!(parse_command("regs"), Ok(Some(Command::Regs)));
+        assert_eq!(
+            parse_command("x/16 I"),
+            Ok(Some(Command::Examine(16, Location::I)))
+        );
+        assert_eq!(
+            parse_command("until pc"),
+            Ok(Some(Command::Until(Location::Pc)))
+        );
+        assert_eq!(
+            parse_command("key a down"),
+            Ok(Some(Command::Key(0xA, true)))
+        );
+        assert_eq!(parse_command("   "), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_command_errors() {
+        assert!(parse_command("jump").is_err());
+        assert!(parse_command("break").is_err());
+        assert!(parse_command("break 0x10000").is_err());
+        assert!(parse_command("regs now").is_err());
+        assert!(parse_command("key G up").is_err());
+        assert!(parse_command("break 0x200 when V0").is_err());
+        assert!(parse_command("break 0x200 if V0 ==").is_err());
+        assert!(parse_command("watch q 0x300").is_err());
+    }
+
+    #[test]
+    fn test_dump_memory() {
+        let memory: Vec<u8> = (0..0x1000).map(|addr| addr as u8).collect();
+        assert_eq!(
+            dump_memory(&memory, 0xFF6, 16),
+            Ok(vec![
+                "0xFF6: F6 F7 F8 F9 FA FB FC FD".to_string(),
+                "0xFFE: FE FF".to_string(),
+            ])
+        );
+        assert_eq!(
+            dump_memory(&memory, 0x2000, 16),
+            Err("Address 0x2000 out of range, memory ends at 0xFFF".to_string())
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use nibble_8_core::Platform;
+use nibble_8_core::disasm::{Syntax, disassemble};
+use std::fs::read;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: nibble-8-disasm <ROM> [options]
+
+Prints a labelled listing of a ROM, following jumps and calls to separate code from data.
+
+Options:
+  --syntax <cowgod|octo>           Mnemonics to use (default: cowgod)
+  --platform <chip8|schip|xochip>  Instruction set to decode (default: xochip)
+  -h, --help                       Print this help";
+
+struct Options {
+    rom_path: String,
+    syntax: Syntax,
+    platform: Platform,
+}
+
+fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom_path = None;
+    let mut syntax = Syntax::default();
+    let mut platform = Platform::XoChip;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--syntax" => {
+                let name = args.next().ok_or("Missing value for --syntax")?;
+                syntax =
+                    Syntax::from_name(&name).ok_or_else(|| format!("Unknown syntax '{}'", name))?;
+            }
+            "--platform" => {
+                let name = args.next().ok_or("Missing value for --platform")?;
+                platform = Platform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown platform '{}'", name))?;
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if rom_path.replace(path.to_string()).is_some() {
+                    return Err("Only one ROM can be given".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        syntax,
+        platform,
+    }))
+}
+
+pub fn main() -> ExitCode {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.rom_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let listing = disassemble(&rom, options.platform);
+    print!("{}", listing.render(options.syntax));
+
+    ExitCode::SUCCESS
+}
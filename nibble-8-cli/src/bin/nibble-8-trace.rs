@@ -0,0 +1,85 @@
+use nibble_8_core::trace::{TraceFilter, parse_pc_range, read_binary_trace};
+use std::fs::read;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: nibble-8-trace <LOG> [options]
+
+Prints a binary trace written by `nibble-8-cli --trace-format binary` as text.
+
+Options:
+  --pc <START-END>                 Only show instructions in this address range
+  --only <NAME,...>                Only show these instructions, e.g. Draw,Call
+  -h, --help                       Print this help";
+
+struct Options {
+    log_path: String,
+    filter: TraceFilter,
+}
+
+fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut log_path = None;
+    let mut filter = TraceFilter::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--pc" => {
+                let range = args.next().ok_or("Missing value for --pc")?;
+                filter.pc_range = Some(parse_pc_range(&range)?);
+            }
+            "--only" => {
+                let names = args.next().ok_or("Missing value for --only")?;
+                filter
+                    .instructions
+                    .extend(names.split(',').map(|name| name.trim().to_string()));
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if log_path.replace(path.to_string()).is_some() {
+                    return Err("Only one log can be given".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(Options {
+        log_path: log_path.ok_or("Missing log path")?,
+        filter,
+    }))
+}
+
+pub fn main() -> ExitCode {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let entries = match read(&options.log_path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| read_binary_trace(&bytes))
+    {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.log_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for entry in entries
+        .iter()
+        .filter(|entry| options.filter.matches(entry.pc, entry.instruction()))
+    {
+        println!("{}", entry.to_text());
+    }
+
+    ExitCode::SUCCESS
+}
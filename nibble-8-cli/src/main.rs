@@ -0,0 +1,297 @@
+mod args;
+mod render;
+
+use args::{Options, OutputFormat, TraceFormat, USAGE};
+use nibble_8_core::audio::{AudioSink, NullSink, WavSink};
+use nibble_8_core::capture::{CaptureFormat, DEFAULT_PALETTE, Frame, FrameExporter, FrameHistory};
+use nibble_8_core::database::RomDatabase;
+use nibble_8_core::memory::KEY_COUNT;
+use nibble_8_core::movie::{Movie, MoviePlayer, MovieRecorder};
+use nibble_8_core::rng::Pcg32Source;
+use nibble_8_core::scheduler::{FakeClock, Scheduler};
+use nibble_8_core::timing::TimingModel;
+use nibble_8_core::trace::{BinaryTraceWriter, TextTraceWriter, TraceSink, Tracer};
+use nibble_8_core::{Bus, Cpu, EmulationError};
+use std::fs::{File, read, read_to_string, write};
+use std::io::BufWriter;
+use std::process::ExitCode;
+
+enum StopReason {
+    FrameLimit,
+    ReachedPc(u16),
+    Halted,
+}
+
+pub fn main() -> ExitCode {
+    let options = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.rom_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut rom_database = RomDatabase::bundled();
+    if let Some(path) = &options.rom_db
+        && let Err(err) = read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                rom_database
+                    .load_overrides(&json)
+                    .map_err(|err| err.to_string())
+            })
+    {
+        eprintln!("error: Failed to read '{}': {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    let profile = rom_database.rom_profile(&rom).with_overrides(
+        options.platform,
+        options.quirks,
+        options.instructions_per_frame,
+    );
+
+    let mut player = None;
+    let (mut cpu, mut bus) = match &options.play_movie {
+        Some(path) => {
+            let movie = match read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| Movie::from_bytes(&bytes).map_err(|err| err.to_string()))
+            {
+                Ok(movie) => movie,
+                Err(err) => {
+                    eprintln!("error: Failed to read '{}': {}", path, err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            match movie.power_on(&rom, Box::new(Pcg32Source::random())) {
+                Ok(machine) => {
+                    player = Some(MoviePlayer::new(movie));
+                    machine
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => {
+            let rng = options
+                .seed
+                .map_or_else(Pcg32Source::random, Pcg32Source::new);
+            let (mut cpu, bus) = match profile.power_on(&rom, Box::new(rng)) {
+                Ok(machine) => machine,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            // A movie brings its own timing model
+            if options.vip_timing {
+                cpu.set_timing_model(TimingModel::CosmacVip);
+            }
+            (cpu, bus)
+        }
+    };
+
+    let mut recorder = options
+        .record_movie
+        .as_ref()
+        .map(|_| MovieRecorder::new(&cpu, &bus, &rom, profile.tickrate));
+
+    if let Some(path) = &options.trace {
+        let file = match File::create(path) {
+            Ok(file) => BufWriter::new(file),
+            Err(err) => {
+                eprintln!("error: Failed to create '{}': {}", path, err);
+                return ExitCode::FAILURE;
+            }
+        };
+        let sink: Box<dyn TraceSink> = match options.trace_format {
+            TraceFormat::Text => Box::new(TextTraceWriter::new(file)),
+            TraceFormat::Binary => Box::new(BinaryTraceWriter::new(file)),
+        };
+        cpu.set_tracer(Some(Tracer::new(sink, options.trace_filter.clone())));
+    }
+
+    let mut wav = options
+        .audio
+        .as_ref()
+        .map(|_| WavSink::new(options.beeper, options.sample_rate));
+    let audio: &mut dyn AudioSink = match &mut wav {
+        Some(wav) => wav,
+        None => &mut NullSink,
+    };
+
+    let mut history = options
+        .capture
+        .as_ref()
+        .map(|_| FrameHistory::new(options.capture_seconds));
+
+    let result = run(
+        &mut cpu,
+        &mut bus,
+        &options,
+        profile.tickrate,
+        player.as_mut(),
+        Outputs {
+            recorder: recorder.as_mut(),
+            audio,
+            history: history.as_mut(),
+        },
+    );
+
+    if let Some(tracer) = cpu.tracer_mut()
+        && let Err(err) = tracer.flush()
+    {
+        eprintln!("error: Failed to write the trace: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    if let (Some(path), Some(recorder)) = (&options.record_movie, recorder)
+        && let Err(err) = write(path, recorder.finish().to_bytes())
+    {
+        eprintln!("error: Failed to write '{}': {}", path, err);
+        return ExitCode::FAILURE;
+    }
+
+    if let (Some(path), Some(wav)) = (&options.audio, wav)
+        && let Err(err) = write(path, wav.to_wav())
+    {
+        eprintln!("error: Failed to write '{}': {}", path, err);
+        return ExitCode::FAILURE;
+    }
+
+    if let (Some((path, format)), Some(history)) = (&options.capture, history) {
+        // The database colors if there are any, in place of the defaults
+        let mut palette = DEFAULT_PALETTE;
+        for (entry, &color) in palette.iter_mut().zip(&profile.colors) {
+            *entry = color;
+        }
+        let exporter = FrameExporter::new(options.capture_scale, palette);
+        // A still shows the display as the run left it, like the framebuffer output
+        let image = match format {
+            CaptureFormat::Png => exporter.png(&Frame::capture(&bus)),
+            _ => exporter.export(*format, &history),
+        };
+        if let Err(err) = image
+            .map_err(|err| err.to_string())
+            .and_then(|image| write(path, image).map_err(|err| err.to_string()))
+        {
+            eprintln!("error: Failed to write '{}': {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let framebuffer = match options.format {
+        OutputFormat::Ascii => render::render_ascii(&bus),
+        OutputFormat::Pbm => render::render_pbm(&bus),
+    };
+    match &options.output {
+        Some(path) => {
+            if let Err(err) = write(path, framebuffer) {
+                eprintln!("error: Failed to write '{}': {}", path, err);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{}", framebuffer),
+    }
+
+    match result {
+        Ok((frames, reason)) => {
+            match reason {
+                StopReason::FrameLimit => eprintln!("Stopped after {} frames", frames),
+                StopReason::ReachedPc(pc) => {
+                    eprintln!("Stopped at PC {:#05X} after {} frames", pc, frames)
+                }
+                StopReason::Halted => eprintln!("ROM exited after {} frames", frames),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// What a run is recorded into, frame by frame.
+struct Outputs<'a> {
+    recorder: Option<&'a mut MovieRecorder>,
+    audio: &'a mut dyn AudioSink,
+    history: Option<&'a mut FrameHistory>,
+}
+
+/// Runs the ROM with the keypad fed from `player` if given, or the `--key` presses otherwise.
+fn run(
+    cpu: &mut Cpu,
+    bus: &mut Bus,
+    options: &Options,
+    instructions_per_frame: u32,
+    mut player: Option<&mut MoviePlayer>,
+    outputs: Outputs,
+) -> Result<(u64, StopReason), EmulationError> {
+    let Outputs {
+        mut recorder,
+        audio,
+        mut history,
+    } = outputs;
+    let (frames, instructions_per_frame) = match &player {
+        Some(player) => (
+            player.movie().frames.len() as u64,
+            player.movie().instructions_per_frame,
+        ),
+        None => (options.frames, instructions_per_frame),
+    };
+
+    // Frames go by the emulated clock, so the scheduler is only asked to run them
+    let mut scheduler = Scheduler::new(Box::new(FakeClock::new()), instructions_per_frame * 60);
+    scheduler.set_cycles_per_tick(cpu.timing_model().cycles_per_tick());
+    scheduler.set_stop_pc(options.until_pc);
+    for frame in 0..frames {
+        match player.as_deref_mut() {
+            Some(player) => {
+                player.feed(bus);
+            }
+            None => {
+                for key in 0..KEY_COUNT as u8 {
+                    let pressed = options
+                        .key_presses
+                        .iter()
+                        .any(|press| press.key == key && press.is_held(frame));
+                    bus.set_key(key, pressed);
+                }
+            }
+        }
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.record_frame(bus);
+        }
+
+        let outcome = scheduler.run_tick(cpu, bus)?;
+        audio.tick(outcome.sound);
+        if let Some(history) = history.as_deref_mut() {
+            history.push(bus);
+        }
+
+        // The frame that stopped the run still counts
+        if cpu.is_halted() {
+            return Ok((frame + 1, StopReason::Halted));
+        }
+        if options.until_pc == Some(cpu.pc()) {
+            return Ok((frame + 1, StopReason::ReachedPc(cpu.pc())));
+        }
+    }
+
+    Ok((frames, StopReason::FrameLimit))
+}
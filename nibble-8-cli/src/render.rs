@@ -0,0 +1,64 @@
+use nibble_8_core::Bus;
+
+/// One character per pixel, `.` is off and `#`, `+`, `@` are planes 1, 2 and both.
+pub fn render_ascii(bus: &Bus) -> String {
+    let mut out = String::with_capacity((bus.display_width() + 1) * bus.display_height());
+
+    for y in 0..bus.display_height() {
+        for x in 0..bus.display_width() {
+            out.push(match bus.get_pixel(x, y) {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Plain PBM (P1) bitmap, a pixel lit in any plane is black.
+pub fn render_pbm(bus: &Bus) -> String {
+    let mut out = format!("P1\n{} {}\n", bus.display_width(), bus.display_height());
+
+    for y in 0..bus.display_height() {
+        let row: Vec<&str> = (0..bus.display_width())
+            .map(|x| if bus.get_pixel(x, y) != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&row.join(" "));
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_ascii() {
+        let mut bus = Bus::new();
+        bus.write_pixel(1, 0, 1);
+
+        let ascii = render_ascii(&bus);
+        let first_line = ascii.lines().next().unwrap();
+        assert_eq!(&first_line[..3], ".#.");
+        assert_eq!(ascii.lines().count(), 32);
+    }
+
+    #[test]
+    fn test_render_pbm() {
+        let mut bus = Bus::new();
+        bus.write_pixel(0, 1, 1);
+
+        let pbm = render_pbm(&bus);
+        let mut lines = pbm.lines();
+        assert_eq!(lines.next(), Some("P1"));
+        assert_eq!(lines.next(), Some("64 32"));
+        assert!(lines.next().unwrap().starts_with("0 0"));
+        assert!(lines.next().unwrap().starts_with("1 0"));
+    }
+}
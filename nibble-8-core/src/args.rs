@@ -0,0 +1,76 @@
+//! Command-line values every frontend takes, parsed the same way everywhere.
+
+use crate::{Platform, Quirks, database::MAX_TICKRATE};
+
+/// Parses decimal or `0x`-prefixed hexadecimal numbers.
+pub fn parse_number<T: TryFrom<u64>>(text: &str) -> Result<T, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    parsed
+        .ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("Invalid number '{}'", text))
+}
+
+/// The value of `--platform`, see `Platform::from_name`.
+pub fn parse_platform(name: &str) -> Result<Platform, String> {
+    Platform::from_name(name).ok_or_else(|| format!("Unknown platform '{}'", name))
+}
+
+/// The value of `--quirks`, see `Quirks::from_preset`.
+pub fn parse_quirks(name: &str) -> Result<Quirks, String> {
+    Quirks::from_preset(name).ok_or_else(|| format!("Unknown quirks preset '{}'", name))
+}
+
+/// The value of `--ipf`, 1 to `MAX_TICKRATE` instructions per frame.
+pub fn parse_tickrate(text: &str) -> Result<u32, String> {
+    let tickrate = parse_number(text)?;
+    if !(1..=MAX_TICKRATE).contains(&tickrate) {
+        return Err(format!(
+            "Invalid instructions per frame '{}', expected 1-{}",
+            text, MAX_TICKRATE
+        ));
+    }
+    Ok(tickrate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number::<u32>("42"), Ok(42));
+        assert_eq!(parse_number::<u16>("0x3DC"), Ok(0x3DC));
+        assert_eq!(
+            parse_number::<u8>("256"),
+            Err("Invalid number '256'".to_string())
+        );
+        assert_eq!(
+            parse_number::<u32>("-1"),
+            Err("Invalid number '-1'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_machine_settings() {
+        assert_eq!(parse_platform("schip"), Ok(Platform::SuperChip));
+        assert_eq!(
+            parse_platform("megachip"),
+            Err("Unknown platform 'megachip'".to_string())
+        );
+        assert_eq!(parse_quirks("vip"), Ok(Quirks::cosmac_vip()));
+        assert_eq!(
+            parse_quirks("fast"),
+            Err("Unknown quirks preset 'fast'".to_string())
+        );
+        assert_eq!(parse_tickrate("0x10"), Ok(16));
+        assert_eq!(
+            parse_tickrate("0"),
+            Err("Invalid instructions per frame '0', expected 1-1000000".to_string())
+        );
+    }
+}
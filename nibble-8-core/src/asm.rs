@@ -0,0 +1,1089 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{instruction::Instruction, memory::ROM_START};
+
+// Nested includes deeper than this are assumed to be a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Same for defines that refer to each other
+const MAX_DEFINE_DEPTH: usize = 64;
+
+/// Where a byte of the output came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Assembly {
+    /// The ROM, ready for `Bus::load_rom`.
+    pub bytes: Vec<u8>,
+    /// Addresses of all labels.
+    pub symbols: BTreeMap<String, u16>,
+    /// Source line of the statement that starts at each address.
+    pub source_map: BTreeMap<u16, SourceLocation>,
+}
+
+/// Assembles source text, `include`s are resolved relative to the working directory.
+///
+/// The syntax is the Cowgod-style one `disasm` prints: one statement per line, `;` comments,
+/// `name:` labels, `define NAME expr`, `db`/`dw` data (`db` also takes strings),
+/// `include "file"`, and C-like integer expressions where `$` is the current address.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.read_source(source, "<source>", Path::new("."), 0)?;
+    assembler.finish()
+}
+
+/// Assembles a file, `include`s are resolved relative to the including file.
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Assembly, AsmError> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: format!("Failed to read file: {}", err),
+    })?;
+
+    let mut assembler = Assembler::default();
+    assembler.read_source(&source, &name, &parent_dir(path), 0)?;
+    assembler.finish()
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Spanned {
+    token: Token,
+    column: usize,
+}
+
+// Errors inside a line carry only a column, the line is added by the caller
+type LineResult<T> = Result<T, (usize, String)>;
+
+const PUNCTUATION: [&str; 18] = [
+    "<<", ">>", ",", ":", "(", ")", "[", "]", "+", "-", "*", "/", "%", "&", "|", "^", "~", "$",
+];
+
+fn tokenize(line: &str) -> LineResult<Vec<Spanned>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let text: String = chars[start..pos].iter().filter(|&&c| c != '_').collect();
+            let lower = text.to_ascii_lowercase();
+            let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = lower.strip_prefix("0b") {
+                i64::from_str_radix(bin, 2)
+            } else {
+                lower.parse()
+            };
+            let number = parsed.map_err(|_| (column, format!("Invalid number '{}'", text)))?;
+            tokens.push(Spanned {
+                token: Token::Number(number),
+                column,
+            });
+        } else if c.is_alphabetic() || c == '_' || c == '.' {
+            let start = pos;
+            while pos < chars.len()
+                && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '.')
+            {
+                pos += 1;
+            }
+            tokens.push(Spanned {
+                token: Token::Ident(chars[start..pos].iter().collect()),
+                column,
+            });
+        } else if c == '"' || c == '\'' {
+            let start = pos;
+            pos += 1;
+            let mut bytes = Vec::new();
+            while pos < chars.len() && chars[pos] != c {
+                let mut ch = chars[pos];
+                if ch == '\\' && pos + 1 < chars.len() {
+                    pos += 1;
+                    ch = match chars[pos] {
+                        'n' => '\n',
+                        't' => '\t',
+                        '0' => '\0',
+                        other => other,
+                    };
+                }
+                if !ch.is_ascii() {
+                    return Err((pos + 1, "Only ASCII characters are supported".to_string()));
+                }
+                bytes.push(ch as u8);
+                pos += 1;
+            }
+            if pos >= chars.len() {
+                return Err((start + 1, "Unterminated literal".to_string()));
+            }
+            pos += 1;
+
+            let token = if c == '"' {
+                Token::Str(bytes)
+            } else if bytes.len() == 1 {
+                Token::Number(bytes[0] as i64)
+            } else {
+                return Err((
+                    column,
+                    "Character literals hold exactly one character".to_string(),
+                ));
+            };
+            tokens.push(Spanned { token, column });
+        } else {
+            let rest: String = chars[pos..].iter().take(2).collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(*punct))
+                .ok_or_else(|| (column, format!("Unexpected character '{}'", c)))?;
+            pos += punct.len();
+            tokens.push(Spanned {
+                token: Token::Punct(punct),
+                column,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(i64),
+    Symbol(String, usize),
+    Here,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, usize, Box<Expr>, Box<Expr>),
+}
+
+// Binary operators from lowest to highest precedence
+const BINARY_OPERATORS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct ExprParser<'a> {
+    tokens: &'a [Spanned],
+    pos: usize,
+    end_column: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(tokens: &'a [Spanned], end_column: usize) -> LineResult<Expr> {
+        let mut parser = Self {
+            tokens,
+            pos: 0,
+            end_column,
+        };
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            Some(extra) => Err((extra.column, "Unexpected token in expression".to_string())),
+            None => Ok(expr),
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end_column, |token| token.column)
+    }
+
+    fn peek_punct(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Spanned {
+                token: Token::Punct(punct),
+                ..
+            }) => Some(punct),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> LineResult<Expr> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self
+            .peek_punct()
+            .filter(|op| BINARY_OPERATORS[level].contains(op))
+        {
+            let column = self.column();
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, column, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> LineResult<Expr> {
+        let column = self.column();
+        let Some(spanned) = self.tokens.get(self.pos) else {
+            return Err((column, "Expected an expression".to_string()));
+        };
+        self.pos += 1;
+
+        match &spanned.token {
+            Token::Number(number) => Ok(Expr::Number(*number)),
+            Token::Ident(name) => Ok(Expr::Symbol(name.clone(), column)),
+            Token::Punct("$") => Ok(Expr::Here),
+            Token::Punct(op @ ("-" | "~" | "+")) => Ok(Expr::Unary(op, Box::new(self.unary()?))),
+            Token::Punct("(") => {
+                let inner = self.binary(0)?;
+                if self.peek_punct() != Some(")") {
+                    return Err((self.column(), "Expected ')'".to_string()));
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            _ => Err((column, "Expected an expression".to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keyword {
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Hf,
+    R,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum OperandKind {
+    Register(u8),
+    Keyword(Keyword),
+    IndirectI,
+    Long(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Operand {
+    kind: OperandKind,
+    column: usize,
+}
+
+fn parse_register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|reg| reg as u8),
+        _ => None,
+    }
+}
+
+fn parse_keyword(name: &str) -> Option<Keyword> {
+    match name.to_ascii_uppercase().as_str() {
+        "I" => Some(Keyword::I),
+        "DT" => Some(Keyword::Dt),
+        "ST" => Some(Keyword::St),
+        "K" => Some(Keyword::K),
+        "F" => Some(Keyword::F),
+        "B" => Some(Keyword::B),
+        "HF" => Some(Keyword::Hf),
+        "R" => Some(Keyword::R),
+        _ => None,
+    }
+}
+
+fn parse_operand(tokens: &[Spanned], end_column: usize) -> LineResult<Operand> {
+    let column = tokens.first().map_or(end_column, |token| token.column);
+    let punct = |index: usize| match tokens.get(index) {
+        Some(Spanned {
+            token: Token::Punct(punct),
+            ..
+        }) => Some(*punct),
+        _ => None,
+    };
+
+    let kind = match tokens {
+        [] => return Err((column, "Expected an operand".to_string())),
+        [
+            Spanned {
+                token: Token::Ident(name),
+                ..
+            },
+        ] if parse_register(name).is_some() => OperandKind::Register(parse_register(name).unwrap()),
+        [
+            Spanned {
+                token: Token::Ident(name),
+                ..
+            },
+        ] if parse_keyword(name).is_some() => OperandKind::Keyword(parse_keyword(name).unwrap()),
+        [
+            _,
+            Spanned {
+                token: Token::Ident(name),
+                ..
+            },
+            _,
+        ] if punct(0) == Some("[") && punct(2) == Some("]") && name.eq_ignore_ascii_case("I") => {
+            OperandKind::IndirectI
+        }
+        [
+            Spanned {
+                token: Token::Ident(name),
+                ..
+            },
+            rest @ ..,
+        ] if name.eq_ignore_ascii_case("LONG") && !rest.is_empty() => {
+            OperandKind::Long(ExprParser::parse(rest, end_column)?)
+        }
+        _ => OperandKind::Expr(ExprParser::parse(tokens, end_column)?),
+    };
+
+    Ok(Operand { kind, column })
+}
+
+// Splits on top-level commas
+fn split_operands(tokens: &[Spanned]) -> Vec<&[Spanned]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, spanned) in tokens.iter().enumerate() {
+        match spanned.token {
+            Token::Punct("(") | Token::Punct("[") => depth += 1,
+            Token::Punct(")") | Token::Punct("]") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                operands.push(&tokens[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(&tokens[start..]);
+
+    operands
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DataItem {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StatementKind {
+    Instruction {
+        mnemonic: String,
+        column: usize,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<(DataItem, usize)>),
+    Words(Vec<(Expr, usize)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Statement {
+    file: usize,
+    line: usize,
+    end_column: usize,
+    addr: u16,
+    kind: StatementKind,
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match &self.kind {
+            StatementKind::Instruction {
+                mnemonic, operands, ..
+            } => {
+                let is_long = mnemonic.eq_ignore_ascii_case("LD")
+                    && operands
+                        .iter()
+                        .any(|operand| matches!(operand.kind, OperandKind::Long(_)));
+                if is_long { 4 } else { 2 }
+            }
+            StatementKind::Bytes(items) => items
+                .iter()
+                .map(|(item, _)| match item {
+                    DataItem::Expr(_) => 1,
+                    DataItem::Str(bytes) => bytes.len(),
+                })
+                .sum(),
+            StatementKind::Words(items) => items.len() * 2,
+        }
+    }
+}
+
+struct Assembler {
+    files: Vec<String>,
+    statements: Vec<Statement>,
+    labels: HashMap<String, u16>,
+    defines: HashMap<String, (Expr, usize, usize)>,
+    addr: usize,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            defines: HashMap::new(),
+            addr: ROM_START as usize,
+        }
+    }
+}
+
+impl Assembler {
+    fn error(&self, file: usize, line: usize, (column, message): (usize, String)) -> AsmError {
+        AsmError {
+            file: self.files[file].clone(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn read_source(
+        &mut self,
+        source: &str,
+        name: &str,
+        base_dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let end_column = text.chars().count() + 1;
+            let tokens = tokenize(text).map_err(|err| self.error(file, line, err))?;
+            self.read_line(&tokens, file, line, end_column, base_dir, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_line(
+        &mut self,
+        mut tokens: &[Spanned],
+        file: usize,
+        line: usize,
+        end_column: usize,
+        base_dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        // Any number of labels can precede the statement
+        while let [
+            Spanned {
+                token: Token::Ident(name),
+                column,
+            },
+            Spanned {
+                token: Token::Punct(":"),
+                ..
+            },
+            rest @ ..,
+        ] = tokens
+        {
+            if self.labels.contains_key(name) || self.defines.contains_key(name) {
+                return Err(self.error(
+                    file,
+                    line,
+                    (*column, format!("'{}' is already defined", name)),
+                ));
+            }
+            if self.addr > u16::MAX as usize {
+                return Err(self.error(file, line, (*column, "Address space exhausted".into())));
+            }
+            self.labels.insert(name.clone(), self.addr as u16);
+            tokens = rest;
+        }
+
+        let [first, rest @ ..] = tokens else {
+            return Ok(());
+        };
+        let Token::Ident(word) = &first.token else {
+            return Err(self.error(
+                file,
+                line,
+                (
+                    first.column,
+                    "Expected a label, directive or instruction".into(),
+                ),
+            ));
+        };
+
+        let kind = match word.to_ascii_lowercase().as_str() {
+            "define" => {
+                let [
+                    Spanned {
+                        token: Token::Ident(name),
+                        column,
+                    },
+                    value @ ..,
+                ] = rest
+                else {
+                    return Err(self.error(
+                        file,
+                        line,
+                        (first.column, "Expected 'define NAME value'".into()),
+                    ));
+                };
+                if self.labels.contains_key(name) || self.defines.contains_key(name) {
+                    return Err(self.error(
+                        file,
+                        line,
+                        (*column, format!("'{}' is already defined", name)),
+                    ));
+                }
+                let expr = ExprParser::parse(value, end_column)
+                    .map_err(|err| self.error(file, line, err))?;
+                self.defines.insert(name.clone(), (expr, file, line));
+                return Ok(());
+            }
+            "include" => {
+                let [
+                    Spanned {
+                        token: Token::Str(path),
+                        column,
+                    },
+                ] = rest
+                else {
+                    return Err(self.error(
+                        file,
+                        line,
+                        (first.column, "Expected 'include \"file\"'".into()),
+                    ));
+                };
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(self.error(
+                        file,
+                        line,
+                        (*column, "Includes are nested too deeply".into()),
+                    ));
+                }
+
+                let path = base_dir.join(String::from_utf8_lossy(path).as_ref());
+                let source = fs::read_to_string(&path).map_err(|err| {
+                    self.error(
+                        file,
+                        line,
+                        (
+                            *column,
+                            format!("Failed to include '{}': {}", path.display(), err),
+                        ),
+                    )
+                })?;
+                return self.read_source(
+                    &source,
+                    &path.display().to_string(),
+                    &parent_dir(&path),
+                    depth + 1,
+                );
+            }
+            "db" => {
+                let mut items = Vec::new();
+                for operand in split_operands(rest) {
+                    let column = operand.first().map_or(end_column, |token| token.column);
+                    let item = match operand {
+                        [
+                            Spanned {
+                                token: Token::Str(bytes),
+                                ..
+                            },
+                        ] => DataItem::Str(bytes.clone()),
+                        _ => DataItem::Expr(
+                            ExprParser::parse(operand, end_column)
+                                .map_err(|err| self.error(file, line, err))?,
+                        ),
+                    };
+                    items.push((item, column));
+                }
+                StatementKind::Bytes(items)
+            }
+            "dw" => {
+                let mut items = Vec::new();
+                for operand in split_operands(rest) {
+                    let column = operand.first().map_or(end_column, |token| token.column);
+                    let expr = ExprParser::parse(operand, end_column)
+                        .map_err(|err| self.error(file, line, err))?;
+                    items.push((expr, column));
+                }
+                StatementKind::Words(items)
+            }
+            _ => {
+                let mut operands = Vec::new();
+                for operand in split_operands(rest) {
+                    operands.push(
+                        parse_operand(operand, end_column)
+                            .map_err(|err| self.error(file, line, err))?,
+                    );
+                }
+                StatementKind::Instruction {
+                    mnemonic: word.to_ascii_uppercase(),
+                    column: first.column,
+                    operands,
+                }
+            }
+        };
+
+        if self.addr > u16::MAX as usize {
+            return Err(self.error(file, line, (first.column, "Address space exhausted".into())));
+        }
+        let statement = Statement {
+            file,
+            line,
+            end_column,
+            addr: self.addr as u16,
+            kind,
+        };
+        self.addr += statement.size();
+        self.statements.push(statement);
+
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, here: u16, depth: usize) -> LineResult<i64> {
+        match expr {
+            Expr::Number(number) => Ok(*number),
+            Expr::Here => Ok(here as i64),
+            Expr::Symbol(name, column) => {
+                if let Some(&addr) = self.labels.get(name) {
+                    return Ok(addr as i64);
+                }
+                let Some((define, _, _)) = self.defines.get(name) else {
+                    return Err((*column, format!("Unknown symbol '{}'", name)));
+                };
+                if depth >= MAX_DEFINE_DEPTH {
+                    return Err((*column, format!("'{}' is defined in terms of itself", name)));
+                }
+                // Errors inside the define are reported where it's used
+                self.eval(define, here, depth + 1)
+                    .map_err(|(_, message)| (*column, message))
+            }
+            Expr::Unary(op, inner) => {
+                let value = self.eval(inner, here, depth)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Binary(op, column, lhs, rhs) => {
+                let lhs = self.eval(lhs, here, depth)?;
+                let rhs = self.eval(rhs, here, depth)?;
+                match *op {
+                    "+" => Ok(lhs.wrapping_add(rhs)),
+                    "-" => Ok(lhs.wrapping_sub(rhs)),
+                    "*" => Ok(lhs.wrapping_mul(rhs)),
+                    "/" | "%" if rhs == 0 => Err((*column, "Division by zero".to_string())),
+                    "/" => Ok(lhs.wrapping_div(rhs)),
+                    "%" => Ok(lhs.wrapping_rem(rhs)),
+                    "&" => Ok(lhs & rhs),
+                    "|" => Ok(lhs | rhs),
+                    "^" => Ok(lhs ^ rhs),
+                    "<<" => Ok(lhs.wrapping_shl(rhs as u32)),
+                    ">>" => Ok(lhs.wrapping_shr(rhs as u32)),
+                    _ => unreachable!("unknown operator {}", op),
+                }
+            }
+        }
+    }
+
+    fn value(&self, expr: &Expr, here: u16, column: usize, min: i64, max: i64) -> LineResult<i64> {
+        let value = self.eval(expr, here, 0)?;
+        if !(min..=max).contains(&value) {
+            return Err((
+                column,
+                format!("Value {} is out of range ({}..={})", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn build(&self, statement: &Statement) -> LineResult<Vec<u8>> {
+        let here = statement.addr;
+        match &statement.kind {
+            StatementKind::Bytes(items) => {
+                let mut bytes = Vec::new();
+                for (item, column) in items {
+                    match item {
+                        DataItem::Str(string) => bytes.extend_from_slice(string),
+                        DataItem::Expr(expr) => {
+                            bytes.push(self.value(expr, here, *column, -128, 0xFF)? as u8)
+                        }
+                    }
+                }
+                Ok(bytes)
+            }
+            StatementKind::Words(items) => {
+                let mut bytes = Vec::new();
+                for (expr, column) in items {
+                    let word = self.value(expr, here, *column, -0x8000, 0xFFFF)? as u16;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            StatementKind::Instruction {
+                mnemonic,
+                column,
+                operands,
+            } => Ok(self
+                .build_instruction(mnemonic, *column, operands, statement)?
+                .encode()),
+        }
+    }
+
+    fn build_instruction(
+        &self,
+        mnemonic: &str,
+        column: usize,
+        operands: &[Operand],
+        statement: &Statement,
+    ) -> LineResult<Instruction> {
+        use Keyword as K;
+        use OperandKind::{Expr as E, IndirectI, Keyword as Kw, Long, Register as Reg};
+
+        let here = statement.addr;
+        let byte =
+            |expr: &Expr, column| self.value(expr, here, column, -128, 0xFF).map(|v| v as u8);
+        let nibble = |expr: &Expr, column| self.value(expr, here, column, 0, 0xF).map(|v| v as u8);
+        let addr = |expr: &Expr, column| self.value(expr, here, column, 0, 0xFFF).map(|v| v as u16);
+
+        let kinds: Vec<&OperandKind> = operands.iter().map(|operand| &operand.kind).collect();
+        let col = |index: usize| {
+            operands
+                .get(index)
+                .map_or(statement.end_column, |operand| operand.column)
+        };
+
+        let instruction = match (mnemonic, kinds.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("JP", [E(nnn)]) => Instruction::Jump(addr(nnn, col(0))?),
+            ("JP", [Reg(0), E(nnn)]) => Instruction::JumpOffset(addr(nnn, col(1))?),
+            ("CALL", [E(nnn)]) => Instruction::Call(addr(nnn, col(0))?),
+            ("SE", [Reg(x), Reg(y)]) => Instruction::SkipRegEq(*x, *y),
+            ("SE", [Reg(x), E(kk)]) => Instruction::SkipEq(*x, byte(kk, col(1))?),
+            ("SNE", [Reg(x), Reg(y)]) => Instruction::SkipRegNotEq(*x, *y),
+            ("SNE", [Reg(x), E(kk)]) => Instruction::SkipNotEq(*x, byte(kk, col(1))?),
+            ("LD", [Reg(x), Reg(y)]) => Instruction::LoadReg(*x, *y),
+            ("LD", [Reg(x), E(kk)]) => Instruction::Load(*x, byte(kk, col(1))?),
+            ("LD", [Kw(K::I), E(nnn)]) => Instruction::LoadI(addr(nnn, col(1))?),
+            ("LD", [Kw(K::I), Long(nnnn)]) => {
+                Instruction::LoadILong(self.value(nnnn, here, col(1), 0, 0xFFFF)? as u16)
+            }
+            ("LD", [Reg(x), Kw(K::Dt)]) => Instruction::LoadRegFromDelay(*x),
+            ("LD", [Reg(x), Kw(K::K)]) => Instruction::WaitForKey(*x),
+            ("LD", [Kw(K::Dt), Reg(x)]) => Instruction::LoadDelayFromReg(*x),
+            ("LD", [Kw(K::St), Reg(x)]) => Instruction::LoadSoundFromReg(*x),
+            ("LD", [Kw(K::F), Reg(x)]) => Instruction::LoadFont(*x),
+            ("LD", [Kw(K::Hf), Reg(x)]) => Instruction::LoadBigFont(*x),
+            ("LD", [Kw(K::B), Reg(x)]) => Instruction::Bcd(*x),
+            ("LD", [IndirectI, Reg(x)]) => Instruction::DumpRegs(*x),
+            ("LD", [Reg(x), IndirectI]) => Instruction::FillRegs(*x),
+            ("LD", [Kw(K::R), Reg(x)]) => Instruction::SaveFlags(*x),
+            ("LD", [Reg(x), Kw(K::R)]) => Instruction::LoadFlags(*x),
+            ("ADD", [Reg(x), Reg(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [Reg(x), E(kk)]) => Instruction::Add(*x, byte(kk, col(1))?),
+            ("ADD", [Kw(K::I), Reg(x)]) => Instruction::AddIndex(*x),
+            ("OR", [Reg(x), Reg(y)]) => Instruction::Or(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => Instruction::And(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Instruction::SubReg(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Instruction::Subn(*x, *y),
+            ("SHR", [Reg(x)]) => Instruction::Shr(*x, *x),
+            ("SHR", [Reg(x), Reg(y)]) => Instruction::Shr(*x, *y),
+            ("SHL", [Reg(x)]) => Instruction::Shl(*x, *x),
+            ("SHL", [Reg(x), Reg(y)]) => Instruction::Shl(*x, *y),
+            ("RND", [Reg(x), E(kk)]) => Instruction::Rand(*x, byte(kk, col(1))?),
+            ("DRW", [Reg(x), Reg(y), E(n)]) => Instruction::Draw(*x, *y, nibble(n, col(2))?),
+            ("SKP", [Reg(x)]) => Instruction::SkipIfPressed(*x),
+            ("SKNP", [Reg(x)]) => Instruction::SkipIfNotPressed(*x),
+            ("SCD", [E(n)]) => Instruction::ScrollDown(nibble(n, col(0))?),
+            ("SCU", [E(n)]) => Instruction::ScrollUp(nibble(n, col(0))?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("SAVE", [Reg(x), Reg(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [Reg(x), Reg(y)]) => Instruction::LoadRange(*x, *y),
+            ("PLANE", [E(n)]) => Instruction::SelectPlanes(nibble(n, col(0))?),
+            ("AUDIO", []) => Instruction::LoadAudioPattern,
+            ("PITCH", [Reg(x)]) => Instruction::SetPitch(*x),
+            _ if is_mnemonic(mnemonic) => {
+                return Err((column, format!("Invalid operands for {}", mnemonic)));
+            }
+            _ => return Err((column, format!("Unknown instruction '{}'", mnemonic))),
+        };
+
+        Ok(instruction)
+    }
+
+    fn finish(self) -> Result<Assembly, AsmError> {
+        let mut assembly = Assembly::default();
+
+        // Defines are checked even when unused, so that typos don't go unnoticed
+        for (expr, file, line) in self.defines.values() {
+            self.eval(expr, ROM_START, 0)
+                .map_err(|err| self.error(*file, *line, err))?;
+        }
+
+        for statement in &self.statements {
+            let bytes = self
+                .build(statement)
+                .map_err(|err| self.error(statement.file, statement.line, err))?;
+            assembly.bytes.extend_from_slice(&bytes);
+            assembly.source_map.insert(
+                statement.addr,
+                SourceLocation {
+                    file: self.files[statement.file].clone(),
+                    line: statement.line,
+                },
+            );
+        }
+
+        assembly.symbols = self
+            .labels
+            .iter()
+            .map(|(name, &addr)| (name.clone(), addr))
+            .collect();
+
+        Ok(assembly)
+    }
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    [
+        "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN",
+        "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW",
+        "HIGH", "SAVE", "LOAD", "PLANE", "AUDIO", "PITCH",
+    ]
+    .contains(&mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decoder::decode,
+        disasm::{Syntax, disassemble},
+        platform::Platform,
+    };
+
+    #[test]
+    fn test_assembles_instructions() {
+        let assembly = assemble(
+            "
+            CLS
+            LD V1, 0x12
+            LD I, 0x345
+            DRW V1, V2, 5
+            LD [I], VF
+            LD I, LONG 0xBEEF
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assembly.bytes,
+            vec![
+                0x00, 0xE0, 0x61, 0x12, 0xA3, 0x45, 0xD1, 0x25, 0xFF, 0x55, 0xF0, 0x00, 0xBE, 0xEF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_forward_references() {
+        let assembly = assemble(
+            "
+            start:  CALL draw      ; forward reference
+                    JP start
+            draw:   RET
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(assembly.bytes, vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+        assert_eq!(assembly.symbols["start"], 0x200);
+        assert_eq!(assembly.symbols["draw"], 0x204);
+    }
+
+    #[test]
+    fn test_defines_and_expressions() {
+        let assembly = assemble(
+            "
+            define SPEED 3
+            define OFFSET (SPEED + 1) * 2 << 1
+            LD V0, OFFSET | 0x01
+            ADD V1, -SPEED
+            JP $ + 2
+            LD V2, 'A'
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assembly.bytes,
+            vec![0x60, 0x11, 0x71, 0xFD, 0x12, 0x06, 0x62, 0x41]
+        );
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let assembly = assemble(
+            "
+            LD I, sprite
+            sprite:
+            db 0xF0, 0b10010000, \"hi\"
+            dw 0x1234, sprite
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assembly.bytes,
+            vec![0xA2, 0x02, 0xF0, 0x90, b'h', b'i', 0x12, 0x34, 0x02, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let assembly = assemble("CLS\n\n  db 1, 2\nRET\n").unwrap();
+
+        let lines: Vec<(u16, usize)> = assembly
+            .source_map
+            .iter()
+            .map(|(&addr, location)| (addr, location.line))
+            .collect();
+        assert_eq!(lines, vec![(0x200, 1), (0x202, 3), (0x204, 4)]);
+    }
+
+    #[test]
+    fn test_errors_have_line_and_column() {
+        let error = assemble("CLS\n  LD V1, missing\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 10));
+        assert_eq!(error.message, "Unknown symbol 'missing'");
+
+        let error = assemble("  FOO V1").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 3));
+        assert_eq!(error.message, "Unknown instruction 'FOO'");
+
+        let error = assemble("LD V1, 0x100").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 8));
+
+        let error = assemble("DRW V1, V2").unwrap_err();
+        assert_eq!(error.message, "Invalid operands for DRW");
+
+        let error = assemble("a:\na:").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn test_recursive_defines_are_rejected() {
+        let error = assemble("define A B\ndefine B A\n").unwrap_err();
+        assert!(error.message.contains("in terms of itself"));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("nibble-8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sprites.asm"), "digit: db 0xF0\n").unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            "LD I, digit\ninclude \"sprites.asm\"\n",
+        )
+        .unwrap();
+
+        let assembly = assemble_file(dir.join("main.asm")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(assembly.bytes, vec![0xA2, 0x02, 0xF0]);
+        assert!(assembly.source_map[&0x202].file.ends_with("sprites.asm"));
+    }
+
+    #[test]
+    fn test_round_trips_disassembly() {
+        let rom = [
+            0x60, 0x05, 0xA2, 0x0C, 0xD0, 0x15, 0x30, 0x05, 0x22, 0x0E, 0x12, 0x00, 0xF0, 0x00,
+            0x00, 0xEE,
+        ];
+
+        let source = disassemble(&rom, Platform::Chip8).render(Syntax::Cowgod);
+        assert_eq!(assemble(&source).unwrap().bytes, rom);
+    }
+
+    #[test]
+    fn test_every_instruction_encodes_back() {
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = decode(opcode) {
+                let source = instruction.to_string();
+                let assembly = assemble(&source).unwrap();
+                assert_eq!(
+                    decode(u16::from_be_bytes([assembly.bytes[0], assembly.bytes[1]])),
+                    Some(instruction),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+}
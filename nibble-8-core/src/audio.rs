@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+
+use crate::memory::AUDIO_PATTERN_SIZE;
+use crate::{Bus, Cpu};
+
+/// Ticks the emulator may run ahead of the audio output before the oldest ones are dropped.
+const MAX_QUEUED_TICKS: usize = 4;
+
+/// The timers count down at 60 Hz.
+pub const TICK_RATE: u32 = 60;
+/// Bits in the XO-CHIP audio pattern.
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_SIZE * 8) as f64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Self::Square),
+            "sine" => Some(Self::Sine),
+            "saw" | "sawtooth" => Some(Self::Sawtooth),
+            _ => None,
+        }
+    }
+
+    /// The value at `phase` in `0.0..1.0`, between -1 and 1.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// The XO-CHIP audio pattern, played as a loop of 1-bit samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pattern {
+    pub bits: [u8; AUDIO_PATTERN_SIZE],
+    /// Bits per second, see `Bus::playback_rate`.
+    pub rate: f64,
+}
+
+impl Pattern {
+    fn bit(&self, position: f64) -> bool {
+        let bit = position as usize;
+        self.bits[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+}
+
+/// The sound state of one timer tick.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SoundTick {
+    /// The sound timer is running.
+    pub active: bool,
+    /// Played instead of the tone of the `BeeperConfig`.
+    pub pattern: Option<Pattern>,
+}
+
+impl SoundTick {
+    /// The tone of the `BeeperConfig`, or silence.
+    pub fn tone(active: bool) -> Self {
+        Self {
+            active,
+            pattern: None,
+        }
+    }
+
+    /// The state of the machine, read at the end of a tick. XO-CHIP machines play their audio
+    /// pattern, unless it is still all zeros because the ROM never loaded one.
+    pub fn of(cpu: &Cpu, bus: &Bus) -> Self {
+        let bits = *bus.audio_pattern();
+        let has_pattern = bus.platform().has_xochip_opcodes() && bits.iter().any(|&b| b != 0);
+        Self {
+            active: cpu.is_sound_active(),
+            pattern: has_pattern.then(|| Pattern {
+                bits,
+                rate: bus.playback_rate(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeeperConfig {
+    pub waveform: Waveform,
+    /// Tone frequency in Hz.
+    pub frequency: f32,
+    /// Output amplitude between 0 and 1.
+    pub volume: f32,
+    /// Seconds the tone takes to fade in and out, avoids clicks at the edges.
+    pub envelope: f32,
+}
+
+impl Default for BeeperConfig {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+            envelope: 0.005,
+        }
+    }
+}
+
+/// Synthesizes the sound timer output: the tone of the config, or the XO-CHIP audio pattern.
+///
+/// The emulator queues the sound state once per 60 Hz timer tick with `push_tick` and the audio
+/// output pulls samples with `fill`, which plays every queued tick for exactly 1/60 s. The tone
+/// therefore follows the emulated timer, however unevenly the frames are rendered. Running out of
+/// ticks plays silence.
+#[derive(Debug, Clone)]
+pub struct Beeper {
+    config: BeeperConfig,
+    sample_rate: u32,
+    ticks: VecDeque<SoundTick>,
+    current: SoundTick,
+    /// Samples left until the next tick starts.
+    tick_remaining: f64,
+    phase: f32,
+    /// Position in the audio pattern, in bits.
+    pattern_position: f64,
+    /// Envelope level between 0 and 1.
+    gain: f32,
+}
+
+impl Beeper {
+    pub fn new(config: BeeperConfig, sample_rate: u32) -> Self {
+        Self {
+            config,
+            sample_rate,
+            ticks: VecDeque::new(),
+            current: SoundTick::default(),
+            tick_remaining: 0.0,
+            phase: 0.0,
+            pattern_position: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn config(&self) -> BeeperConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: BeeperConfig) {
+        self.config = config;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queues the sound state of the next timer tick.
+    pub fn push_tick(&mut self, sound: SoundTick) {
+        if self.ticks.len() == MAX_QUEUED_TICKS {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(sound);
+    }
+
+    /// Renders mono samples between -1 and 1.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let samples_per_tick = self.sample_rate as f64 / TICK_RATE as f64;
+        let envelope_step = match self.config.envelope * self.sample_rate as f32 {
+            samples if samples >= 1.0 => 1.0 / samples,
+            _ => 1.0,
+        };
+
+        for sample in out {
+            if self.tick_remaining <= 0.0 {
+                self.current = self.ticks.pop_front().unwrap_or_default();
+                self.tick_remaining += samples_per_tick;
+            }
+            self.tick_remaining -= 1.0;
+
+            let target = if self.current.active { 1.0 } else { 0.0 };
+            self.gain = if self.gain < target {
+                (self.gain + envelope_step).min(target)
+            } else {
+                (self.gain - envelope_step).max(target)
+            };
+
+            let wave = match &self.current.pattern {
+                Some(pattern) => {
+                    let level = if pattern.bit(self.pattern_position) {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+                    self.pattern_position = (self.pattern_position
+                        + pattern.rate / self.sample_rate as f64)
+                        % PATTERN_BITS;
+                    level
+                }
+                None => {
+                    let level = self.config.waveform.sample(self.phase);
+                    self.phase =
+                        (self.phase + self.config.frequency / self.sample_rate as f32).fract();
+                    level
+                }
+            };
+            *sample = wave * self.gain * self.config.volume;
+        }
+    }
+}
+
+/// Receives the sound state once per 60 Hz timer tick.
+pub trait AudioSink {
+    fn tick(&mut self, sound: SoundTick);
+}
+
+impl AudioSink for Beeper {
+    fn tick(&mut self, sound: SoundTick) {
+        self.push_tick(sound);
+    }
+}
+
+/// Discards everything, for running without audio.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn tick(&mut self, _sound: SoundTick) {}
+}
+
+/// Records the beeper output, see `to_wav`.
+pub struct WavSink {
+    beeper: Beeper,
+    ticks: u64,
+    samples: Vec<f32>,
+}
+
+impl WavSink {
+    pub fn new(config: BeeperConfig, sample_rate: u32) -> Self {
+        Self {
+            beeper: Beeper::new(config, sample_rate),
+            ticks: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Encodes the recording as a 16-bit mono PCM WAV file.
+    pub fn to_wav(&self) -> Vec<u8> {
+        encode_wav(&self.samples, self.beeper.sample_rate())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn tick(&mut self, sound: SoundTick) {
+        let rate = self.beeper.sample_rate() as u64;
+        let tick_samples =
+            (self.ticks + 1) * rate / TICK_RATE as u64 - self.ticks * rate / TICK_RATE as u64;
+        self.ticks += 1;
+
+        self.beeper.push_tick(sound);
+        let start = self.samples.len();
+        self.samples.resize(start + tick_samples as usize, 0.0);
+        self.beeper.fill(&mut self.samples[start..]);
+    }
+}
+
+/// Encodes samples between -1 and 1 as a 16-bit mono PCM WAV file.
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    // Block align and bits per sample
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks, cpu::ThreadRngSource};
+
+    fn config(waveform: Waveform) -> BeeperConfig {
+        BeeperConfig {
+            waveform,
+            frequency: 1000.0,
+            volume: 0.5,
+            envelope: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_waveforms() {
+        let mut beeper = Beeper::new(config(Waveform::Square), 8000);
+        beeper.push_tick(SoundTick::tone(true));
+        let mut samples = [0.0; 8];
+        beeper.fill(&mut samples);
+        assert_eq!(samples, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+
+        let mut beeper = Beeper::new(config(Waveform::Sawtooth), 8000);
+        beeper.push_tick(SoundTick::tone(true));
+        beeper.fill(&mut samples);
+        assert_eq!(samples[..3], [-0.5, -0.375, -0.25]);
+
+        let mut beeper = Beeper::new(config(Waveform::Sine), 8000);
+        beeper.push_tick(SoundTick::tone(true));
+        beeper.fill(&mut samples);
+        assert!((samples[2] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ticks_last_a_sixtieth_of_a_second() {
+        let mut beeper = Beeper::new(config(Waveform::Square), 6000);
+        beeper.push_tick(SoundTick::tone(true));
+        beeper.push_tick(SoundTick::tone(false));
+        beeper.push_tick(SoundTick::tone(true));
+        let mut samples = [0.0; 400];
+        beeper.fill(&mut samples);
+
+        assert!(samples[..100].iter().all(|&sample| sample != 0.0));
+        assert!(samples[100..200].iter().all(|&sample| sample == 0.0));
+        assert!(samples[200..300].iter().all(|&sample| sample != 0.0));
+        // Ran out of ticks
+        assert!(samples[300..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_envelope_ramps_instead_of_clicking() {
+        let mut beeper = Beeper::new(
+            BeeperConfig {
+                envelope: 0.01,
+                ..config(Waveform::Sawtooth)
+            },
+            6000,
+        );
+        beeper.push_tick(SoundTick::tone(true));
+        let mut samples = [0.0; 100];
+        beeper.fill(&mut samples);
+
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(peak(&samples[..10]) < 0.1);
+        assert!(peak(&samples[80..]) > 0.4);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut beeper = Beeper::new(config(Waveform::Sine), 8000);
+        let mut bits = [0; AUDIO_PATTERN_SIZE];
+        bits[0] = 0xF0;
+        beeper.push_tick(SoundTick {
+            active: true,
+            pattern: Some(Pattern { bits, rate: 4000.0 }),
+        });
+        let mut samples = [0.0; 16];
+        beeper.fill(&mut samples);
+
+        // Two samples per bit
+        assert!(samples[..8].iter().all(|&sample| sample == 0.5));
+        assert!(samples[8..].iter().all(|&sample| sample == -0.5));
+    }
+
+    #[test]
+    fn test_sound_tick_of_machine() {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::xochip());
+        assert_eq!(SoundTick::of(&cpu, &bus), SoundTick::tone(false));
+
+        cpu.execute(0x6005, &mut bus).unwrap();
+        cpu.execute(0xF018, &mut bus).unwrap();
+        bus.set_audio_pattern(&[0xAA; AUDIO_PATTERN_SIZE]);
+        bus.set_pitch(112);
+        let sound = SoundTick::of(&cpu, &bus);
+        assert!(sound.active);
+        assert_eq!(
+            sound.pattern,
+            Some(Pattern {
+                bits: [0xAA; AUDIO_PATTERN_SIZE],
+                rate: 8000.0
+            })
+        );
+
+        // Only XO-CHIP has patterns
+        let mut bus = Bus::with_platform(Platform::SuperChip);
+        bus.set_audio_pattern(&[0xAA; AUDIO_PATTERN_SIZE]);
+        assert_eq!(SoundTick::of(&cpu, &bus).pattern, None);
+    }
+
+    #[test]
+    fn test_wav_sink() {
+        let mut sink = WavSink::new(config(Waveform::Square), 44100);
+        sink.tick(SoundTick::tone(true));
+        sink.tick(SoundTick::tone(false));
+        assert_eq!(sink.samples().len(), 1470);
+
+        let wav = sink.to_wav();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav.len(), 44 + 1470 * 2);
+        assert_eq!(i16::from_le_bytes([wav[44], wav[45]]), i16::MAX / 2);
+    }
+}
@@ -0,0 +1,426 @@
+//! Screenshots and animations of the display as PNG, GIF and APNG.
+//!
+//! Frames are captured as the display buffer, one per 60 Hz timer tick, and only turned into
+//! images when exported. Runs of identical frames are stored and encoded once, with a longer
+//! delay.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+
+use crate::Bus;
+
+/// Frames per second of captured animations, the rate of the timers.
+pub const FRAME_RATE: u32 = 60;
+/// Black background, white plane 1, and grays for plane 2 and both planes.
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] =
+    [[0, 0, 0], [255, 255, 255], [85, 85, 85], [170, 170, 170]];
+/// GIF delays are in 1/100 s and viewers slow down anything shorter than this.
+const MIN_GIF_DELAY: u64 = 2;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// There are no frames to export.
+    Empty,
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Empty => write!(f, "No frames have been captured"),
+            CaptureError::Png(err) => write!(f, "PNG encoding failed: {}", err),
+            CaptureError::Gif(err) => write!(f, "GIF encoding failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(err: png::EncodingError) -> Self {
+        CaptureError::Png(err)
+    }
+}
+
+impl From<gif::EncodingError> for CaptureError {
+    fn from(err: gif::EncodingError) -> Self {
+        CaptureError::Gif(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// A still of the latest frame.
+    Png,
+    Gif,
+    Apng,
+}
+
+impl CaptureFormat {
+    /// By file extension, `.apng` for animated PNGs.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(CaptureFormat::Png),
+            "gif" => Some(CaptureFormat::Gif),
+            "apng" => Some(CaptureFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// The display buffer at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Bitmasks of the lit planes, row by row.
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn capture(bus: &Bus) -> Self {
+        Self {
+            width: bus.display_width(),
+            height: bus.display_height(),
+            pixels: bus.display_buffer().to_vec(),
+        }
+    }
+
+    fn shows(&self, bus: &Bus) -> bool {
+        self.width == bus.display_width() && self.pixels == bus.display_buffer()
+    }
+}
+
+/// A frame and how many ticks it stayed on screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedFrame {
+    pub frame: Frame,
+    pub ticks: u32,
+}
+
+/// The frames of the last few seconds, fed once per tick.
+pub struct FrameHistory {
+    frames: VecDeque<TimedFrame>,
+    ticks: u64,
+    capacity: u64,
+}
+
+impl FrameHistory {
+    pub fn new(seconds: u32) -> Self {
+        Self {
+            frames: VecDeque::new(),
+            ticks: 0,
+            capacity: seconds as u64 * FRAME_RATE as u64,
+        }
+    }
+
+    /// Records the display at the end of a tick, dropping the oldest tick once full.
+    pub fn push(&mut self, bus: &Bus) {
+        match self.frames.back_mut() {
+            Some(last) if last.frame.shows(bus) => last.ticks += 1,
+            _ => self.frames.push_back(TimedFrame {
+                frame: Frame::capture(bus),
+                ticks: 1,
+            }),
+        }
+        self.ticks += 1;
+
+        while self.ticks > self.capacity {
+            let Some(first) = self.frames.front_mut() else {
+                break;
+            };
+            first.ticks -= 1;
+            self.ticks -= 1;
+            if first.ticks == 0 {
+                self.frames.pop_front();
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.ticks = 0;
+    }
+
+    /// Distinct frames, a run of identical ones counts once.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Ticks covered by the history.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TimedFrame> {
+        self.frames.iter()
+    }
+
+    pub fn last(&self) -> Option<&Frame> {
+        self.frames.back().map(|timed| &timed.frame)
+    }
+
+    /// The largest resolution in the history, which animations are scaled to.
+    fn size(&self) -> Option<(usize, usize)> {
+        let width = self.iter().map(|timed| timed.frame.width).max()?;
+        let height = self.iter().map(|timed| timed.frame.height).max()?;
+        Some((width, height))
+    }
+}
+
+/// Renders frames into image files with every display pixel drawn as a `scale` sized square.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameExporter {
+    pub scale: u32,
+    /// Colors by the bitmask of lit planes, see `DEFAULT_PALETTE`.
+    pub palette: [[u8; 3]; 4],
+}
+
+impl FrameExporter {
+    pub fn new(scale: u32, palette: [[u8; 3]; 4]) -> Self {
+        Self { scale, palette }
+    }
+
+    /// Exports `history` as `format`, which for PNG is its latest frame.
+    pub fn export(
+        &self,
+        format: CaptureFormat,
+        history: &FrameHistory,
+    ) -> Result<Vec<u8>, CaptureError> {
+        match format {
+            CaptureFormat::Png => self.png(history.last().ok_or(CaptureError::Empty)?),
+            CaptureFormat::Gif => self.gif(history),
+            CaptureFormat::Apng => self.apng(history),
+        }
+    }
+
+    pub fn png(&self, frame: &Frame) -> Result<Vec<u8>, CaptureError> {
+        let (width, height) = self.image_size(frame.width, frame.height);
+        let mut bytes = Vec::new();
+        let mut writer = self.png_encoder(&mut bytes, width, height).write_header()?;
+        writer.write_image_data(&self.indexed(frame, width, height))?;
+        writer.finish()?;
+        Ok(bytes)
+    }
+
+    /// A looping GIF. Its delays are rounded from the 60 Hz ticks so that the total time stays
+    /// right, and a frame that would get less than `MIN_GIF_DELAY` is left out for the next one.
+    pub fn gif(&self, history: &FrameHistory) -> Result<Vec<u8>, CaptureError> {
+        let (width, height) = history.size().ok_or(CaptureError::Empty)?;
+        let (width, height) = self.image_size(width, height);
+        let mut encoder = gif::Encoder::new(
+            Vec::new(),
+            width as u16,
+            height as u16,
+            &self.palette.concat(),
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        let mut ticks = 0;
+        // In 1/100 s
+        let mut shown_until = 0;
+        for (index, timed) in history.iter().enumerate() {
+            ticks += timed.ticks as u64;
+            let end = (ticks * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+            let is_last = index + 1 == history.len();
+            if end - shown_until < MIN_GIF_DELAY && !is_last {
+                continue;
+            }
+
+            let pixels = self.indexed(&timed.frame, width, height);
+            let mut frame =
+                gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+            frame.delay = (end - shown_until).clamp(MIN_GIF_DELAY, u16::MAX as u64) as u16;
+            encoder.write_frame(&frame)?;
+            shown_until = end;
+        }
+        Ok(encoder.into_inner()?)
+    }
+
+    /// A looping animated PNG, its delays are exact.
+    pub fn apng(&self, history: &FrameHistory) -> Result<Vec<u8>, CaptureError> {
+        let (width, height) = history.size().ok_or(CaptureError::Empty)?;
+        let (width, height) = self.image_size(width, height);
+        let mut bytes = Vec::new();
+        let mut encoder = self.png_encoder(&mut bytes, width, height);
+        encoder.set_animated(history.len() as u32, 0)?;
+
+        let mut writer = encoder.write_header()?;
+        for timed in history.iter() {
+            let ticks = timed.ticks.min(u16::MAX as u32) as u16;
+            writer.set_frame_delay(ticks, FRAME_RATE as u16)?;
+            writer.write_image_data(&self.indexed(&timed.frame, width, height))?;
+        }
+        writer.finish()?;
+        Ok(bytes)
+    }
+
+    fn image_size(&self, width: usize, height: usize) -> (usize, usize) {
+        (width * self.scale as usize, height * self.scale as usize)
+    }
+
+    fn png_encoder<W: Write>(
+        &self,
+        writer: W,
+        width: usize,
+        height: usize,
+    ) -> png::Encoder<'static, W> {
+        let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(self.palette.concat());
+        encoder
+    }
+
+    /// Palette indices of `frame` stretched to `width` x `height`, which also scales lo-res
+    /// frames up to the hi-res ones of an animation.
+    fn indexed(&self, frame: &Frame, width: usize, height: usize) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = y * frame.height / height * frame.width;
+            for x in 0..width {
+                pixels.push(frame.pixels[row + x * frame.width / width] & 0b11);
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Platform;
+
+    fn history(seconds: u32, frames: &[u32]) -> (FrameHistory, Bus) {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        let mut history = FrameHistory::new(seconds);
+        for (index, &ticks) in frames.iter().enumerate() {
+            bus.write_pixel(index, 0, 0b01);
+            for _ in 0..ticks {
+                history.push(&bus);
+            }
+        }
+        (history, bus)
+    }
+
+    #[test]
+    fn test_history() {
+        let (mut history, mut bus) = history(1, &[10, 20, 25]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.ticks(), 55);
+
+        bus.write_pixel(0, 1, 0b10);
+        for _ in 0..10 {
+            history.push(&bus);
+        }
+        // The oldest frame is trimmed...
+        let ticks: Vec<u32> = history.iter().map(|timed| timed.ticks).collect();
+        assert_eq!(ticks, [5, 20, 25, 10]);
+        // ...and then dropped
+        for _ in 0..5 {
+            history.push(&bus);
+        }
+        let ticks: Vec<u32> = history.iter().map(|timed| timed.ticks).collect();
+        assert_eq!(ticks, [20, 25, 15]);
+        assert_eq!(history.last(), Some(&Frame::capture(&bus)));
+    }
+
+    #[test]
+    fn test_png() {
+        let (history, _) = history(1, &[1, 1]);
+        let exporter = FrameExporter::new(3, DEFAULT_PALETTE);
+        let bytes = exporter.export(CaptureFormat::Png, &history).unwrap();
+
+        let mut reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        assert_eq!(reader.info().size(), (64 * 3, 32 * 3));
+        assert_eq!(
+            reader.info().palette.as_deref(),
+            Some(&DEFAULT_PALETTE.concat()[..])
+        );
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut pixels).unwrap();
+        // Pixels (0, 0) and (1, 0) are lit, three image pixels each
+        assert_eq!(pixels[..8], [1, 1, 1, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_apng_delays() {
+        let (history, _) = history(10, &[1, 3, 60]);
+        let exporter = FrameExporter::new(1, DEFAULT_PALETTE);
+        let bytes = exporter.apng(&history).unwrap();
+
+        let mut reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, 3);
+        assert_eq!(animation.num_plays, 0);
+
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            reader.next_frame(&mut pixels).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            delays.push((control.delay_num, control.delay_den));
+        }
+        assert_eq!(delays, [(1, 60), (3, 60), (60, 60)]);
+    }
+
+    #[test]
+    fn test_gif_delays() {
+        let (history, _) = history(10, &[1, 1, 1, 3, 60]);
+        let exporter = FrameExporter::new(2, DEFAULT_PALETTE);
+        let bytes = exporter.gif(&history).unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(Cursor::new(bytes))
+            .unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        // After each frame 2, 3, 5, 10 and 110 hundredths have passed. The second frame would
+        // only get 1/100 s and is left out, the total stays the same.
+        assert_eq!(delays, [2, 3, 5, 100]);
+        assert_eq!(delays.iter().sum::<u16>(), 110);
+    }
+
+    #[test]
+    fn test_animation_scales_lo_res_frames() {
+        let mut bus = Bus::with_platform(Platform::SuperChip);
+        let mut history = FrameHistory::new(1);
+        bus.write_pixel(1, 0, 0b01);
+        history.push(&bus);
+        bus.set_hires(true);
+        history.push(&bus);
+
+        let exporter = FrameExporter::new(1, DEFAULT_PALETTE);
+        let frame = &history.iter().next().unwrap().frame;
+        let pixels = exporter.indexed(frame, 128, 64);
+        assert_eq!(pixels[..4], [0, 0, 1, 1]);
+        assert_eq!(pixels[128..132], [0, 0, 1, 1]);
+        assert_eq!(pixels[256..260], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let exporter = FrameExporter::new(1, DEFAULT_PALETTE);
+        assert!(matches!(
+            exporter.export(CaptureFormat::Gif, &FrameHistory::new(1)),
+            Err(CaptureError::Empty)
+        ));
+        assert_eq!(
+            CaptureFormat::from_path("clip.GIF"),
+            Some(CaptureFormat::Gif)
+        );
+        assert_eq!(CaptureFormat::from_path("clip"), None);
+    }
+}
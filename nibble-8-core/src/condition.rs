@@ -0,0 +1,342 @@
+use std::fmt;
+
+use crate::{Bus, Cpu};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Hits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Variable(Variable),
+    /// The byte at an address, e.g. `[I + 1]`.
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// A boolean expression over machine state for conditional breakpoints, like
+/// `V3 == 0x10 && I > 0x300` or `hits >= 5`.
+///
+/// Operands are numbers, `V0`-`VF`, `I`, `PC`, `SP`, `DT`, `ST`, `hits` (how often the breakpoint
+/// was reached, including this time) and `[expr]` for a byte of memory. Operators from loosest to
+/// tightest are `||`, `&&`, comparisons, `+ - &` and the unary `! -`. Non-zero values are true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+const OPERATORS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "!", "(", ")", "[", "]",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Operator(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphanumeric() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let token = if c.is_ascii_digit() {
+                let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                Token::Number(parsed.map_err(|_| format!("Invalid number '{}'", word))?)
+            } else {
+                Token::Ident(word.to_ascii_uppercase())
+            };
+            tokens.push(token);
+            rest = &rest[len..];
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+                .ok_or_else(|| format!("Unexpected character '{}'", c))?;
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// Binary operators from loosest to tightest
+const LEVELS: [&[&str]; 4] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<=", ">=", "<", ">"],
+    &["+", "-", "&"],
+];
+
+// Deeper expressions are refused rather than risking the stack when parsing and evaluating
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How deep the expression being parsed is nested.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        if self.peek_operator() != Some(operator) {
+            return Err(format!("Expected '{}'", operator));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Goes one level deeper into the expression.
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("Condition is nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        // Every operator in a chain nests the left side one level deeper
+        let depth = self.depth;
+        while let Some(operator) = self
+            .peek_operator()
+            .filter(|operator| LEVELS[level].contains(operator))
+        {
+            self.pos += 1;
+            self.enter()?;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(operator, Box::new(lhs), Box::new(rhs));
+        }
+        self.depth = depth;
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("Unexpected end of condition")?;
+        self.pos += 1;
+
+        let nests = matches!(token, Token::Operator("!" | "-" | "(" | "["));
+        if nests {
+            self.enter()?;
+        }
+        let expr = match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Ident(name) => parse_variable(&name)
+                .map(Expr::Variable)
+                .ok_or_else(|| format!("Unknown variable '{}'", name)),
+            Token::Operator("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Operator("-") => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Token::Operator("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Operator("[") => {
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Token::Operator(operator) => Err(format!("Unexpected '{}'", operator)),
+        };
+        if nests {
+            self.depth -= 1;
+        }
+        expr
+    }
+}
+
+fn parse_variable(name: &str) -> Option<Variable> {
+    match name {
+        "I" => Some(Variable::I),
+        "PC" => Some(Variable::Pc),
+        "SP" => Some(Variable::Sp),
+        "DT" => Some(Variable::Dt),
+        "ST" => Some(Variable::St),
+        "HITS" => Some(Variable::Hits),
+        _ => {
+            let digit = name.strip_prefix('V')?;
+            if digit.len() != 1 {
+                return None;
+            }
+            u8::from_str_radix(digit, 16).ok().map(Variable::V)
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.binary(0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err("Unexpected input after the condition".to_string());
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluates the condition, `hits` is the value of the `hits` variable.
+    pub fn evaluate(&self, cpu: &Cpu, bus: &Bus, hits: u64) -> bool {
+        evaluate(&self.expr, cpu, bus, hits) != 0
+    }
+}
+
+fn evaluate(expr: &Expr, cpu: &Cpu, bus: &Bus, hits: u64) -> i64 {
+    let eval = |expr: &Expr| evaluate(expr, cpu, bus, hits);
+
+    match expr {
+        Expr::Number(number) => *number,
+        Expr::Variable(variable) => match *variable {
+            Variable::V(x) => cpu.v_registers()[x as usize] as i64,
+            Variable::I => cpu.i() as i64,
+            Variable::Pc => cpu.pc() as i64,
+            Variable::Sp => cpu.sp() as i64,
+            Variable::Dt => cpu.delay_timer() as i64,
+            Variable::St => cpu.sound_timer() as i64,
+            Variable::Hits => hits as i64,
+        },
+        // Addresses outside of RAM read as 0
+        Expr::Memory(addr) => usize::try_from(eval(addr))
+            .ok()
+            .and_then(|addr| bus.memory().get(addr).copied())
+            .unwrap_or(0) as i64,
+        Expr::Not(inner) => (eval(inner) == 0) as i64,
+        Expr::Negate(inner) => eval(inner).wrapping_neg(),
+        Expr::Binary(operator, lhs, rhs) => {
+            let lhs = eval(lhs);
+            // `||` and `&&` short-circuit
+            match *operator {
+                "||" => return (lhs != 0 || eval(rhs) != 0) as i64,
+                "&&" => return (lhs != 0 && eval(rhs) != 0) as i64,
+                _ => {}
+            }
+            let rhs = eval(rhs);
+            match *operator {
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "&" => lhs & rhs,
+                _ => unreachable!("unknown operator {}", operator),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, asm::assemble, cpu::ThreadRngSource};
+
+    fn setup() -> (Cpu, Bus) {
+        let program = assemble("LD V3, 0x10\nLD I, 0x310\nLD [I], V3").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        for _ in 0..3 {
+            cpu.step(&mut bus).unwrap();
+        }
+        (cpu, bus)
+    }
+
+    fn check(source: &str, hits: u64) -> bool {
+        let (cpu, bus) = setup();
+        Condition::parse(source).unwrap().evaluate(&cpu, &bus, hits)
+    }
+
+    #[test]
+    fn test_evaluates_machine_state() {
+        assert!(check("V3 == 0x10 && I > 0x300", 1));
+        assert!(!check("V3 == 0x10 && I > 0x400", 1));
+        assert!(check("v3 != 0x10 || pc == 0x206", 1));
+        assert!(check("[0x313] == 16 && [0x30F + 1] == 0", 1));
+        assert!(check("!(V0) && V3 & 0x10", 1));
+        assert!(check("V3 - 0x11 == -1", 1));
+    }
+
+    #[test]
+    fn test_hit_counts() {
+        assert!(!check("hits >= 3", 2));
+        assert!(check("hits >= 3", 3));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Condition::parse("V3 ==").is_err());
+        assert!(Condition::parse("VG == 1").is_err());
+        assert!(Condition::parse("(V3 == 1").is_err());
+        assert!(Condition::parse("V3 == 1 V4").is_err());
+        assert!(Condition::parse("V3 = 1").is_err());
+
+        let deep = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(
+            Condition::parse(&deep),
+            Err("Condition is nested too deeply".to_string())
+        );
+        assert!(Condition::parse(&"!".repeat(10_000)).is_err());
+        assert!(Condition::parse(&vec!["1"; 10_000].join(" + ")).is_err());
+        assert!(Condition::parse(&format!("{}1{}", "(".repeat(20), ")".repeat(20))).is_ok());
+    }
+
+    #[test]
+    fn test_display_shows_source() {
+        let condition = Condition::parse("  V3 == 0x10 ").unwrap();
+        assert_eq!(condition.to_string(), "V3 == 0x10");
+    }
+}
@@ -1,15 +1,32 @@
+#[cfg(feature = "trace")]
+use crate::trace::{TraceEntry, Tracer};
 use crate::{
     Bus,
-    decoder::decode,
+    decoder::{decode, decode_long, is_long},
+    error::EmulationError,
     instruction::Instruction,
-    memory::{FONT_BASE, KEY_COUNT, ROM_START, SCREEN_HEIGHT, SCREEN_WIDTH},
+    memory::{AUDIO_PATTERN_SIZE, BIG_FONT_BASE, FONT_BASE, KEY_COUNT, PLANE_COUNT, ROM_START},
+    quirks::{IndexIncrement, Quirks},
+    state::CpuState,
+    timing::{TimingModel, vip_cycles},
 };
 use rand::{self, Rng};
 
 pub trait RngSource {
     fn next_byte(&mut self) -> u8;
+
+    /// Exports the generator's position so that it can be saved, `None` if that isn't supported.
+    fn export_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores a position from `export_state`, returns whether it was accepted.
+    fn import_state(&mut self, _state: &[u8]) -> bool {
+        false
+    }
 }
 
+/// Unseeded and unsaveable, see the `rng` module for reproducible sources.
 pub struct ThreadRngSource {
     rng: rand::rngs::ThreadRng,
 }
@@ -32,6 +49,28 @@ impl RngSource for ThreadRngSource {
     }
 }
 
+/// What to do when an instruction addresses memory past the end of RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressPolicy {
+    /// Stop with `EmulationError::MemoryOutOfBounds`.
+    #[default]
+    Fault,
+    /// Wrap the address around to the start of RAM.
+    Wrap,
+}
+
+/// What happened during a single `Cpu::execute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepOutcome {
+    /// The display buffer changed and should be presented again.
+    pub redraw: bool,
+    /// Machine cycles the instruction took under the `TimingModel`, 0 without one.
+    pub cycles: u32,
+    /// The instruction read the delay timer, waited for a key or was held back by the display
+    /// wait. Games do this once the frame is drawn.
+    pub waiting: bool,
+}
+
 pub struct Cpu {
     v_registers: [u8; 16],
     pc: u16,
@@ -41,10 +80,20 @@ pub struct Cpu {
     stack: [u16; 16],
     sp: u8,
     rng: Box<dyn RngSource>,
+    quirks: Quirks,
+    address_policy: AddressPolicy,
+    halted: bool,
+    timing: TimingModel,
+    /// A `DXYN` is held back by the display wait quirk.
+    waiting_for_vblank: bool,
+    /// The 60 Hz tick the held back `DXYN` waited for has passed.
+    vblank_passed: bool,
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer>,
 }
 
 impl Cpu {
-    pub fn new(rng: Box<dyn RngSource>) -> Self {
+    pub fn new(rng: Box<dyn RngSource>, quirks: Quirks) -> Self {
         Self {
             v_registers: [0; 16],
             pc: ROM_START,
@@ -54,43 +103,283 @@ impl Cpu {
             stack: [0; 16],
             sp: 0,
             rng,
+            quirks,
+            address_policy: AddressPolicy::default(),
+            halted: false,
+            timing: TimingModel::default(),
+            waiting_for_vblank: false,
+            vblank_passed: false,
+            #[cfg(feature = "trace")]
+            tracer: None,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn address_policy(&self) -> AddressPolicy {
+        self.address_policy
+    }
+
+    pub fn set_address_policy(&mut self, policy: AddressPolicy) {
+        self.address_policy = policy;
+    }
+
+    pub fn timing_model(&self) -> TimingModel {
+        self.timing
+    }
+
+    pub fn set_timing_model(&mut self, timing: TimingModel) {
+        self.timing = timing;
+    }
+
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            v_registers: self.v_registers,
+            pc: self.pc,
+            i: self.i,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack,
+            sp: self.sp,
+            halted: self.halted,
+            waiting_for_vblank: self.waiting_for_vblank,
+            vblank_passed: self.vblank_passed,
+            rng_state: self.rng.export_state(),
+        }
+    }
+
+    /// Restores a snapshot. The RNG is only restored if the snapshot carries its state and the
+    /// current `RngSource` accepts it.
+    pub fn restore(&mut self, state: &CpuState) {
+        self.v_registers = state.v_registers;
+        self.pc = state.pc;
+        self.i = state.i;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.halted = state.halted;
+        self.waiting_for_vblank = state.waiting_for_vblank;
+        self.vblank_passed = state.vblank_passed;
+        if let Some(rng_state) = &state.rng_state {
+            self.rng.import_state(rng_state);
+        }
+    }
+
+    /// Moves the RNG to a position from `RngSource::export_state`, returns whether it was accepted.
+    pub fn import_rng_state(&mut self, state: &[u8]) -> bool {
+        self.rng.import_state(state)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn v_registers(&self) -> &[u8; 16] {
+        &self.v_registers
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// Return addresses of the active calls, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Whether the buzzer sounds, which it does while the sound timer is non-zero.
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Whether the ROM executed the SUPER-CHIP `exit` instruction (00FD).
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether a `DXYN` is waiting for the next `decrease_timers`, see `Quirks::display_wait`.
+    /// `step` does nothing until then.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    fn resolve_address(&self, bus: &Bus, addr: usize) -> Result<usize, EmulationError> {
+        match self.address_policy {
+            AddressPolicy::Fault if addr >= bus.memory().len() => {
+                Err(EmulationError::MemoryOutOfBounds { addr })
+            }
+            AddressPolicy::Fault => Ok(addr),
+            AddressPolicy::Wrap => Ok(addr % bus.memory().len()),
         }
     }
 
+    fn read_memory(&self, bus: &mut Bus, addr: usize) -> Result<u8, EmulationError> {
+        let addr = self.resolve_address(bus, addr)?;
+        Ok(bus.read(addr))
+    }
+
+    fn write_memory(&self, bus: &mut Bus, addr: usize, value: u8) -> Result<(), EmulationError> {
+        let addr = self.resolve_address(bus, addr)?;
+        bus.write(addr, value);
+        Ok(())
+    }
+
+    // XO-CHIP skips over `F000 NNNN` as a whole
+    fn skip_next_instruction(&mut self, bus: &Bus) {
+        let next_is_long = bus.platform().has_xochip_opcodes()
+            && bus.memory().get(self.pc as usize) == Some(&0xF0)
+            && bus.memory().get(self.pc as usize + 1) == Some(&0x00);
+
+        self.pc = self.pc.wrapping_add(if next_is_long { 4 } else { 2 });
+    }
+
+    /// The register `BNNN` adds to NNN, VX with `Quirks::jump_uses_vx` and V0 otherwise.
+    pub(crate) fn jump_offset_register(&self, nnn: u16) -> u8 {
+        if self.quirks.jump_uses_vx {
+            ((nnn >> 8) & 0xF) as u8
+        } else {
+            0x0
+        }
+    }
+
+    /// Moves PC back onto the two byte instruction just fetched so that it runs again, undoing
+    /// `fetch` under either `AddressPolicy`.
+    fn repeat_instruction(&mut self, bus: &Bus) {
+        self.pc = match self.address_policy {
+            AddressPolicy::Fault => self.pc.wrapping_sub(2),
+            AddressPolicy::Wrap => {
+                let len = bus.memory().len();
+                ((self.pc as usize + len - 2) % len) as u16
+            }
+        };
+    }
+
     fn clear_screen(&mut self, bus: &mut Bus) {
         bus.clear_display();
     }
 
-    fn draw_sprite(&mut self, x: u8, y: u8, n: u8, bus: &mut Bus) {
-        let x_coord = self.v_registers[x as usize] % SCREEN_WIDTH as u8;
-        let y_coord = self.v_registers[y as usize] % SCREEN_HEIGHT as u8;
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8, bus: &mut Bus) -> Result<(), EmulationError> {
+        let width = bus.display_width();
+        let height = bus.display_height();
+        let x_coord = self.v_registers[x as usize] as usize % width;
+        let y_coord = self.v_registers[y as usize] as usize % height;
+
+        // SUPER-CHIP draws a 16x16 sprite (two bytes per row) for DXY0
+        let (rows, columns) = if n == 0 && bus.platform().has_superchip_opcodes() {
+            (16, 16)
+        } else {
+            (n as usize, 8)
+        };
+        let bytes_per_row = columns / 8;
+        let sprite_size = rows * bytes_per_row;
 
         self.v_registers[0xF] = 0;
 
-        for row in 0..n {
-            let current_y = y_coord + row;
-            if current_y >= SCREEN_HEIGHT as u8 {
-                break;
+        // XO-CHIP draws one sprite per selected plane, stored one after another
+        let mut sprite_addr = self.i as usize;
+        for plane in 0..PLANE_COUNT {
+            let plane_mask = 1 << plane;
+            if bus.selected_planes() & plane_mask == 0 {
+                continue;
             }
 
-            let sprite_row = bus.memory[self.i as usize + row as usize];
+            self.draw_plane(
+                bus,
+                sprite_addr,
+                plane_mask,
+                (x_coord, y_coord),
+                (rows, columns),
+            )?;
+            sprite_addr += sprite_size;
+        }
+
+        Ok(())
+    }
 
-            for bit_idx in 0..8 {
-                let current_x = x_coord + bit_idx;
-                if current_x >= SCREEN_WIDTH as u8 {
+    fn draw_plane(
+        &mut self,
+        bus: &mut Bus,
+        sprite_addr: usize,
+        plane_mask: u8,
+        (x_coord, y_coord): (usize, usize),
+        (rows, columns): (usize, usize),
+    ) -> Result<(), EmulationError> {
+        let width = bus.display_width();
+        let height = bus.display_height();
+        let bytes_per_row = columns / 8;
+
+        for row in 0..rows {
+            let mut current_y = y_coord + row;
+            if current_y >= height {
+                if !self.quirks.wrap_sprites {
                     break;
                 }
+                current_y %= height;
+            }
 
-                let bit = (sprite_row >> (7 - bit_idx)) & 1;
+            let row_addr = sprite_addr + row * bytes_per_row;
+            let mut sprite_row = (self.read_memory(bus, row_addr)? as u16) << 8;
+            if bytes_per_row == 2 {
+                sprite_row |= self.read_memory(bus, row_addr + 1)? as u16;
+            }
+
+            for bit_idx in 0..columns {
+                let mut current_x = x_coord + bit_idx;
+                if current_x >= width {
+                    if !self.quirks.wrap_sprites {
+                        break;
+                    }
+                    current_x %= width;
+                }
 
-                if bit == 1 && bus.write_pixel(current_x, current_y, 1) {
+                let bit = (sprite_row >> (15 - bit_idx)) & 1;
+
+                if bit == 1 && bus.write_pixel(current_x, current_y, plane_mask) {
                     self.v_registers[0xF] = 1;
                 }
             }
         }
+
+        Ok(())
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v_registers[0xF] = 0;
+        }
+    }
+
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        match self.quirks.load_store {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.i = self.i.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
     }
 
     pub fn decrease_timers(&mut self) {
+        if std::mem::take(&mut self.waiting_for_vblank) {
+            self.vblank_passed = true;
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -99,21 +388,122 @@ impl Cpu {
         }
     }
 
-    pub fn fetch(&mut self, bus: &Bus) -> u16 {
-        let byte1: u16 = (bus.memory[self.pc as usize] as u16) << 8;
-        let byte2: u16 = bus.memory[self.pc as usize + 1] as u16;
+    pub fn fetch(&mut self, bus: &mut Bus) -> Result<u16, EmulationError> {
+        let byte1: u16 = (bus.fetch(self.resolve_address(bus, self.pc as usize)?) as u16) << 8;
+        let byte2: u16 = bus.fetch(self.resolve_address(bus, self.pc as usize + 1)?) as u16;
+
+        self.pc = match self.address_policy {
+            AddressPolicy::Fault => self.pc.wrapping_add(2),
+            AddressPolicy::Wrap => ((self.pc as usize + 2) % bus.memory().len()) as u16,
+        };
 
-        // BUG what if it goes past RAM_SIZE?
-        // TODO: Move this out of here to somewhere else, maybe main loop or maybe not? Idk bro.
-        self.pc += 2;
+        Ok(byte1 | byte2)
+    }
+
+    /// Fetches and executes the instruction at PC.
+    pub fn step(&mut self, bus: &mut Bus) -> Result<StepOutcome, EmulationError> {
+        if self.halted || self.waiting_for_vblank {
+            return Ok(StepOutcome::default());
+        }
+
+        let opcode = self.fetch(bus)?;
+        self.execute(opcode, bus)
+    }
+
+    /// Installs or removes the tracer that records every executed instruction.
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
 
-        byte1 | byte2
+    #[cfg(not(feature = "trace"))]
+    pub fn execute(&mut self, opcode: u16, bus: &mut Bus) -> Result<StepOutcome, EmulationError> {
+        self.execute_instruction(opcode, bus)
     }
 
-    pub fn execute(&mut self, opcode: u16, bus: &mut Bus) -> bool {
+    /// Executes `opcode`, which was fetched from `PC - 2`. Only successfully executed
+    /// instructions are traced.
+    #[cfg(feature = "trace")]
+    pub fn execute(&mut self, opcode: u16, bus: &mut Bus) -> Result<StepOutcome, EmulationError> {
+        let pc = self.pc.wrapping_sub(2);
+        let operand = (is_long(opcode) && bus.platform().has_xochip_opcodes()).then(|| {
+            let byte = |offset: usize| {
+                bus.memory()
+                    .get(self.pc as usize + offset)
+                    .copied()
+                    .unwrap_or(0)
+            };
+            u16::from_be_bytes([byte(0), byte(1)])
+        });
+
+        let traced = self
+            .tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.wants(pc, opcode, operand));
+        if !traced {
+            return self.execute_instruction(opcode, bus);
+        }
+
+        let registers_before = self.v_registers;
+        bus.start_write_log();
+        let result = self.execute_instruction(opcode, bus);
+        let writes = bus.take_write_log();
+
+        if result.is_ok()
+            && let Some(tracer) = &mut self.tracer
+        {
+            tracer.record(&TraceEntry {
+                pc,
+                opcode,
+                operand,
+                registers_before,
+                registers: self.v_registers,
+                i: self.i,
+                sp: self.sp,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+                memory_writes: writes.memory,
+                display_writes: writes.display,
+            });
+        }
+
+        result
+    }
+
+    fn execute_instruction(
+        &mut self,
+        opcode: u16,
+        bus: &mut Bus,
+    ) -> Result<StepOutcome, EmulationError> {
         let mut should_redraw = false;
-        let instruction =
-            decode(opcode).unwrap_or_else(|| panic!("Invalid opcode: {:#06X}", opcode));
+        let mut waiting = false;
+        let invalid_opcode = EmulationError::InvalidOpcode {
+            pc: self.pc.wrapping_sub(2),
+            opcode,
+        };
+        let instruction = if is_long(opcode) && bus.platform().has_xochip_opcodes() {
+            let operand = self.fetch(bus)?;
+            decode_long(opcode, operand)
+        } else {
+            decode(opcode)
+        }
+        .ok_or(invalid_opcode)?;
+
+        if (instruction.is_superchip() && !bus.platform().has_superchip_opcodes())
+            || (instruction.is_xochip() && !bus.platform().has_xochip_opcodes())
+        {
+            return Err(invalid_opcode);
+        }
+
+        let cycles = match self.timing {
+            TimingModel::None => 0,
+            TimingModel::CosmacVip => vip_cycles(&instruction, self, bus),
+        };
 
         match instruction {
             Instruction::Cls => {
@@ -122,30 +512,37 @@ impl Cpu {
             }
             Instruction::Ret => {
                 if self.sp == 0 {
-                    panic!("The SP cannot be negative!");
+                    return Err(EmulationError::StackUnderflow {
+                        pc: self.pc.wrapping_sub(2),
+                    });
                 }
                 self.pc = self.stack[self.sp as usize];
                 self.sp -= 1;
             }
             Instruction::Jump(nnn) => self.pc = nnn,
             Instruction::Call(nnn) => {
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return Err(EmulationError::StackOverflow {
+                        pc: self.pc.wrapping_sub(2),
+                    });
+                }
                 self.sp += 1;
                 self.stack[self.sp as usize] = self.pc;
                 self.pc = nnn;
             }
             Instruction::SkipEq(x, kk) => {
                 if self.v_registers[x as usize] == kk {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
             Instruction::SkipNotEq(x, kk) => {
                 if self.v_registers[x as usize] != kk {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
             Instruction::SkipRegEq(x, y) => {
                 if self.v_registers[x as usize] == self.v_registers[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
             Instruction::Load(x, kk) => self.v_registers[x as usize] = kk,
@@ -155,9 +552,18 @@ impl Cpu {
             Instruction::LoadReg(x, y) => {
                 self.v_registers[x as usize] = self.v_registers[y as usize]
             }
-            Instruction::Or(x, y) => self.v_registers[x as usize] |= self.v_registers[y as usize],
-            Instruction::And(x, y) => self.v_registers[x as usize] &= self.v_registers[y as usize],
-            Instruction::Xor(x, y) => self.v_registers[x as usize] ^= self.v_registers[y as usize],
+            Instruction::Or(x, y) => {
+                self.v_registers[x as usize] |= self.v_registers[y as usize];
+                self.reset_vf();
+            }
+            Instruction::And(x, y) => {
+                self.v_registers[x as usize] &= self.v_registers[y as usize];
+                self.reset_vf();
+            }
+            Instruction::Xor(x, y) => {
+                self.v_registers[x as usize] ^= self.v_registers[y as usize];
+                self.reset_vf();
+            }
             Instruction::AddReg(x, y) => {
                 let result: u16 =
                     self.v_registers[x as usize] as u16 + self.v_registers[y as usize] as u16;
@@ -176,9 +582,12 @@ impl Cpu {
                 self.v_registers[x as usize] = result;
                 self.v_registers[0xF] = carry;
             }
-            // TODO also look into RESEARCH.md, there are 2 versions of this, the legacy op copies VY into
-            // VX and then shifts, the modern op just shifts. So maybe add a config?
-            Instruction::Shr(x, _) => {
+            // The legacy op copies VY into VX and then shifts, the modern op just shifts.
+            // See `Quirks::shift_uses_vy` and RESEARCH.md.
+            Instruction::Shr(x, y) => {
+                if self.quirks.shift_uses_vy {
+                    self.v_registers[x as usize] = self.v_registers[y as usize];
+                }
                 if self.v_registers[x as usize] & 1 == 1 {
                     self.v_registers[0xF] = 1;
                 } else {
@@ -197,7 +606,10 @@ impl Cpu {
                 self.v_registers[x as usize] = result;
                 self.v_registers[0xF] = carry;
             }
-            Instruction::Shl(x, _) => {
+            Instruction::Shl(x, y) => {
+                if self.quirks.shift_uses_vy {
+                    self.v_registers[x as usize] = self.v_registers[y as usize];
+                }
                 if (self.v_registers[x as usize] & 0x80) != 0 {
                     self.v_registers[0xF] = 1;
                 } else {
@@ -207,31 +619,45 @@ impl Cpu {
             }
             Instruction::SkipRegNotEq(x, y) => {
                 if self.v_registers[x as usize] != self.v_registers[y as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
             Instruction::LoadI(nnn) => self.i = nnn,
-            Instruction::JumpOffset(nnn) => self.pc = nnn + self.v_registers[0x0] as u16,
+            Instruction::JumpOffset(nnn) => {
+                let offset_reg = self.jump_offset_register(nnn);
+                self.pc = nnn + self.v_registers[offset_reg as usize] as u16;
+            }
             Instruction::Rand(x, kk) => self.v_registers[x as usize] = self.rng.next_byte() & kk,
+            Instruction::Draw(..)
+                if self.quirks.display_wait && !std::mem::take(&mut self.vblank_passed) =>
+            {
+                self.waiting_for_vblank = true;
+                self.repeat_instruction(bus);
+                return Ok(StepOutcome {
+                    waiting: true,
+                    ..StepOutcome::default()
+                });
+            }
             Instruction::Draw(x, y, n) => {
-                self.draw_sprite(x, y, n, bus);
+                self.draw_sprite(x, y, n, bus)?;
                 should_redraw = true;
             }
             Instruction::SkipIfPressed(x) => {
                 let key = self.v_registers[x as usize] & 0x0F;
                 if bus.is_key_pressed(key) {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
 
             Instruction::SkipIfNotPressed(x) => {
                 let key = self.v_registers[x as usize] & 0x0F;
                 if !bus.is_key_pressed(key) {
-                    self.pc += 2;
+                    self.skip_next_instruction(bus);
                 }
             }
             Instruction::LoadRegFromDelay(x) => {
                 self.v_registers[x as usize] = self.delay_timer;
+                waiting = true;
             }
             Instruction::WaitForKey(x) => {
                 let mut key_pressed = None;
@@ -245,7 +671,10 @@ impl Cpu {
 
                 match key_pressed {
                     Some(k) => self.v_registers[x as usize] = k,
-                    None => self.pc -= 2,
+                    None => {
+                        self.repeat_instruction(bus);
+                        waiting = true;
+                    }
                 }
             }
             Instruction::LoadDelayFromReg(x) => {
@@ -255,7 +684,7 @@ impl Cpu {
                 self.sound_timer = self.v_registers[x as usize];
             }
             Instruction::AddIndex(x) => {
-                self.i += self.v_registers[x as usize] as u16;
+                self.i = self.i.wrapping_add(self.v_registers[x as usize] as u16);
             }
             Instruction::LoadFont(x) => {
                 self.i = FONT_BASE + ((self.v_registers[x as usize] & 0x0F) * 5) as u16;
@@ -265,36 +694,115 @@ impl Cpu {
                 let tens = (self.v_registers[x as usize] / 10) % 10;
                 let ones = self.v_registers[x as usize] % 10;
 
-                bus.memory[self.i as usize..self.i as usize + 3]
-                    .copy_from_slice(&[hundreds, tens, ones]);
+                for (offset, digit) in [hundreds, tens, ones].into_iter().enumerate() {
+                    self.write_memory(bus, self.i as usize + offset, digit)?;
+                }
             }
             Instruction::DumpRegs(x) => {
                 for reg_num in 0..=x {
-                    bus.memory[self.i as usize + reg_num as usize] =
-                        self.v_registers[reg_num as usize];
+                    self.write_memory(
+                        bus,
+                        self.i as usize + reg_num as usize,
+                        self.v_registers[reg_num as usize],
+                    )?;
                 }
+                self.increment_index_after_load_store(x);
             }
             Instruction::FillRegs(x) => {
                 for byte_num in 0..=x {
                     self.v_registers[byte_num as usize] =
-                        bus.memory[self.i as usize + byte_num as usize];
+                        self.read_memory(bus, self.i as usize + byte_num as usize)?;
+                }
+                self.increment_index_after_load_store(x);
+            }
+            Instruction::ScrollDown(n) => {
+                bus.scroll_down(n as usize);
+                should_redraw = true;
+            }
+            Instruction::ScrollRight => {
+                bus.scroll_right(4);
+                should_redraw = true;
+            }
+            Instruction::ScrollLeft => {
+                bus.scroll_left(4);
+                should_redraw = true;
+            }
+            Instruction::Exit => self.halted = true,
+            Instruction::LowRes => {
+                bus.set_hires(false);
+                should_redraw = true;
+            }
+            Instruction::HighRes => {
+                bus.set_hires(true);
+                should_redraw = true;
+            }
+            Instruction::LoadBigFont(x) => {
+                self.i = BIG_FONT_BASE + ((self.v_registers[x as usize] & 0x0F) as u16 * 10);
+            }
+            Instruction::SaveFlags(x) => {
+                bus.set_rpl_flags(&self.v_registers[..=x as usize]);
+            }
+            Instruction::LoadFlags(x) => {
+                self.v_registers[..=x as usize].copy_from_slice(&bus.rpl_flags()[..=x as usize]);
+            }
+            Instruction::ScrollUp(n) => {
+                bus.scroll_up(n as usize);
+                should_redraw = true;
+            }
+            Instruction::SaveRange(x, y) => {
+                for (offset, reg_num) in register_range(x, y).enumerate() {
+                    self.write_memory(
+                        bus,
+                        self.i as usize + offset,
+                        self.v_registers[reg_num as usize],
+                    )?;
+                }
+            }
+            Instruction::LoadRange(x, y) => {
+                for (offset, reg_num) in register_range(x, y).enumerate() {
+                    self.v_registers[reg_num as usize] =
+                        self.read_memory(bus, self.i as usize + offset)?;
+                }
+            }
+            Instruction::LoadILong(nnnn) => self.i = nnnn,
+            Instruction::SelectPlanes(n) => bus.select_planes(n),
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_memory(bus, self.i as usize + offset)?;
                 }
+                bus.set_audio_pattern(&pattern);
             }
+            Instruction::SetPitch(x) => bus.set_pitch(self.v_registers[x as usize]),
         }
 
-        should_redraw
+        Ok(StepOutcome {
+            redraw: should_redraw,
+            cycles,
+            waiting,
+        })
+    }
+}
+
+// 5XY2/5XY3 walk the registers from X to Y, backwards if X > Y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
 impl Default for Cpu {
     fn default() -> Self {
-        Self::new(Box::new(ThreadRngSource::new()))
+        Self::new(Box::new(ThreadRngSource::new()), Quirks::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::memory::FONT_BASE;
+    use crate::platform::Platform;
 
     use super::*;
 
@@ -315,11 +823,18 @@ mod tests {
     }
 
     fn setup() -> (Cpu, Bus) {
-        (Cpu::new(Box::new(MockRng::new(0x54))), Bus::new())
+        (
+            Cpu::new(Box::new(MockRng::new(0x54)), Quirks::default()),
+            Bus::new(),
+        )
+    }
+
+    fn setup_with_quirks(quirks: Quirks) -> (Cpu, Bus) {
+        (Cpu::new(Box::new(MockRng::new(0x54)), quirks), Bus::new())
     }
 
     fn setup_with_sprite(bus: &mut Bus, cpu: &mut Cpu, address: u16, data: u8) {
-        bus.memory[address as usize] = data;
+        bus.write(address as usize, data);
         cpu.i = address;
     }
 
@@ -331,7 +846,7 @@ mod tests {
 
         bus.load_rom(&dummy_rom).unwrap();
 
-        let opcode = cpu.fetch(&bus);
+        let opcode = cpu.fetch(&mut bus).unwrap();
         // bytes should should be successfully fetched and combined into a u16 opcode (Big Endian)
         assert_eq!(opcode, 0x1234);
         // pc should move forward upon reading bytes from memory (2 bytes at a time)
@@ -347,11 +862,11 @@ mod tests {
         cpu.v_registers[1] = 10;
 
         // Draw something first
-        cpu.execute(0xD011, &mut bus);
+        cpu.execute(0xD011, &mut bus).unwrap();
         assert_eq!(bus.get_pixel(10, 10), 1);
 
         // Test the Clear
-        cpu.execute(0x00E0, &mut bus);
+        cpu.execute(0x00E0, &mut bus).unwrap();
         assert_eq!(bus.get_pixel(10, 10), 0);
     }
 
@@ -364,7 +879,7 @@ mod tests {
 
         let old_sp = cpu.sp;
 
-        cpu.execute(0x00EE, &mut bus);
+        cpu.execute(0x00EE, &mut bus).unwrap();
         assert_eq!(cpu.pc, 0x123);
         assert_eq!(cpu.sp, old_sp - 1);
     }
@@ -373,7 +888,7 @@ mod tests {
     fn test_op_1nnn_jump() {
         let (mut cpu, mut bus) = setup();
 
-        cpu.execute(0x1234, &mut bus);
+        cpu.execute(0x1234, &mut bus).unwrap();
         assert_eq!(cpu.pc, 0x234);
     }
 
@@ -383,7 +898,7 @@ mod tests {
 
         let old_pc = cpu.pc;
 
-        cpu.execute(0x2432, &mut bus);
+        cpu.execute(0x2432, &mut bus).unwrap();
         assert_eq!(cpu.sp, 1);
         assert_eq!(cpu.stack[cpu.sp as usize], old_pc);
         assert_eq!(cpu.pc, 0x432);
@@ -396,11 +911,11 @@ mod tests {
         cpu.v_registers[0x6] = 0x78;
         let old_pc = cpu.pc;
 
-        cpu.execute(0x3612, &mut bus);
+        cpu.execute(0x3612, &mut bus).unwrap();
         assert_ne!(cpu.v_registers[0x6], 0x12);
         assert_eq!(cpu.pc, old_pc);
 
-        cpu.execute(0x3678, &mut bus);
+        cpu.execute(0x3678, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x6], 0x78);
         assert_eq!(cpu.pc, old_pc + 2);
     }
@@ -412,11 +927,11 @@ mod tests {
         cpu.v_registers[0x6] = 0x78;
         let old_pc = cpu.pc;
 
-        cpu.execute(0x4678, &mut bus);
+        cpu.execute(0x4678, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x6], 0x78);
         assert_eq!(cpu.pc, old_pc);
 
-        cpu.execute(0x4612, &mut bus);
+        cpu.execute(0x4612, &mut bus).unwrap();
         assert_ne!(cpu.v_registers[0x6], 0x12);
         assert_eq!(cpu.pc, old_pc + 2);
     }
@@ -429,12 +944,12 @@ mod tests {
         cpu.v_registers[0x7] = 0x67;
         let old_pc = cpu.pc;
 
-        cpu.execute(0x5670, &mut bus);
+        cpu.execute(0x5670, &mut bus).unwrap();
         assert_ne!(cpu.v_registers[0x6], cpu.v_registers[0x7]);
         assert_eq!(cpu.pc, old_pc);
 
         cpu.v_registers[0x6] = 0x67;
-        cpu.execute(0x5670, &mut bus);
+        cpu.execute(0x5670, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x6], cpu.v_registers[0x7]);
         assert_eq!(cpu.pc, old_pc + 2);
     }
@@ -443,7 +958,7 @@ mod tests {
     fn test_op_6xkk_load() {
         let (mut cpu, mut bus) = setup();
 
-        cpu.execute(0x6350, &mut bus);
+        cpu.execute(0x6350, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[3], 0x50);
     }
 
@@ -453,7 +968,7 @@ mod tests {
         cpu.v_registers[1] = 0xFE; // 254
 
         // Add 3 to register 1 (should result in 1, wrapping around)
-        cpu.execute(0x7103, &mut bus);
+        cpu.execute(0x7103, &mut bus).unwrap();
 
         assert_eq!(cpu.v_registers[1], 0x01);
         assert_eq!(cpu.v_registers[0xF], 0, "VF should NOT be affected by 7XKK");
@@ -466,7 +981,7 @@ mod tests {
         cpu.v_registers[0x7] = 0x42;
         cpu.v_registers[0x4] = 0x00;
 
-        cpu.execute(0x8470, &mut bus);
+        cpu.execute(0x8470, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x4], cpu.v_registers[0x7]);
     }
 
@@ -477,7 +992,7 @@ mod tests {
         cpu.v_registers[0x7] = 0x42;
         cpu.v_registers[0x4] = 0x54;
 
-        cpu.execute(0x8471, &mut bus);
+        cpu.execute(0x8471, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x4], 0x56);
     }
 
@@ -488,7 +1003,7 @@ mod tests {
         cpu.v_registers[0x7] = 0x42;
         cpu.v_registers[0x4] = 0x54;
 
-        cpu.execute(0x8472, &mut bus);
+        cpu.execute(0x8472, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x4], 0x40);
     }
 
@@ -499,7 +1014,7 @@ mod tests {
         cpu.v_registers[0x7] = 0x42;
         cpu.v_registers[0x4] = 0x54;
 
-        cpu.execute(0x8473, &mut bus);
+        cpu.execute(0x8473, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x4], 0x16);
     }
 
@@ -512,20 +1027,20 @@ mod tests {
 
         // carry flag should be 0 at start
         assert_eq!(cpu.v_registers[0xF], 0);
-        cpu.execute(0x8474, &mut bus);
+        cpu.execute(0x8474, &mut bus).unwrap();
         // should still be 0, since result < 255
         assert_eq!(cpu.v_registers[0xF], 0);
         assert_eq!(cpu.v_registers[0x4], 0x96);
 
         cpu.v_registers[0x7] = 0x96;
-        cpu.execute(0x8474, &mut bus);
+        cpu.execute(0x8474, &mut bus).unwrap();
         // carry flag should be set to 1, since result > 255
         assert_eq!(cpu.v_registers[0xF], 1);
         assert_eq!(cpu.v_registers[0x4], 0x2C);
 
         cpu.v_registers[0x7] = 0x01;
         cpu.v_registers[0x4] = 0x01;
-        cpu.execute(0x8474, &mut bus);
+        cpu.execute(0x8474, &mut bus).unwrap();
         // carry flag should be set back to 0, since result < 255
         assert_eq!(cpu.v_registers[0xF], 0);
     }
@@ -539,12 +1054,12 @@ mod tests {
 
         // carry flag should be 0 at start
         assert_eq!(cpu.v_registers[0xF], 0);
-        cpu.execute(0x8475, &mut bus);
+        cpu.execute(0x8475, &mut bus).unwrap();
         // should become 1, since VX > VY
         assert_eq!(cpu.v_registers[0xF], 1);
         assert_eq!(cpu.v_registers[0x4], 0x12);
 
-        cpu.execute(0x8475, &mut bus);
+        cpu.execute(0x8475, &mut bus).unwrap();
         // carry flag should become 0, since VX < VY
         assert_eq!(cpu.v_registers[0xF], 0);
         // underflow should be handled correctly
@@ -558,12 +1073,12 @@ mod tests {
         cpu.v_registers[0x4] = 0xA9;
         // carry flag should be 0 at start
         assert_eq!(cpu.v_registers[0xF], 0);
-        cpu.execute(0x8476, &mut bus);
+        cpu.execute(0x8476, &mut bus).unwrap();
         // carry flag should be set to 1, since lsb is 1
         assert_eq!(cpu.v_registers[0xF], 1);
         assert_eq!(cpu.v_registers[0x4], 0x54);
 
-        cpu.execute(0x8476, &mut bus);
+        cpu.execute(0x8476, &mut bus).unwrap();
         // carry flag should be set to 0, since lsb is 0
         assert_eq!(cpu.v_registers[0xF], 0);
         assert_eq!(cpu.v_registers[0x4], 0x2A);
@@ -578,13 +1093,13 @@ mod tests {
 
         // carry flag should be 0 at start
         assert_eq!(cpu.v_registers[0xF], 0);
-        cpu.execute(0x8477, &mut bus);
+        cpu.execute(0x8477, &mut bus).unwrap();
         // should become 1, since VY > VX
         assert_eq!(cpu.v_registers[0xF], 1);
         assert_eq!(cpu.v_registers[0x4], 0x12);
 
         cpu.v_registers[0x7] = 0x11;
-        cpu.execute(0x8477, &mut bus);
+        cpu.execute(0x8477, &mut bus).unwrap();
         // carry flag should become 0, since VY < VX
         assert_eq!(cpu.v_registers[0xF], 0);
         // underflow should be handled correctly
@@ -598,12 +1113,12 @@ mod tests {
         cpu.v_registers[0x4] = 0xA9;
         // carry flag should be 0 at start
         assert_eq!(cpu.v_registers[0xF], 0);
-        cpu.execute(0x847E, &mut bus);
+        cpu.execute(0x847E, &mut bus).unwrap();
         // carry flag should be set to 1, since msb is 1
         assert_eq!(cpu.v_registers[0xF], 1);
         assert_eq!(cpu.v_registers[0x4], 0x52);
 
-        cpu.execute(0x847E, &mut bus);
+        cpu.execute(0x847E, &mut bus).unwrap();
         // carry flag should be set to 0, since msb is 0
         assert_eq!(cpu.v_registers[0xF], 0);
         assert_eq!(cpu.v_registers[0x4], 0xA4);
@@ -617,12 +1132,12 @@ mod tests {
         cpu.v_registers[0x7] = 0x67;
         let old_pc = cpu.pc;
 
-        cpu.execute(0x9670, &mut bus);
+        cpu.execute(0x9670, &mut bus).unwrap();
         assert_eq!(cpu.v_registers[0x6], cpu.v_registers[0x7]);
         assert_eq!(cpu.pc, old_pc);
 
         cpu.v_registers[0x6] = 0x78;
-        cpu.execute(0x9670, &mut bus);
+        cpu.execute(0x9670, &mut bus).unwrap();
         assert_ne!(cpu.v_registers[0x6], cpu.v_registers[0x7]);
         assert_eq!(cpu.pc, old_pc + 2);
     }
@@ -631,7 +1146,7 @@ mod tests {
     fn test_op_annn_load_i() {
         let (mut cpu, mut bus) = setup();
 
-        cpu.execute(0xA123, &mut bus);
+        cpu.execute(0xA123, &mut bus).unwrap();
         assert_eq!(cpu.i, 0x123);
     }
 
@@ -641,7 +1156,7 @@ mod tests {
 
         cpu.v_registers[0x0] = 0x32;
 
-        cpu.execute(0xB234, &mut bus);
+        cpu.execute(0xB234, &mut bus).unwrap();
         assert_eq!(cpu.pc, 0x266);
     }
 
@@ -650,7 +1165,7 @@ mod tests {
         let (mut cpu, mut bus) = setup();
         cpu.v_registers[0x4] = 0xFF;
 
-        cpu.execute(0xC442, &mut bus);
+        cpu.execute(0xC442, &mut bus).unwrap();
 
         // 0x54 & 0x42 = 0x40 (I'm using mock rand generator)
         assert_eq!(cpu.v_registers[0x4], 0x40);
@@ -664,12 +1179,12 @@ mod tests {
         cpu.v_registers[0] = 10;
         cpu.v_registers[1] = 10;
 
-        cpu.execute(0xD011, &mut bus);
+        cpu.execute(0xD011, &mut bus).unwrap();
         assert_eq!(bus.get_pixel(10, 10), 1);
         assert_eq!(cpu.v_registers[0xF], 0);
 
         // Test Collision
-        cpu.execute(0xD011, &mut bus);
+        cpu.execute(0xD011, &mut bus).unwrap();
         assert_eq!(bus.get_pixel(10, 10), 0);
         assert_eq!(cpu.v_registers[0xF], 1);
     }
@@ -680,11 +1195,11 @@ mod tests {
 
         let old_pc = cpu.pc;
 
-        cpu.execute(0xE09E, &mut bus);
+        cpu.execute(0xE09E, &mut bus).unwrap();
         assert_eq!(cpu.pc, old_pc);
 
         bus.set_key(0x0, true);
-        cpu.execute(0xE09E, &mut bus);
+        cpu.execute(0xE09E, &mut bus).unwrap();
         assert_eq!(cpu.pc, old_pc + 2);
     }
 
@@ -695,11 +1210,11 @@ mod tests {
         let old_pc = cpu.pc;
 
         bus.set_key(0x0, true);
-        cpu.execute(0xE0A1, &mut bus);
+        cpu.execute(0xE0A1, &mut bus).unwrap();
         assert_eq!(cpu.pc, old_pc);
 
         bus.set_key(0x0, false);
-        cpu.execute(0xE0A1, &mut bus);
+        cpu.execute(0xE0A1, &mut bus).unwrap();
         assert_eq!(cpu.pc, old_pc + 2);
     }
 
@@ -708,7 +1223,7 @@ mod tests {
         let (mut cpu, mut bus) = setup();
 
         cpu.delay_timer = 0xFF;
-        cpu.execute(0xF007, &mut bus);
+        assert!(cpu.execute(0xF007, &mut bus).unwrap().waiting);
         assert_eq!(cpu.v_registers[0x0], 0xFF);
     }
 
@@ -717,16 +1232,16 @@ mod tests {
         let (mut cpu, mut bus) = setup();
         let old_pc = cpu.pc;
 
-        bus.memory[cpu.pc as usize] = 0xF1;
-        bus.memory[cpu.pc as usize + 1] = 0x0A;
+        bus.write(cpu.pc as usize, 0xF1);
+        bus.write(cpu.pc as usize + 1, 0x0A);
 
-        let opcode = cpu.fetch(&bus);
-        cpu.execute(opcode, &mut bus);
+        let opcode = cpu.fetch(&mut bus).unwrap();
+        assert!(cpu.execute(opcode, &mut bus).unwrap().waiting);
         assert_eq!(cpu.pc, old_pc);
 
         bus.set_key(0xA, true);
-        let opcode = cpu.fetch(&bus);
-        cpu.execute(opcode, &mut bus);
+        let opcode = cpu.fetch(&mut bus).unwrap();
+        assert!(!cpu.execute(opcode, &mut bus).unwrap().waiting);
         assert_eq!(cpu.pc, old_pc + 2);
         assert_eq!(cpu.v_registers[0x1], 0xA);
     }
@@ -736,7 +1251,7 @@ mod tests {
         let (mut cpu, mut bus) = setup();
         cpu.v_registers[0x1] = 0xFF;
 
-        cpu.execute(0xF115, &mut bus);
+        cpu.execute(0xF115, &mut bus).unwrap();
         assert_eq!(cpu.delay_timer, 0xFF);
     }
 
@@ -745,7 +1260,7 @@ mod tests {
         let (mut cpu, mut bus) = setup();
         cpu.v_registers[0x1] = 0xFF;
 
-        cpu.execute(0xF118, &mut bus);
+        cpu.execute(0xF118, &mut bus).unwrap();
         assert_eq!(cpu.sound_timer, 0xFF);
     }
 
@@ -755,7 +1270,7 @@ mod tests {
         cpu.i = 0x500;
         cpu.v_registers[0x1] = 0xFE;
 
-        cpu.execute(0xF11E, &mut bus);
+        cpu.execute(0xF11E, &mut bus).unwrap();
 
         assert_eq!(cpu.i, 0x5FE);
     }
@@ -766,7 +1281,7 @@ mod tests {
         cpu.i = 0x500;
         cpu.v_registers[0x1] = 0x32;
 
-        cpu.execute(0xF129, &mut bus);
+        cpu.execute(0xF129, &mut bus).unwrap();
         assert_eq!(
             cpu.i,
             FONT_BASE + ((cpu.v_registers[0x1] & 0x0F) * 5) as u16
@@ -779,11 +1294,11 @@ mod tests {
         cpu.i = 0x500;
         cpu.v_registers[0x1] = 0xFB;
 
-        cpu.execute(0xF133, &mut bus);
+        cpu.execute(0xF133, &mut bus).unwrap();
 
-        assert_eq!(bus.memory[cpu.i as usize], 2);
-        assert_eq!(bus.memory[cpu.i as usize + 1], 5);
-        assert_eq!(bus.memory[cpu.i as usize + 2], 1);
+        assert_eq!(bus.memory()[cpu.i as usize], 2);
+        assert_eq!(bus.memory()[cpu.i as usize + 1], 5);
+        assert_eq!(bus.memory()[cpu.i as usize + 2], 1);
     }
 
     #[test]
@@ -795,10 +1310,10 @@ mod tests {
             cpu.v_registers[x] = (x * 10) as u8;
         }
 
-        cpu.execute(0xF555, &mut bus);
+        cpu.execute(0xF555, &mut bus).unwrap();
 
         for x in 0..=5 {
-            assert_eq!(bus.memory[cpu.i as usize + x], (x * 10) as u8);
+            assert_eq!(bus.memory()[cpu.i as usize + x], (x * 10) as u8);
         }
     }
 
@@ -809,11 +1324,428 @@ mod tests {
 
         let test_data = [0xFF, 0xEE, 0xDD, 0xCC];
         for (index, &byte) in test_data.iter().enumerate() {
-            bus.memory[cpu.i as usize + index] = byte;
+            bus.write(cpu.i as usize + index, byte);
         }
 
-        cpu.execute(0xF365, &mut bus);
+        cpu.execute(0xF365, &mut bus).unwrap();
 
         assert_eq!(&cpu.v_registers[0..test_data.len()], test_data);
     }
+
+    #[test]
+    fn test_quirk_shift_uses_vy() {
+        let (mut cpu, mut bus) = setup_with_quirks(Quirks::cosmac_vip());
+
+        cpu.v_registers[0x4] = 0x00;
+        cpu.v_registers[0x7] = 0x81;
+
+        cpu.execute(0x8476, &mut bus).unwrap();
+        // VY is copied into VX before shifting
+        assert_eq!(cpu.v_registers[0x4], 0x40);
+        assert_eq!(cpu.v_registers[0xF], 1);
+
+        cpu.execute(0x847E, &mut bus).unwrap();
+        assert_eq!(cpu.v_registers[0x4], 0x02);
+        assert_eq!(cpu.v_registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_quirk_load_store_increments_i() {
+        let (mut cpu, mut bus) = setup_with_quirks(Quirks::cosmac_vip());
+        cpu.i = 0x500;
+
+        cpu.execute(0xF355, &mut bus).unwrap();
+        assert_eq!(cpu.i, 0x504);
+
+        cpu.set_quirks(Quirks::chip48());
+        cpu.execute(0xF365, &mut bus).unwrap();
+        assert_eq!(cpu.i, 0x507);
+    }
+
+    #[test]
+    fn test_quirk_jump_uses_vx() {
+        let (mut cpu, mut bus) = setup_with_quirks(Quirks::superchip());
+
+        cpu.v_registers[0x0] = 0x10;
+        cpu.v_registers[0x2] = 0x32;
+
+        cpu.execute(0xB234, &mut bus).unwrap();
+        assert_eq!(cpu.pc, 0x266);
+    }
+
+    #[test]
+    fn test_quirk_wrap_sprites() {
+        let quirks = Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        };
+        let (mut cpu, mut bus) = setup_with_quirks(quirks);
+        setup_with_sprite(&mut bus, &mut cpu, 0x400, 0xFF);
+
+        cpu.v_registers[0] = 60;
+        cpu.v_registers[1] = 31;
+
+        cpu.execute(0xD011, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(63, 31), 1);
+        // the right half of the sprite wraps around to the left edge
+        assert_eq!(bus.get_pixel(0, 31), 1);
+        assert_eq!(bus.get_pixel(3, 31), 1);
+        assert_eq!(bus.get_pixel(4, 31), 0);
+    }
+
+    #[test]
+    fn test_quirk_clip_sprites() {
+        let (mut cpu, mut bus) = setup();
+        setup_with_sprite(&mut bus, &mut cpu, 0x400, 0xFF);
+
+        cpu.v_registers[0] = 60;
+        cpu.v_registers[1] = 31;
+
+        cpu.execute(0xD011, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(63, 31), 1);
+        assert_eq!(bus.get_pixel(0, 31), 0);
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        let (mut cpu, mut bus) = setup_with_quirks(Quirks::cosmac_vip());
+
+        for opcode in [0x8471, 0x8472, 0x8473] {
+            cpu.v_registers[0xF] = 1;
+            cpu.execute(opcode, &mut bus).unwrap();
+            assert_eq!(cpu.v_registers[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_opcode_is_an_error() {
+        let (mut cpu, mut bus) = setup();
+        bus.load_rom(&[0xFF, 0xFF]).unwrap();
+
+        assert_eq!(
+            cpu.step(&mut bus),
+            Err(EmulationError::InvalidOpcode {
+                pc: ROM_START,
+                opcode: 0xFFFF
+            })
+        );
+    }
+
+    #[test]
+    fn test_ret_with_empty_stack_is_an_error() {
+        let (mut cpu, mut bus) = setup();
+
+        assert_eq!(
+            cpu.execute(0x00EE, &mut bus),
+            Err(EmulationError::StackUnderflow { pc: ROM_START - 2 })
+        );
+    }
+
+    #[test]
+    fn test_call_with_full_stack_is_an_error() {
+        let (mut cpu, mut bus) = setup();
+
+        for _ in 0..15 {
+            cpu.execute(0x2300, &mut bus).unwrap();
+        }
+        assert!(matches!(
+            cpu.execute(0x2300, &mut bus),
+            Err(EmulationError::StackOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fetch_past_end_of_ram() {
+        let (mut cpu, mut bus) = setup();
+        cpu.pc = 0xFFF;
+
+        assert_eq!(
+            cpu.fetch(&mut bus),
+            Err(EmulationError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+
+        cpu.set_address_policy(AddressPolicy::Wrap);
+        assert!(cpu.fetch(&mut bus).is_ok());
+        assert_eq!(cpu.pc, 0x001);
+    }
+
+    #[test]
+    fn test_wait_for_key_at_end_of_ram() {
+        let (mut cpu, mut bus) = setup();
+        cpu.set_address_policy(AddressPolicy::Wrap);
+        bus.write(0xFFE, 0xF0);
+        bus.write(0xFFF, 0x0A);
+        cpu.pc = 0xFFE;
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc, 0xFFE);
+        bus.set_key(0x7, true);
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.v_registers[0x0], 0x7);
+        assert_eq!(cpu.pc, 0x000);
+    }
+
+    #[test]
+    fn test_display_wait_at_end_of_ram() {
+        let (_, mut bus) = setup();
+        let mut cpu = Cpu::new(Box::new(MockRng::new(0)), Quirks::cosmac_vip());
+        cpu.set_address_policy(AddressPolicy::Wrap);
+        bus.write(0xFFE, 0xD0);
+        bus.write(0xFFF, 0x01);
+        cpu.pc = 0xFFE;
+
+        assert!(cpu.step(&mut bus).unwrap().waiting);
+        assert_eq!(cpu.pc, 0xFFE);
+        cpu.decrease_timers();
+        assert!(cpu.step(&mut bus).unwrap().redraw);
+        assert_eq!(cpu.pc, 0x000);
+    }
+
+    #[test]
+    fn test_memory_access_past_end_of_ram() {
+        let (mut cpu, mut bus) = setup();
+        cpu.i = 0xFFE;
+        cpu.v_registers[0x1] = 0xFB;
+
+        assert_eq!(
+            cpu.execute(0xF133, &mut bus),
+            Err(EmulationError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+
+        cpu.set_address_policy(AddressPolicy::Wrap);
+        cpu.execute(0xF133, &mut bus).unwrap();
+        assert_eq!(bus.memory()[0xFFE], 2);
+        assert_eq!(bus.memory()[0xFFF], 5);
+        assert_eq!(bus.memory()[0x000], 1);
+    }
+
+    fn setup_superchip() -> (Cpu, Bus) {
+        (
+            Cpu::new(Box::new(MockRng::new(0x54)), Quirks::superchip()),
+            Bus::with_platform(Platform::SuperChip),
+        )
+    }
+
+    #[test]
+    fn test_superchip_opcodes_rejected_on_chip8() {
+        let (mut cpu, mut bus) = setup();
+
+        assert!(matches!(
+            cpu.execute(0x00FF, &mut bus),
+            Err(EmulationError::InvalidOpcode { opcode: 0x00FF, .. })
+        ));
+    }
+
+    #[test]
+    fn test_op_00ff_00fe_resolution() {
+        let (mut cpu, mut bus) = setup_superchip();
+
+        assert!(cpu.execute(0x00FF, &mut bus).unwrap().redraw);
+        assert!(bus.is_hires());
+        assert_eq!(bus.display_width(), 128);
+
+        cpu.execute(0x00FE, &mut bus).unwrap();
+        assert!(!bus.is_hires());
+        assert_eq!(bus.display_width(), 64);
+    }
+
+    #[test]
+    fn test_op_00cn_scroll_down() {
+        let (mut cpu, mut bus) = setup_superchip();
+        bus.write_pixel(5, 5, 1);
+
+        cpu.execute(0x00C2, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(5, 5), 0);
+        assert_eq!(bus.get_pixel(5, 7), 1);
+    }
+
+    #[test]
+    fn test_op_00fb_00fc_scroll_sideways() {
+        let (mut cpu, mut bus) = setup_superchip();
+        bus.write_pixel(5, 5, 1);
+
+        cpu.execute(0x00FB, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(9, 5), 1);
+
+        cpu.execute(0x00FC, &mut bus).unwrap();
+        cpu.execute(0x00FC, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(1, 5), 1);
+    }
+
+    #[test]
+    fn test_op_00fd_exit() {
+        let (mut cpu, mut bus) = setup_superchip();
+        bus.load_rom(&[0x00, 0xFD, 0x12, 0x00]).unwrap();
+
+        cpu.step(&mut bus).unwrap();
+        assert!(cpu.is_halted());
+
+        // A halted CPU doesn't execute anything anymore
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc, ROM_START + 2);
+    }
+
+    #[test]
+    fn test_op_dxy0_draw_16x16() {
+        let (mut cpu, mut bus) = setup_superchip();
+        cpu.execute(0x00FF, &mut bus).unwrap();
+
+        cpu.i = 0x400;
+        for row in 0..16 {
+            bus.write(0x400 + row * 2, 0x80);
+            bus.write(0x400 + row * 2 + 1, 0x01);
+        }
+        cpu.v_registers[0] = 100;
+        cpu.v_registers[1] = 40;
+
+        cpu.execute(0xD010, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(100, 40), 1);
+        assert_eq!(bus.get_pixel(115, 55), 1);
+        assert_eq!(bus.get_pixel(101, 40), 0);
+        assert_eq!(cpu.v_registers[0xF], 0);
+
+        cpu.execute(0xD010, &mut bus).unwrap();
+        assert_eq!(bus.get_pixel(100, 40), 0);
+        assert_eq!(cpu.v_registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_op_fx30_load_big_font() {
+        let (mut cpu, mut bus) = setup_superchip();
+        cpu.v_registers[0x1] = 0x7;
+
+        cpu.execute(0xF130, &mut bus).unwrap();
+        assert_eq!(cpu.i, BIG_FONT_BASE + 70);
+    }
+
+    #[test]
+    fn test_op_fx75_fx85_rpl_flags() {
+        let (mut cpu, mut bus) = setup_superchip();
+        for x in 0..=3 {
+            cpu.v_registers[x] = (x as u8 + 1) * 0x11;
+        }
+
+        cpu.execute(0xF375, &mut bus).unwrap();
+        assert_eq!(&bus.rpl_flags()[..4], &[0x11, 0x22, 0x33, 0x44]);
+
+        cpu.v_registers = [0; 16];
+        cpu.execute(0xF285, &mut bus).unwrap();
+        assert_eq!(&cpu.v_registers[..4], &[0x11, 0x22, 0x33, 0x00]);
+    }
+
+    fn setup_xochip() -> (Cpu, Bus) {
+        (
+            Cpu::new(Box::new(MockRng::new(0x54)), Quirks::xochip()),
+            Bus::with_platform(Platform::XoChip),
+        )
+    }
+
+    #[test]
+    fn test_xochip_opcodes_rejected_on_superchip() {
+        let (mut cpu, mut bus) = setup_superchip();
+
+        assert!(matches!(
+            cpu.execute(0xF201, &mut bus),
+            Err(EmulationError::InvalidOpcode { opcode: 0xF201, .. })
+        ));
+        assert!(matches!(
+            cpu.execute(0xF000, &mut bus),
+            Err(EmulationError::InvalidOpcode { opcode: 0xF000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_op_f000_nnnn_load_i_long() {
+        let (mut cpu, mut bus) = setup_xochip();
+        bus.load_rom(&[0xF0, 0x00, 0xBE, 0xEF]).unwrap();
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.i, 0xBEEF);
+        assert_eq!(cpu.pc, ROM_START + 4);
+    }
+
+    #[test]
+    fn test_skip_over_long_instruction() {
+        let (mut cpu, mut bus) = setup_xochip();
+        bus.load_rom(&[0x30, 0x00, 0xF0, 0x00, 0xBE, 0xEF]).unwrap();
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc, ROM_START + 6);
+    }
+
+    #[test]
+    fn test_op_5xy2_5xy3_register_ranges() {
+        let (mut cpu, mut bus) = setup_xochip();
+        cpu.i = 0x8000;
+        cpu.v_registers[0x2] = 0x22;
+        cpu.v_registers[0x3] = 0x33;
+        cpu.v_registers[0x4] = 0x44;
+
+        cpu.execute(0x5242, &mut bus).unwrap();
+        assert_eq!(&bus.memory()[0x8000..0x8003], &[0x22, 0x33, 0x44]);
+        assert_eq!(cpu.i, 0x8000);
+
+        // loading backwards reverses the order
+        cpu.execute(0x5A83, &mut bus).unwrap();
+        assert_eq!(&cpu.v_registers[0x8..=0xA], &[0x44, 0x33, 0x22]);
+    }
+
+    #[test]
+    fn test_op_fn01_draw_to_planes() {
+        let (mut cpu, mut bus) = setup_xochip();
+        cpu.i = 0x400;
+        bus.write(0x400, 0x80);
+        bus.write(0x401, 0x40);
+
+        cpu.execute(0xF301, &mut bus).unwrap();
+        cpu.execute(0xD011, &mut bus).unwrap();
+
+        // the second plane takes the sprite right after the first one
+        assert_eq!(bus.get_pixel(0, 0), 0b01);
+        assert_eq!(bus.get_pixel(1, 0), 0b10);
+    }
+
+    #[test]
+    fn test_op_f002_fx3a_audio() {
+        let (mut cpu, mut bus) = setup_xochip();
+        cpu.i = 0x400;
+        for offset in 0..16 {
+            bus.write(0x400 + offset, offset as u8);
+        }
+        cpu.v_registers[0x5] = 100;
+
+        cpu.execute(0xF002, &mut bus).unwrap();
+        cpu.execute(0xF53A, &mut bus).unwrap();
+        assert_eq!(bus.audio_pattern()[15], 15);
+        assert_eq!(bus.pitch(), 100);
+    }
+
+    #[test]
+    fn test_runs_assembled_program() {
+        let (mut cpu, mut bus) = setup();
+        let program = crate::asm::assemble(
+            "
+            define COUNT 5
+                    LD V0, 0
+                    LD V1, COUNT
+            loop:   CALL bump
+                    ADD V1, -1
+                    SE V1, 0
+                    JP loop
+            done:   JP done
+
+            bump:   ADD V0, 2
+                    RET
+            ",
+        )
+        .unwrap();
+        bus.load_rom(&program.bytes).unwrap();
+
+        while cpu.pc() != program.symbols["done"] {
+            cpu.step(&mut bus).unwrap();
+        }
+
+        assert_eq!(cpu.v_registers[0x0], 10);
+        assert_eq!(cpu.v_registers[0x1], 0);
+    }
 }
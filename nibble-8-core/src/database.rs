@@ -0,0 +1,499 @@
+//! ROM lookup by SHA-1 in the format of the community CHIP-8 database
+//! (<https://github.com/chip-8/chip-8-database>).
+//!
+//! The database is split into `programs.json`, an array of programs each listing its ROMs by
+//! SHA-1, and `sha1-hashes.json`, which maps every SHA-1 to the index of its program. The bundled
+//! files in `data/` use the same format, so upstream releases can be dropped in as they are.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::{Bus, Cpu, Platform, Quirks, cpu::RngSource, quirks::IndexIncrement};
+
+/// Instructions per frame for ROMs the database has no tickrate for.
+pub const DEFAULT_TICKRATE: u32 = 10;
+/// The most instructions per frame a ROM can be run at, which keeps the rate per second well
+/// within a `u32`.
+pub const MAX_TICKRATE: u32 = 1_000_000;
+
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../data/sha1-hashes.json");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseError {
+    /// The JSON is malformed or doesn't match the database format.
+    Parse(String),
+    /// `sha1-hashes.json` points past the end of `programs.json`.
+    BadIndex { sha1: String, index: usize },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Parse(err) => write!(f, "Invalid ROM database: {}", err),
+            DatabaseError::BadIndex { sha1, index } => {
+                write!(f, "ROM {} refers to missing program {}", sha1, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(err: serde_json::Error) -> Self {
+        DatabaseError::Parse(err.to_string())
+    }
+}
+
+/// How to run a ROM, from its database entry or the defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomProfile {
+    /// `None` if the database doesn't know the ROM.
+    pub title: Option<String>,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// Instructions per frame.
+    pub tickrate: u32,
+    /// Display colors as RGB, background first, then one per bitplane combination. Empty if the
+    /// entry has none.
+    pub colors: Vec<[u8; 3]>,
+    /// What the keypad keys do in the game, e.g. `"up"` to key 5.
+    pub key_hints: BTreeMap<String, u8>,
+}
+
+impl Default for RomProfile {
+    fn default() -> Self {
+        Self {
+            title: None,
+            platform: Platform::default(),
+            quirks: Platform::default().default_quirks(),
+            tickrate: DEFAULT_TICKRATE,
+            colors: Vec::new(),
+            key_hints: BTreeMap::new(),
+        }
+    }
+}
+
+impl RomProfile {
+    /// The profile with the machine settings a user picked taking precedence. A different
+    /// `platform` without `quirks` gets that platform's usual quirks.
+    pub fn with_overrides(
+        mut self,
+        platform: Option<Platform>,
+        quirks: Option<Quirks>,
+        tickrate: Option<u32>,
+    ) -> Self {
+        if let Some(platform) = platform
+            && platform != self.platform
+        {
+            self.platform = platform;
+            self.quirks = platform.default_quirks();
+        }
+        if let Some(quirks) = quirks {
+            self.quirks = quirks;
+        }
+        if let Some(tickrate) = tickrate {
+            self.tickrate = tickrate;
+        }
+        self
+    }
+
+    /// A machine for the profile's platform and quirks with `rom` loaded.
+    pub fn power_on(&self, rom: &[u8], rng: Box<dyn RngSource>) -> Result<(Cpu, Bus), String> {
+        let cpu = Cpu::new(rng, self.quirks);
+        let mut bus = Bus::with_platform(self.platform);
+        bus.load_rom(rom)?;
+        Ok((cpu, bus))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>,
+}
+
+/// A ROM in `programs.json`, also the format of an override.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RomEntry {
+    /// Only used by overrides, database entries take the program's title.
+    title: Option<String>,
+    /// Platform ids in order of preference.
+    platforms: Vec<String>,
+    /// Deviations from a platform's quirks for this ROM.
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    keys: BTreeMap<String, u8>,
+}
+
+impl RomEntry {
+    /// `self` with the fields `other` sets replaced.
+    fn merged(mut self, other: &RomEntry) -> RomEntry {
+        if other.title.is_some() {
+            self.title.clone_from(&other.title);
+        }
+        if !other.platforms.is_empty() {
+            self.platforms.clone_from(&other.platforms);
+        }
+        for (platform, quirks) in &other.quirky_platforms {
+            self.quirky_platforms
+                .insert(platform.clone(), quirks.clone());
+        }
+        self.tickrate = other.tickrate.or(self.tickrate);
+        if other.colors.is_some() {
+            self.colors.clone_from(&other.colors);
+        }
+        self.keys
+            .extend(other.keys.iter().map(|(name, &key)| (name.clone(), key)));
+        self
+    }
+
+    fn profile(&self) -> RomProfile {
+        let mut profile = RomProfile {
+            title: self.title.clone(),
+            key_hints: self.keys.clone(),
+            ..RomProfile::default()
+        };
+
+        if let Some((id, platform, quirks)) = self
+            .platforms
+            .iter()
+            .find_map(|id| platform_preset(id).map(|(platform, quirks)| (id, platform, quirks)))
+        {
+            profile.platform = platform;
+            profile.quirks = match self.quirky_platforms.get(id) {
+                Some(overrides) => overrides.apply(quirks),
+                None => quirks,
+            };
+        }
+        if let Some(tickrate) = self.tickrate {
+            profile.tickrate = tickrate.clamp(1, MAX_TICKRATE);
+        }
+        if let Some(colors) = &self.colors {
+            profile.colors = colors
+                .pixels
+                .iter()
+                .filter_map(|hex| parse_color(hex))
+                .collect();
+        }
+
+        profile
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// Quirk names from the database's `platforms.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct QuirkOverrides {
+    /// `8XY6`/`8XYE` shift VX in place.
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    fn apply(&self, mut quirks: Quirks) -> Quirks {
+        if let Some(shift) = self.shift {
+            quirks.shift_uses_vy = !shift;
+        }
+        if self.memory_leave_i_unchanged == Some(true) {
+            quirks.load_store = IndexIncrement::Unchanged;
+        } else if self.memory_increment_by_x == Some(true) {
+            quirks.load_store = IndexIncrement::ByX;
+        } else if self.memory_leave_i_unchanged.is_some() || self.memory_increment_by_x.is_some() {
+            quirks.load_store = IndexIncrement::ByXPlusOne;
+        }
+        quirks.wrap_sprites = self.wrap.unwrap_or(quirks.wrap_sprites);
+        quirks.jump_uses_vx = self.jump.unwrap_or(quirks.jump_uses_vx);
+        quirks.display_wait = self.vblank.unwrap_or(quirks.display_wait);
+        quirks.vf_reset = self.logic.unwrap_or(quirks.vf_reset);
+        quirks
+    }
+}
+
+/// The machine for a database platform id, `None` for ones that aren't emulated (MEGA-CHIP).
+fn platform_preset(id: &str) -> Option<(Platform, Quirks)> {
+    Some(match id {
+        "originalChip8" | "hybridVIP" => (Platform::Chip8, Quirks::cosmac_vip()),
+        "modernChip8" => (Platform::Chip8, Quirks::modern()),
+        "chip48" => (Platform::Chip8, Quirks::chip48()),
+        "superchip1" | "superchip" => (Platform::SuperChip, Quirks::superchip()),
+        "xochip" => (Platform::XoChip, Quirks::xochip()),
+        _ => return None,
+    })
+}
+
+/// Parses `#RRGGBB`.
+fn parse_color(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    sha1_smol::Sha1::from(data).digest().bytes()
+}
+
+/// Lowercase hex, the way the database writes hashes.
+pub fn sha1_hex(digest: &[u8; 20]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// ROM profiles by SHA-1, with local overrides on top.
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    overrides: HashMap<String, RomEntry>,
+}
+
+impl RomDatabase {
+    /// An empty database, every ROM gets the default profile.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The database shipped in `data/`.
+    pub fn bundled() -> Self {
+        Self::from_json(BUNDLED_PROGRAMS, BUNDLED_HASHES).expect("the bundled database is valid")
+    }
+
+    /// Parses the contents of `programs.json` and `sha1-hashes.json`.
+    pub fn from_json(programs: &str, hashes: &str) -> Result<Self, DatabaseError> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes)?;
+        if let Some((sha1, &index)) = hashes.iter().find(|&(_, &index)| index >= programs.len()) {
+            return Err(DatabaseError::BadIndex {
+                sha1: sha1.clone(),
+                index,
+            });
+        }
+
+        Ok(Self {
+            programs,
+            hashes: hashes
+                .into_iter()
+                .map(|(sha1, index)| (sha1.to_ascii_lowercase(), index))
+                .collect(),
+            overrides: HashMap::new(),
+        })
+    }
+
+    /// Adds overrides from a JSON object that maps SHA-1s to ROM entries as in `programs.json`,
+    /// plus an optional `title`. Only the fields an override sets replace the database's, and it
+    /// may also describe ROMs the database doesn't have. Later overrides win.
+    pub fn load_overrides(&mut self, json: &str) -> Result<(), DatabaseError> {
+        let overrides: HashMap<String, RomEntry> = serde_json::from_str(json)?;
+        for (sha1, entry) in overrides {
+            let sha1 = sha1.to_ascii_lowercase();
+            let merged = match self.overrides.remove(&sha1) {
+                Some(existing) => existing.merged(&entry),
+                None => entry,
+            };
+            self.overrides.insert(sha1, merged);
+        }
+        Ok(())
+    }
+
+    /// Number of programs, not counting overrides.
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    /// The profile of a known ROM.
+    pub fn lookup(&self, sha1: &[u8; 20]) -> Option<RomProfile> {
+        let sha1 = sha1_hex(sha1);
+        let entry = self.hashes.get(&sha1).map(|&index| {
+            let program = &self.programs[index];
+            RomEntry {
+                title: Some(program.title.clone()),
+                ..program.roms.get(&sha1).cloned().unwrap_or_default()
+            }
+        });
+
+        match (entry, self.overrides.get(&sha1)) {
+            (Some(entry), Some(overrides)) => Some(entry.merged(overrides)),
+            (Some(entry), None) => Some(entry),
+            (None, Some(overrides)) => Some(overrides.clone()),
+            (None, None) => None,
+        }
+        .map(|entry| entry.profile())
+    }
+
+    /// The profile of any ROM, the default one if it isn't known.
+    pub fn profile(&self, sha1: &[u8; 20]) -> RomProfile {
+        self.lookup(sha1).unwrap_or_default()
+    }
+
+    /// The profile of the ROM `rom`. The profile picks the platform of the `Bus` the ROM is then
+    /// loaded into, so this hashes the ROM itself rather than going by `Bus::rom_sha1`.
+    pub fn rom_profile(&self, rom: &[u8]) -> RomProfile {
+        self.profile(&sha1(rom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::ThreadRngSource;
+
+    const ROM: &[u8] = &[0x00, 0xE0, 0x12, 0x00];
+
+    fn database() -> RomDatabase {
+        let sha1 = sha1_hex(&sha1(ROM));
+        let programs = format!(
+            r##"[
+                {{ "title": "Other", "roms": {{}} }},
+                {{
+                    "title": "Loop",
+                    "authors": ["Someone"],
+                    "roms": {{
+                        "{sha1}": {{
+                            "file": "loop.ch8",
+                            "platforms": ["megachip8", "superchip", "xochip"],
+                            "quirkyPlatforms": {{
+                                "superchip": {{ "shift": false, "memoryIncrementByX": true }}
+                            }},
+                            "tickrate": 30,
+                            "colors": {{ "pixels": ["#000000", "#FFCC00"] }},
+                            "keys": {{ "up": 5, "a": 6 }}
+                        }}
+                    }}
+                }}
+            ]"##
+        );
+        let hashes = format!(r#"{{ "{}": 1 }}"#, sha1.to_uppercase());
+        RomDatabase::from_json(&programs, &hashes).unwrap()
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            sha1_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut bus = Bus::new();
+        bus.load_rom(ROM).unwrap();
+        let profile = database().lookup(&bus.rom_sha1().unwrap()).unwrap();
+
+        assert_eq!(profile.title.as_deref(), Some("Loop"));
+        // MEGA-CHIP isn't emulated, so the next platform is used
+        assert_eq!(profile.platform, Platform::SuperChip);
+        assert_eq!(
+            profile.quirks,
+            Quirks {
+                shift_uses_vy: true,
+                load_store: IndexIncrement::ByX,
+                ..Quirks::superchip()
+            }
+        );
+        assert_eq!(profile.tickrate, 30);
+        assert_eq!(profile.colors, [[0, 0, 0], [0xFF, 0xCC, 0x00]]);
+        assert_eq!(profile.key_hints["up"], 5);
+    }
+
+    #[test]
+    fn test_unknown_rom_gets_the_default_profile() {
+        let database = database();
+        assert_eq!(database.lookup(&sha1(b"unknown")), None);
+        assert_eq!(database.profile(&sha1(b"unknown")), RomProfile::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut database = database();
+        let known = sha1_hex(&sha1(ROM));
+        let unknown = sha1_hex(&sha1(b"homebrew"));
+        database
+            .load_overrides(&format!(
+                r#"{{
+                    "{known}": {{ "tickrate": 100, "keys": {{ "b": 7 }} }},
+                    "{unknown}": {{ "title": "Homebrew", "platforms": ["originalChip8"] }}
+                }}"#
+            ))
+            .unwrap();
+
+        let profile = database.rom_profile(ROM);
+        assert_eq!(profile.tickrate, 100);
+        assert_eq!(profile.platform, Platform::SuperChip);
+        assert_eq!(profile.key_hints.len(), 3);
+
+        let profile = database.profile(&sha1(b"homebrew"));
+        assert_eq!(profile.title.as_deref(), Some("Homebrew"));
+        assert_eq!(profile.quirks, Quirks::cosmac_vip());
+        assert_eq!(profile.tickrate, DEFAULT_TICKRATE);
+    }
+
+    #[test]
+    fn test_with_overrides() {
+        let profile = RomProfile::default();
+        assert_eq!(profile.clone().with_overrides(None, None, None), profile);
+
+        // Another platform brings its quirks unless they are given too
+        let profile = profile.with_overrides(Some(Platform::SuperChip), None, Some(30));
+        assert_eq!(profile.quirks, Quirks::superchip());
+        assert_eq!(profile.tickrate, 30);
+        let profile = profile.with_overrides(Some(Platform::XoChip), Some(Quirks::modern()), None);
+        assert_eq!(profile.platform, Platform::XoChip);
+        assert_eq!(profile.quirks, Quirks::modern());
+        assert_eq!(profile.tickrate, 30);
+    }
+
+    #[test]
+    fn test_power_on() {
+        let profile = RomProfile::default().with_overrides(Some(Platform::XoChip), None, None);
+        let (cpu, bus) = profile
+            .power_on(ROM, Box::new(ThreadRngSource::new()))
+            .unwrap();
+        assert_eq!(cpu.quirks(), Quirks::xochip());
+        assert_eq!(bus.platform(), Platform::XoChip);
+        assert_eq!(&bus.memory()[0x200..0x204], ROM);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            RomDatabase::from_json("{", "{}"),
+            Err(DatabaseError::Parse(_))
+        ));
+        assert_eq!(
+            RomDatabase::from_json("[]", r#"{ "00": 0 }"#).unwrap_err(),
+            DatabaseError::BadIndex {
+                sha1: "00".to_string(),
+                index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_bundled_database_parses() {
+        RomDatabase::bundled();
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    Bus, Cpu, condition::Condition, decoder::decode, error::EmulationError,
+    instruction::Instruction, memory::WatchHit,
+};
+
+/// Why `Debugger::run` handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The step, step-over, step-out or run-to target was reached.
+    Done,
+    /// PC reached a breakpoint, the instruction there hasn't run yet.
+    Breakpoint(u16),
+    /// The instruction at `pc` triggered one of the `Bus` watchpoints. It has already run.
+    Watchpoint {
+        pc: u16,
+        hit: WatchHit,
+    },
+    /// The ROM executed `exit`.
+    Halted,
+    Fault(EmulationError),
+}
+
+/// What happened during a single `Debugger::run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunOutcome {
+    /// The display buffer changed and should be presented again.
+    pub redraw: bool,
+    /// How many instructions were executed.
+    pub instructions: usize,
+    /// `None` if the instruction budget ran out and the debugger is still running.
+    pub stop: Option<Stop>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Breakpoint {
+    /// Only stop if this holds, see `Condition`.
+    pub condition: Option<Condition>,
+    /// How often PC reached the breakpoint, whether or not it stopped.
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Breakpoint,
+    Step,
+    Pc { pc: u16, sp: Option<u8> },
+    Return { sp: u8 },
+}
+
+/// Drives a `Cpu` with breakpoints and stepping. Watchpoints live on the `Bus`, which sees every
+/// memory access, and are reported here.
+///
+/// A command (`resume`, `step`, `step_over`, ...) only chooses where to stop, `run` then executes
+/// up to a given number of instructions towards it. That way a front end can keep its own frame
+/// loop and tick timers between calls.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    target: Option<Target>,
+    // Resuming from a breakpoint must not stop on it straight away
+    skip_breakpoint: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `false` if there already was a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.contains_key(&addr) {
+            return false;
+        }
+        self.breakpoints.insert(addr, Breakpoint::default());
+        true
+    }
+
+    /// Returns `false` if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    /// Makes the breakpoint at `addr` conditional, or unconditional again with `None`. Returns
+    /// `false` if there is no breakpoint at `addr`.
+    pub fn set_condition(&mut self, addr: u16, condition: Option<Condition>) -> bool {
+        match self.breakpoints.get_mut(&addr) {
+            Some(breakpoint) => {
+                breakpoint.condition = condition;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Breakpoint> {
+        &self.breakpoints
+    }
+
+    // Counts the hit and checks the condition
+    fn should_break(&mut self, cpu: &Cpu, bus: &Bus) -> bool {
+        let Some(breakpoint) = self.breakpoints.get_mut(&cpu.pc()) else {
+            return false;
+        };
+        breakpoint.hits += 1;
+        breakpoint
+            .condition
+            .as_ref()
+            .is_none_or(|condition| condition.evaluate(cpu, bus, breakpoint.hits))
+    }
+
+    /// Whether a command is in progress, i.e. `run` will execute anything.
+    pub fn is_running(&self) -> bool {
+        self.target.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        self.target = None;
+    }
+
+    /// Runs until a breakpoint.
+    pub fn resume(&mut self) {
+        self.start(Target::Breakpoint);
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) {
+        self.start(Target::Step);
+    }
+
+    /// Like `step`, but runs a `Call` until it returns.
+    pub fn step_over(&mut self, cpu: &Cpu, bus: &Bus) {
+        let pc = cpu.pc() as usize;
+        let opcode = bus
+            .memory()
+            .get(pc..pc + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+
+        match opcode.and_then(decode) {
+            Some(Instruction::Call(_)) => self.start(Target::Pc {
+                pc: cpu.pc().wrapping_add(2),
+                sp: Some(cpu.sp()),
+            }),
+            _ => self.start(Target::Step),
+        }
+    }
+
+    /// Runs until the current subroutine returns. Outside of a subroutine this only stops at
+    /// breakpoints.
+    pub fn step_out(&mut self, cpu: &Cpu) {
+        self.start(Target::Return { sp: cpu.sp() });
+    }
+
+    /// Runs until PC reaches `addr`.
+    pub fn run_to(&mut self, addr: u16) {
+        self.start(Target::Pc { pc: addr, sp: None });
+    }
+
+    fn start(&mut self, target: Target) {
+        self.target = Some(target);
+        self.skip_breakpoint = true;
+    }
+
+    fn reached(&self, target: Target, cpu: &Cpu) -> bool {
+        match target {
+            Target::Breakpoint => false,
+            Target::Step => true,
+            Target::Pc { pc, sp } => cpu.pc() == pc && sp.is_none_or(|sp| cpu.sp() == sp),
+            Target::Return { sp } => cpu.sp() < sp,
+        }
+    }
+
+    /// Executes up to `max_instructions` towards the current command's target. The debugger
+    /// pauses whenever it reports a stop.
+    pub fn run(&mut self, cpu: &mut Cpu, bus: &mut Bus, max_instructions: usize) -> RunOutcome {
+        let mut outcome = RunOutcome::default();
+
+        for _ in 0..max_instructions {
+            let Some(target) = self.target else {
+                break;
+            };
+
+            let stop = if cpu.is_halted() {
+                Some(Stop::Halted)
+            } else if !self.skip_breakpoint && self.should_break(cpu, bus) {
+                Some(Stop::Breakpoint(cpu.pc()))
+            } else {
+                self.skip_breakpoint = false;
+                outcome.instructions += 1;
+                let pc = cpu.pc();
+                match cpu.step(bus) {
+                    Ok(step) => {
+                        outcome.redraw |= step.redraw;
+                        match bus.take_watch_hit() {
+                            Some(hit) => Some(Stop::Watchpoint { pc, hit }),
+                            None => self.reached(target, cpu).then_some(Stop::Done),
+                        }
+                    }
+                    Err(err) => Some(Stop::Fault(err)),
+                }
+            };
+
+            if stop.is_some() {
+                self.target = None;
+                outcome.stop = stop;
+                break;
+            }
+        }
+
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Quirks,
+        asm::{Assembly, assemble},
+        cpu::ThreadRngSource,
+        memory::{Access, Watchpoint},
+    };
+
+    fn setup(source: &str) -> (Cpu, Bus, Assembly) {
+        let program = assemble(source).unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        (cpu, bus, program)
+    }
+
+    const PROGRAM: &str = "
+                LD V0, 1
+                CALL sub
+        after:  LD V2, 3
+        done:   JP done
+
+        sub:    LD V1, 2
+                CALL inner
+                RET
+        inner:  RET
+    ";
+
+    #[test]
+    fn test_step() {
+        let (mut cpu, mut bus, _) = setup(PROGRAM);
+        let mut debugger = Debugger::new();
+
+        debugger.step();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(outcome.stop, Some(Stop::Done));
+        assert_eq!(cpu.pc(), 0x202);
+        assert!(!debugger.is_running());
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (mut cpu, mut bus, program) = setup(PROGRAM);
+        let mut debugger = Debugger::new();
+        let sub = program.symbols["sub"];
+        assert!(debugger.add_breakpoint(sub));
+        assert!(!debugger.add_breakpoint(sub));
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+        assert_eq!(outcome.stop, Some(Stop::Breakpoint(sub)));
+        assert_eq!(cpu.v_registers()[0x1], 0);
+
+        // resuming moves past the breakpoint instead of stopping on it again
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+        assert_eq!(outcome.stop, None);
+        assert_eq!(cpu.v_registers()[0x2], 3);
+
+        assert!(debugger.remove_breakpoint(sub));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_step_over() {
+        let (mut cpu, mut bus, program) = setup(PROGRAM);
+        let mut debugger = Debugger::new();
+
+        debugger.step_over(&cpu, &bus);
+        debugger.run(&mut cpu, &mut bus, 100);
+        debugger.step_over(&cpu, &bus);
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(outcome.stop, Some(Stop::Done));
+        assert_eq!(cpu.pc(), program.symbols["after"]);
+        assert_eq!(cpu.v_registers()[0x1], 2);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let (mut cpu, mut bus, program) = setup(PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.run_to(program.symbols["inner"]);
+        debugger.run(&mut cpu, &mut bus, 100);
+        assert_eq!(cpu.stack(), &[0x204, 0x20C]);
+
+        debugger.step_out(&cpu);
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(outcome.stop, Some(Stop::Done));
+        assert_eq!(cpu.pc(), 0x20C);
+        assert_eq!(cpu.sp(), 1);
+    }
+
+    #[test]
+    fn test_run_continues_across_calls() {
+        let (mut cpu, mut bus, program) = setup(PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.run_to(program.symbols["done"]);
+
+        let outcome = debugger.run(&mut cpu, &mut bus, 2);
+        assert_eq!(outcome.stop, None);
+        assert_eq!(outcome.instructions, 2);
+        assert!(debugger.is_running());
+
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+        assert_eq!(outcome.stop, Some(Stop::Done));
+        assert_eq!(cpu.pc(), program.symbols["done"]);
+    }
+
+    #[test]
+    fn test_fault_stops() {
+        let (mut cpu, mut bus, _) = setup("RET");
+        let mut debugger = Debugger::new();
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(
+            outcome.stop,
+            Some(Stop::Fault(EmulationError::StackUnderflow { pc: 0x200 }))
+        );
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let (mut cpu, mut bus, program) = setup(
+            "
+                    LD V0, 0
+            loop:   ADD V0, 1
+                    JP loop
+            ",
+        );
+        let mut debugger = Debugger::new();
+        let addr = program.symbols["loop"];
+        debugger.add_breakpoint(addr);
+        assert!(debugger.set_condition(addr, Some(Condition::parse("V0 == 3").unwrap())));
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(outcome.stop, Some(Stop::Breakpoint(addr)));
+        assert_eq!(cpu.v_registers()[0x0], 3);
+        assert_eq!(debugger.breakpoints()[&addr].hits, 4);
+        assert!(!debugger.set_condition(0x300, None));
+    }
+
+    #[test]
+    fn test_hit_count_breakpoint() {
+        let (mut cpu, mut bus, program) = setup("LD V0, 0\nloop: ADD V0, 1\nJP loop");
+        let mut debugger = Debugger::new();
+        let addr = program.symbols["loop"];
+        debugger.add_breakpoint(addr);
+        debugger.set_condition(addr, Some(Condition::parse("hits == 10").unwrap()));
+
+        debugger.resume();
+        debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(cpu.v_registers()[0x0], 9);
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let (mut cpu, mut bus, _) = setup(
+            "
+                    LD V0, 0x42
+                    LD I, target
+                    LD [I], V0
+            target: db 0, 0
+            ",
+        );
+        bus.add_watchpoint(Watchpoint {
+            start: 0x206,
+            end: 0x207,
+            read: false,
+            write: true,
+            execute: false,
+        });
+        let mut debugger = Debugger::new();
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+
+        assert_eq!(
+            outcome.stop,
+            Some(Stop::Watchpoint {
+                pc: 0x204,
+                hit: WatchHit {
+                    addr: 0x206,
+                    access: Access::Write,
+                    value: 0x42
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_and_execute_watchpoints() {
+        let (mut cpu, mut bus, _) = setup("LD I, 0x300\nLD V0, [I]\nCLS");
+        bus.add_watchpoint(Watchpoint {
+            start: 0x300,
+            end: 0x300,
+            read: true,
+            write: false,
+            execute: false,
+        });
+        bus.add_watchpoint(Watchpoint {
+            start: 0x204,
+            end: 0x205,
+            read: false,
+            write: false,
+            execute: true,
+        });
+        let mut debugger = Debugger::new();
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+        assert!(matches!(
+            outcome.stop,
+            Some(Stop::Watchpoint { pc: 0x202, hit }) if hit.access == Access::Read
+        ));
+
+        debugger.resume();
+        let outcome = debugger.run(&mut cpu, &mut bus, 100);
+        assert!(matches!(
+            outcome.stop,
+            Some(Stop::Watchpoint { pc: 0x204, hit }) if hit.access == Access::Execute
+        ));
+
+        assert!(bus.remove_watchpoint(1).is_some());
+        assert_eq!(bus.watchpoints().len(), 1);
+    }
+}
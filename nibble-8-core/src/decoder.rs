@@ -23,12 +23,36 @@ impl From<u16> for OpcodeComponents {
     }
 }
 
+/// XO-CHIP's `F000 NNNN` is the only instruction that is four bytes long.
+pub fn is_long(opcode: u16) -> bool {
+    opcode == 0xF000
+}
+
+/// Decodes a double-length instruction given its second word, anything else is passed on to
+/// `decode`.
+pub fn decode_long(opcode: u16, operand: u16) -> Option<Instruction> {
+    if is_long(opcode) {
+        Some(Instruction::LoadILong(operand))
+    } else {
+        decode(opcode)
+    }
+}
+
+/// Decodes a single-word instruction. Double-length instructions (see `is_long`) need their
+/// second word and decode to `None` here, use `decode_long` for those.
 pub fn decode(opcode: u16) -> Option<Instruction> {
     let opcode_components = OpcodeComponents::from(opcode);
     match opcode_components.op {
         0x0 => match opcode_components.kk {
+            0x00C0..=0x00CF => Some(Instruction::ScrollDown(opcode_components.n)),
+            0x00D0..=0x00DF => Some(Instruction::ScrollUp(opcode_components.n)),
             0x00E0 => Some(Instruction::Cls),
             0x00EE => Some(Instruction::Ret),
+            0x00FB => Some(Instruction::ScrollRight),
+            0x00FC => Some(Instruction::ScrollLeft),
+            0x00FD => Some(Instruction::Exit),
+            0x00FE => Some(Instruction::LowRes),
+            0x00FF => Some(Instruction::HighRes),
             _ => None,
         },
         0x1 => Some(Instruction::Jump(opcode_components.nnn)),
@@ -46,6 +70,14 @@ pub fn decode(opcode: u16) -> Option<Instruction> {
                 opcode_components.x,
                 opcode_components.y,
             )),
+            0x2 => Some(Instruction::SaveRange(
+                opcode_components.x,
+                opcode_components.y,
+            )),
+            0x3 => Some(Instruction::LoadRange(
+                opcode_components.x,
+                opcode_components.y,
+            )),
             _ => None,
         },
         0x6 => Some(Instruction::Load(opcode_components.x, opcode_components.kk)),
@@ -92,15 +124,21 @@ pub fn decode(opcode: u16) -> Option<Instruction> {
             _ => None,
         },
         0xF => match opcode_components.kk {
+            0x01 => Some(Instruction::SelectPlanes(opcode_components.x)),
+            0x02 if opcode_components.x == 0 => Some(Instruction::LoadAudioPattern),
             0x07 => Some(Instruction::LoadRegFromDelay(opcode_components.x)),
             0x0A => Some(Instruction::WaitForKey(opcode_components.x)),
             0x15 => Some(Instruction::LoadDelayFromReg(opcode_components.x)),
             0x18 => Some(Instruction::LoadSoundFromReg(opcode_components.x)),
             0x1E => Some(Instruction::AddIndex(opcode_components.x)),
             0x29 => Some(Instruction::LoadFont(opcode_components.x)),
+            0x30 => Some(Instruction::LoadBigFont(opcode_components.x)),
             0x33 => Some(Instruction::Bcd(opcode_components.x)),
+            0x3A => Some(Instruction::SetPitch(opcode_components.x)),
             0x55 => Some(Instruction::DumpRegs(opcode_components.x)),
             0x65 => Some(Instruction::FillRegs(opcode_components.x)),
+            0x75 => Some(Instruction::SaveFlags(opcode_components.x)),
+            0x85 => Some(Instruction::LoadFlags(opcode_components.x)),
             _ => None,
         },
         _ => None,
@@ -136,4 +174,40 @@ mod tests {
         let decoded = decode(opcode);
         assert_eq!(decoded, Some(Instruction::Draw(0x1, 0x2, 0x3)));
     }
+
+    #[test]
+    fn test_decode_superchip_opcodes() {
+        assert_eq!(decode(0x00C4), Some(Instruction::ScrollDown(0x4)));
+        assert_eq!(decode(0x00FB), Some(Instruction::ScrollRight));
+        assert_eq!(decode(0x00FC), Some(Instruction::ScrollLeft));
+        assert_eq!(decode(0x00FD), Some(Instruction::Exit));
+        assert_eq!(decode(0x00FE), Some(Instruction::LowRes));
+        assert_eq!(decode(0x00FF), Some(Instruction::HighRes));
+        assert_eq!(decode(0xD120), Some(Instruction::Draw(0x1, 0x2, 0x0)));
+        assert_eq!(decode(0xF330), Some(Instruction::LoadBigFont(0x3)));
+        assert_eq!(decode(0xF775), Some(Instruction::SaveFlags(0x7)));
+        assert_eq!(decode(0xF785), Some(Instruction::LoadFlags(0x7)));
+    }
+
+    #[test]
+    fn test_decode_xochip_opcodes() {
+        assert_eq!(decode(0x00D3), Some(Instruction::ScrollUp(0x3)));
+        assert_eq!(decode(0x5122), Some(Instruction::SaveRange(0x1, 0x2)));
+        assert_eq!(decode(0x5123), Some(Instruction::LoadRange(0x1, 0x2)));
+        assert_eq!(decode(0xF201), Some(Instruction::SelectPlanes(0x2)));
+        assert_eq!(decode(0xF002), Some(Instruction::LoadAudioPattern));
+        assert_eq!(decode(0xF102), None);
+        assert_eq!(decode(0xF43A), Some(Instruction::SetPitch(0x4)));
+    }
+
+    #[test]
+    fn test_decode_long() {
+        assert!(is_long(0xF000));
+        assert_eq!(decode(0xF000), None);
+        assert_eq!(
+            decode_long(0xF000, 0x1234),
+            Some(Instruction::LoadILong(0x1234))
+        );
+        assert_eq!(decode_long(0xA123, 0x1234), Some(Instruction::LoadI(0x123)));
+    }
 }
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::{
+    decoder::{decode, decode_long, is_long},
+    instruction::Instruction,
+    memory::ROM_START,
+    platform::Platform,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Mnemonics from Cowgod's technical reference, `LD V1, 0x12`.
+    #[default]
+    Cowgod,
+    /// Octo assembly, `v1 := 0x12`.
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+/// An instruction rendered in one of the `Syntax`es, with addresses replaced by labels where
+/// known. Created by `Instruction::cowgod`, `Instruction::octo` and `Instruction::format`.
+pub struct Formatted<'a> {
+    instruction: &'a Instruction,
+    syntax: Syntax,
+    labels: Option<&'a BTreeMap<u16, String>>,
+}
+
+impl Instruction {
+    pub fn cowgod(&self) -> Formatted<'_> {
+        self.format(Syntax::Cowgod, None)
+    }
+
+    pub fn octo(&self) -> Formatted<'_> {
+        self.format(Syntax::Octo, None)
+    }
+
+    pub fn format<'a>(
+        &'a self,
+        syntax: Syntax,
+        labels: Option<&'a BTreeMap<u16, String>>,
+    ) -> Formatted<'a> {
+        Formatted {
+            instruction: self,
+            syntax,
+            labels,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cowgod().fmt(f)
+    }
+}
+
+impl Formatted<'_> {
+    fn addr(&self, addr: u16) -> String {
+        match self.labels.and_then(|labels| labels.get(&addr)) {
+            Some(label) => label.clone(),
+            None => format!("{:#05X}", addr),
+        }
+    }
+
+    fn fmt_cowgod(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.instruction {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP {}", self.addr(nnn)),
+            Instruction::Call(nnn) => write!(f, "CALL {}", self.addr(nnn)),
+            Instruction::SkipEq(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SkipNotEq(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::SkipRegEq(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::Add(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LoadReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubReg(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipRegNotEq(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, {}", self.addr(nnn)),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, {}", self.addr(nnn)),
+            Instruction::Rand(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:#X}", x, y, n),
+            Instruction::SkipIfPressed(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadRegFromDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LoadDelayFromReg(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LoadSoundFromReg(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::DumpRegs(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::FillRegs(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::ScrollDown(n) => write!(f, "SCD {:#X}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::LoadBigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::ScrollUp(n) => write!(f, "SCU {:#X}", n),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadILong(nnnn) => write!(f, "LD I, LONG {}", self.addr(nnnn)),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {:#X}", n),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::SetPitch(x) => write!(f, "PITCH V{:X}", x),
+        }
+    }
+
+    fn fmt_octo(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.instruction {
+            Instruction::Cls => write!(f, "clear"),
+            Instruction::Ret => write!(f, "return"),
+            Instruction::Jump(nnn) => write!(f, "jump {}", self.addr(nnn)),
+            Instruction::Call(nnn) => match self.labels.and_then(|labels| labels.get(&nnn)) {
+                Some(label) => write!(f, "{}", label),
+                None => write!(f, ":call {:#05X}", nnn),
+            },
+            // Octo spells skips as the condition under which the next instruction runs
+            Instruction::SkipEq(x, kk) => write!(f, "if v{:x} != {:#04X} then", x, kk),
+            Instruction::SkipNotEq(x, kk) => write!(f, "if v{:x} == {:#04X} then", x, kk),
+            Instruction::SkipRegEq(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+            Instruction::Load(x, kk) => write!(f, "v{:x} := {:#04X}", x, kk),
+            Instruction::Add(x, kk) => write!(f, "v{:x} += {:#04X}", x, kk),
+            Instruction::LoadReg(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+            Instruction::Or(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+            Instruction::And(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+            Instruction::Xor(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+            Instruction::SubReg(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+            Instruction::Shr(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+            Instruction::Subn(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+            Instruction::Shl(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+            Instruction::SkipRegNotEq(x, y) => write!(f, "if v{:x} == v{:x} then", x, y),
+            Instruction::LoadI(nnn) => write!(f, "i := {}", self.addr(nnn)),
+            Instruction::JumpOffset(nnn) => write!(f, "jump0 {}", self.addr(nnn)),
+            Instruction::Rand(x, kk) => write!(f, "v{:x} := random {:#04X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::SkipIfPressed(x) => write!(f, "if v{:x} -key then", x),
+            Instruction::SkipIfNotPressed(x) => write!(f, "if v{:x} key then", x),
+            Instruction::LoadRegFromDelay(x) => write!(f, "v{:x} := delay", x),
+            Instruction::WaitForKey(x) => write!(f, "v{:x} := key", x),
+            Instruction::LoadDelayFromReg(x) => write!(f, "delay := v{:x}", x),
+            Instruction::LoadSoundFromReg(x) => write!(f, "buzzer := v{:x}", x),
+            Instruction::AddIndex(x) => write!(f, "i += v{:x}", x),
+            Instruction::LoadFont(x) => write!(f, "i := hex v{:x}", x),
+            Instruction::Bcd(x) => write!(f, "bcd v{:x}", x),
+            Instruction::DumpRegs(x) => write!(f, "save v{:x}", x),
+            Instruction::FillRegs(x) => write!(f, "load v{:x}", x),
+            Instruction::ScrollDown(n) => write!(f, "scroll-down {}", n),
+            Instruction::ScrollRight => write!(f, "scroll-right"),
+            Instruction::ScrollLeft => write!(f, "scroll-left"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::LowRes => write!(f, "lores"),
+            Instruction::HighRes => write!(f, "hires"),
+            Instruction::LoadBigFont(x) => write!(f, "i := bighex v{:x}", x),
+            Instruction::SaveFlags(x) => write!(f, "saveflags v{:x}", x),
+            Instruction::LoadFlags(x) => write!(f, "loadflags v{:x}", x),
+            Instruction::ScrollUp(n) => write!(f, "scroll-up {}", n),
+            Instruction::SaveRange(x, y) => write!(f, "save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "load v{:x} - v{:x}", x, y),
+            Instruction::LoadILong(nnnn) => write!(f, "i := long {}", self.addr(nnnn)),
+            Instruction::SelectPlanes(n) => write!(f, "plane {}", n),
+            Instruction::LoadAudioPattern => write!(f, "audio"),
+            Instruction::SetPitch(x) => write!(f, "pitch := v{:x}", x),
+        }
+    }
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.syntax {
+            Syntax::Cowgod => self.fmt_cowgod(f),
+            Syntax::Octo => self.fmt_octo(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    Code(Instruction),
+    Data,
+}
+
+/// A run of bytes in the listing, either one instruction or up to `DATA_BYTES_PER_LINE` bytes of
+/// data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+pub const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+/// Decodes the instruction at `addr` in `rom` (which is loaded at `ROM_START`), returns it with
+/// its length in bytes.
+pub fn decode_at(rom: &[u8], addr: u16, platform: Platform) -> Option<(Instruction, u16)> {
+    let word = |addr: u16| -> Option<u16> {
+        let offset = addr.checked_sub(ROM_START)? as usize;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    let opcode = word(addr)?;
+    let (instruction, len) = if is_long(opcode) && platform.has_xochip_opcodes() {
+        (decode_long(opcode, word(addr.checked_add(2)?)?)?, 4)
+    } else {
+        (decode(opcode)?, 2)
+    };
+
+    let supported = (!instruction.is_superchip() || platform.has_superchip_opcodes())
+        && (!instruction.is_xochip() || platform.has_xochip_opcodes());
+    supported.then_some((instruction, len))
+}
+
+/// Recursive-descent disassembly: follows jumps, calls and skips from `ROM_START` and treats
+/// everything that is never reached as data.
+pub fn disassemble(rom: &[u8], platform: Platform) -> Listing {
+    let rom_end = ROM_START as usize + rom.len();
+    let in_rom = |addr: u16| (ROM_START as usize..rom_end).contains(&(addr as usize));
+
+    let mut code = BTreeMap::new();
+    let mut jump_targets = BTreeSet::new();
+    let mut call_targets = BTreeSet::new();
+    let mut data_targets = BTreeSet::new();
+    let mut pending = vec![ROM_START];
+
+    while let Some(mut addr) = pending.pop() {
+        while in_rom(addr) && !code.contains_key(&addr) {
+            let Some((instruction, len)) = decode_at(rom, addr, platform) else {
+                break;
+            };
+            code.insert(addr, (instruction, len));
+            let next = addr.wrapping_add(len);
+
+            match instruction {
+                Instruction::Jump(nnn) => {
+                    jump_targets.insert(nnn);
+                    pending.push(nnn);
+                    break;
+                }
+                Instruction::JumpOffset(nnn) => {
+                    // Usually a jump table, the first entry is the best guess we have
+                    jump_targets.insert(nnn);
+                    pending.push(nnn);
+                    break;
+                }
+                Instruction::Call(nnn) => {
+                    call_targets.insert(nnn);
+                    pending.push(nnn);
+                }
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::SkipEq(..)
+                | Instruction::SkipNotEq(..)
+                | Instruction::SkipRegEq(..)
+                | Instruction::SkipRegNotEq(..)
+                | Instruction::SkipIfPressed(_)
+                | Instruction::SkipIfNotPressed(_) => {
+                    let skipped_len = decode_at(rom, next, platform).map_or(2, |(_, len)| len);
+                    pending.push(next.wrapping_add(skipped_len));
+                }
+                Instruction::LoadI(nnn) | Instruction::LoadILong(nnn) => {
+                    data_targets.insert(nnn);
+                }
+                _ => {}
+            }
+
+            addr = next;
+        }
+    }
+
+    let mut labels = BTreeMap::new();
+    for &addr in data_targets.iter().filter(|&&addr| in_rom(addr)) {
+        labels.insert(addr, format!("data_{:03X}", addr));
+    }
+    for &addr in jump_targets.iter().filter(|&&addr| in_rom(addr)) {
+        labels.insert(addr, format!("label_{:03X}", addr));
+    }
+    for &addr in call_targets.iter().filter(|&&addr| in_rom(addr)) {
+        labels.insert(addr, format!("sub_{:03X}", addr));
+    }
+
+    let mut lines = Vec::new();
+    let mut addr = ROM_START as usize;
+    while addr < rom_end {
+        let offset = addr - ROM_START as usize;
+        if let Some(&(instruction, len)) = code.get(&(addr as u16)) {
+            lines.push(Line {
+                addr: addr as u16,
+                bytes: rom[offset..offset + len as usize].to_vec(),
+                kind: LineKind::Code(instruction),
+            });
+            addr += len as usize;
+            continue;
+        }
+
+        // Data runs until the next instruction or label, or until the line is full
+        let mut end = addr + 1;
+        while end < rom_end
+            && end - addr < DATA_BYTES_PER_LINE
+            && !code.contains_key(&(end as u16))
+            && !labels.contains_key(&(end as u16))
+        {
+            end += 1;
+        }
+        lines.push(Line {
+            addr: addr as u16,
+            bytes: rom[offset..end - ROM_START as usize].to_vec(),
+            kind: LineKind::Data,
+        });
+        addr = end;
+    }
+
+    Listing { lines, labels }
+}
+
+impl Listing {
+    pub fn render(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                match syntax {
+                    Syntax::Cowgod => out.push_str(&format!("{}:\n", label)),
+                    Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+                }
+            }
+
+            let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text = match (&line.kind, syntax) {
+                (LineKind::Code(instruction), _) => {
+                    instruction.format(syntax, Some(&self.labels)).to_string()
+                }
+                (LineKind::Data, Syntax::Cowgod) => {
+                    let bytes: Vec<String> =
+                        line.bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                    format!("DB {}", bytes.join(", "))
+                }
+                (LineKind::Data, Syntax::Octo) => {
+                    let bytes: Vec<String> =
+                        line.bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                    bytes.join(" ")
+                }
+            };
+
+            match syntax {
+                Syntax::Cowgod => out.push_str(&format!(
+                    "    {:<28} ; {:03X}: {}\n",
+                    text,
+                    line.addr,
+                    hex.join(" ")
+                )),
+                Syntax::Octo => out.push_str(&format!(
+                    "\t{:<28} # {:03X}: {}\n",
+                    text,
+                    line.addr,
+                    hex.join(" ")
+                )),
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_cowgod() {
+        assert_eq!(Instruction::Load(0x1, 0x12).to_string(), "LD V1, 0x12");
+        assert_eq!(
+            Instruction::Draw(0xA, 0xB, 0x5).to_string(),
+            "DRW VA, VB, 0x5"
+        );
+        assert_eq!(Instruction::Jump(0x2A4).to_string(), "JP 0x2A4");
+        assert_eq!(Instruction::DumpRegs(0x3).to_string(), "LD [I], V3");
+    }
+
+    #[test]
+    fn test_display_octo() {
+        assert_eq!(
+            Instruction::Load(0x1, 0x12).octo().to_string(),
+            "v1 := 0x12"
+        );
+        assert_eq!(
+            Instruction::SkipEq(0x3, 0x00).octo().to_string(),
+            "if v3 != 0x00 then"
+        );
+        assert_eq!(
+            Instruction::SaveRange(0x2, 0x5).octo().to_string(),
+            "save v2 - v5"
+        );
+        assert_eq!(Instruction::Call(0x300).octo().to_string(), ":call 0x300");
+    }
+
+    #[test]
+    fn test_labels_replace_addresses() {
+        let labels = BTreeMap::from([(0x300, "draw_score".to_string())]);
+
+        let call = Instruction::Call(0x300);
+        assert_eq!(
+            call.format(Syntax::Cowgod, Some(&labels)).to_string(),
+            "CALL draw_score"
+        );
+        assert_eq!(
+            call.format(Syntax::Octo, Some(&labels)).to_string(),
+            "draw_score"
+        );
+    }
+
+    #[test]
+    fn test_recursive_descent_separates_code_and_data() {
+        let rom = [
+            0xA2, 0x0A, // 200: LD I, data_20A
+            0x22, 0x08, // 202: CALL sub_208
+            0x12, 0x04, // 204: JP label_204
+            0xFF, 0xFF, // 206: never reached
+            0x00, 0xEE, // 208: RET
+            0xF0, 0x90, // 20A: sprite data
+        ];
+
+        let listing = disassemble(&rom, Platform::Chip8);
+
+        let kinds: Vec<(u16, bool)> = listing
+            .lines
+            .iter()
+            .map(|line| (line.addr, matches!(line.kind, LineKind::Code(_))))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0x200, true),
+                (0x202, true),
+                (0x204, true),
+                (0x206, false),
+                (0x208, true),
+                (0x20A, false),
+            ]
+        );
+        assert_eq!(listing.labels[&0x204], "label_204");
+        assert_eq!(listing.labels[&0x208], "sub_208");
+        assert_eq!(listing.labels[&0x20A], "data_20A");
+
+        let text = listing.render(Syntax::Cowgod);
+        assert!(text.contains("CALL sub_208"));
+        assert!(text.contains("DB 0xF0, 0x90"));
+    }
+
+    #[test]
+    fn test_skips_follow_both_paths() {
+        let rom = [
+            0x30, 0x00, // 200: SE V0, 0x00
+            0x12, 0x06, // 202: JP 0x206
+            0x00, 0xE0, // 204: CLS
+            0x00, 0xFD, // 206: EXIT
+        ];
+
+        let listing = disassemble(&rom, Platform::SuperChip);
+        assert!(
+            listing
+                .lines
+                .iter()
+                .all(|line| matches!(line.kind, LineKind::Code(_)))
+        );
+    }
+
+    #[test]
+    fn test_long_instructions() {
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x12, 0x04, 0x01, 0x02];
+
+        let listing = disassemble(&rom, Platform::XoChip);
+        assert_eq!(
+            listing.lines[0].kind,
+            LineKind::Code(Instruction::LoadILong(0x206))
+        );
+        assert_eq!(listing.lines[0].bytes.len(), 4);
+        assert!(listing.render(Syntax::Octo).contains("i := long data_206"));
+    }
+}
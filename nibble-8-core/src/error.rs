@@ -0,0 +1,32 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulationError {
+    /// The opcode at `pc` doesn't decode to any known instruction.
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// `Ret` was executed with an empty call stack.
+    StackUnderflow { pc: u16 },
+    /// `Call` was executed with a full call stack.
+    StackOverflow { pc: u16 },
+    /// An instruction tried to access memory past the end of RAM.
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulationError::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            EmulationError::StackUnderflow { pc } => {
+                write!(f, "Stack underflow at {:#05X}", pc)
+            }
+            EmulationError::StackOverflow { pc } => write!(f, "Stack overflow at {:#05X}", pc),
+            EmulationError::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {:#06X}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmulationError {}
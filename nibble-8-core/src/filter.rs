@@ -0,0 +1,254 @@
+//! Post-processing between the display buffer and the screen.
+//!
+//! Programs erase and redraw sprites by XORing them, and when the two land in different frames
+//! the sprite is missing from one of them and flickers. The filters here hide that, each frame is
+//! fed in once per timer tick and the result is a brightness per pixel and plane.
+
+use crate::Bus;
+
+/// Fully lit.
+pub const FULL: u8 = 255;
+/// Used by `FilterMode::from_name` for `decay`.
+pub const DEFAULT_DECAY_FRAMES: u32 = 4;
+/// `DrawSync` presents the display anyway after holding it back this many frames, for programs
+/// that never wait.
+const MAX_HELD_FRAMES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    /// The display as it is at the end of every frame.
+    #[default]
+    Off,
+    /// Pixels light up at once and fade out over `frames` frames, like the phosphor of a CRT.
+    Decay { frames: u32 },
+    /// A pixel is lit if it was lit at the end of this frame or the previous one.
+    Deflicker,
+    /// The display is only presented once a draw has been followed by a wait, when the program
+    /// has likely finished the frame. See `TickOutcome::settled`.
+    DrawSync,
+}
+
+impl FilterMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" | "none" => Some(FilterMode::Off),
+            "decay" => Some(FilterMode::Decay {
+                frames: DEFAULT_DECAY_FRAMES,
+            }),
+            "deflicker" => Some(FilterMode::Deflicker),
+            "sync" | "drawsync" => Some(FilterMode::DrawSync),
+            _ => None,
+        }
+    }
+}
+
+pub struct DisplayFilter {
+    mode: FilterMode,
+    width: usize,
+    /// The display at the end of the previous frame.
+    previous: Vec<u8>,
+    /// Brightness of plane 1 and plane 2 per pixel.
+    output: Vec<[u8; 2]>,
+    /// Frames `DrawSync` has held the display back for.
+    held_frames: u32,
+}
+
+impl DisplayFilter {
+    pub fn new(mode: FilterMode) -> Self {
+        Self {
+            mode,
+            width: 0,
+            previous: Vec::new(),
+            output: Vec::new(),
+            held_frames: 0,
+        }
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+        self.held_frames = 0;
+    }
+
+    /// Starts over from the display as it is, after loading a state for example.
+    pub fn reset(&mut self, bus: &Bus) {
+        let display = bus.display_buffer();
+        self.width = bus.display_width();
+        self.previous = display.to_vec();
+        self.output = display.iter().map(|&planes| lit(planes)).collect();
+        self.held_frames = 0;
+    }
+
+    /// Feeds in the display at the end of a frame, `settled` as reported by the tick. Returns
+    /// whether the output changed.
+    pub fn push(&mut self, bus: &Bus, settled: bool) -> bool {
+        let display = bus.display_buffer();
+        if bus.display_width() != self.width || display.len() != self.output.len() {
+            self.reset(bus);
+            return true;
+        }
+
+        let mut changed = false;
+        let mut update = |output: &mut [u8; 2], new: [u8; 2]| {
+            changed |= *output != new;
+            *output = new;
+        };
+        match self.mode {
+            FilterMode::Off => {
+                for (output, &planes) in self.output.iter_mut().zip(display) {
+                    update(output, lit(planes));
+                }
+            }
+            FilterMode::Decay { frames } => {
+                let fade = FULL.div_ceil(frames.clamp(1, FULL as u32) as u8);
+                for (output, &planes) in self.output.iter_mut().zip(display) {
+                    let [plane1, plane2] = lit(planes);
+                    let new = [
+                        plane1.max(output[0].saturating_sub(fade)),
+                        plane2.max(output[1].saturating_sub(fade)),
+                    ];
+                    update(output, new);
+                }
+            }
+            FilterMode::Deflicker => {
+                for ((output, &planes), &previous) in
+                    self.output.iter_mut().zip(display).zip(&self.previous)
+                {
+                    update(output, lit(planes | previous));
+                }
+            }
+            FilterMode::DrawSync => {
+                if settled || self.held_frames >= MAX_HELD_FRAMES {
+                    self.held_frames = 0;
+                    for (output, &planes) in self.output.iter_mut().zip(display) {
+                        update(output, lit(planes));
+                    }
+                } else {
+                    self.held_frames += 1;
+                }
+            }
+        }
+        self.previous.copy_from_slice(display);
+        changed
+    }
+
+    /// Brightness of plane 1 and plane 2 per pixel, row by row.
+    pub fn output(&self) -> &[[u8; 2]] {
+        &self.output
+    }
+
+    /// Pixels per row of the output.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+fn lit(planes: u8) -> [u8; 2] {
+    let brightness = |plane: u8| if planes & plane != 0 { FULL } else { 0 };
+    [brightness(0b01), brightness(0b10)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Platform;
+
+    fn filter(mode: FilterMode) -> (DisplayFilter, Bus) {
+        let bus = Bus::with_platform(Platform::XoChip);
+        let mut filter = DisplayFilter::new(mode);
+        filter.reset(&bus);
+        (filter, bus)
+    }
+
+    fn pixel(filter: &DisplayFilter, x: usize, y: usize) -> [u8; 2] {
+        filter.output()[y * filter.width() + x]
+    }
+
+    #[test]
+    fn test_off() {
+        let (mut filter, mut bus) = filter(FilterMode::Off);
+        bus.write_pixel(1, 2, 0b11);
+        assert!(filter.push(&bus, false));
+        assert_eq!(pixel(&filter, 1, 2), [FULL, FULL]);
+        assert!(!filter.push(&bus, false));
+
+        bus.write_pixel(1, 2, 0b01);
+        assert!(filter.push(&bus, false));
+        assert_eq!(pixel(&filter, 1, 2), [0, FULL]);
+    }
+
+    #[test]
+    fn test_decay() {
+        let (mut filter, mut bus) = filter(FilterMode::Decay { frames: 4 });
+        bus.write_pixel(0, 0, 0b01);
+        filter.push(&bus, false);
+        assert_eq!(pixel(&filter, 0, 0), [FULL, 0]);
+
+        bus.write_pixel(0, 0, 0b01);
+        let brightness: Vec<u8> = (0..5)
+            .map(|_| {
+                filter.push(&bus, false);
+                pixel(&filter, 0, 0)[0]
+            })
+            .collect();
+        assert_eq!(brightness, [191, 127, 63, 0, 0]);
+
+        // Lighting up again is immediate
+        bus.write_pixel(0, 0, 0b01);
+        filter.push(&bus, false);
+        assert_eq!(pixel(&filter, 0, 0), [FULL, 0]);
+    }
+
+    #[test]
+    fn test_deflicker() {
+        let (mut filter, mut bus) = filter(FilterMode::Deflicker);
+        // A sprite erased in one frame and drawn again in the next stays visible
+        bus.write_pixel(3, 3, 0b10);
+        filter.push(&bus, false);
+        bus.write_pixel(3, 3, 0b10);
+        filter.push(&bus, false);
+        assert_eq!(pixel(&filter, 3, 3), [0, FULL]);
+        bus.write_pixel(3, 3, 0b10);
+        filter.push(&bus, false);
+        assert_eq!(pixel(&filter, 3, 3), [0, FULL]);
+
+        // Gone once it stays off for two frames
+        bus.write_pixel(3, 3, 0b10);
+        filter.push(&bus, false);
+        filter.push(&bus, false);
+        assert_eq!(pixel(&filter, 3, 3), [0, 0]);
+    }
+
+    #[test]
+    fn test_draw_sync() {
+        let (mut filter, mut bus) = filter(FilterMode::DrawSync);
+        bus.write_pixel(0, 0, 0b01);
+        assert!(!filter.push(&bus, false));
+        assert_eq!(pixel(&filter, 0, 0), [0, 0]);
+        assert!(filter.push(&bus, true));
+        assert_eq!(pixel(&filter, 0, 0), [FULL, 0]);
+
+        // Shown anyway when the program never waits
+        bus.write_pixel(0, 0, 0b01);
+        for _ in 0..MAX_HELD_FRAMES {
+            assert!(!filter.push(&bus, false));
+        }
+        assert!(filter.push(&bus, false));
+        assert_eq!(pixel(&filter, 0, 0), [0, 0]);
+    }
+
+    #[test]
+    fn test_resolution_change_resets() {
+        let (mut filter, mut bus) = filter(FilterMode::Deflicker);
+        bus.write_pixel(0, 0, 0b01);
+        filter.push(&bus, false);
+
+        bus.set_hires(true);
+        assert!(filter.push(&bus, false));
+        assert_eq!(filter.width(), 128);
+        assert_eq!(pixel(&filter, 0, 0), [0, 0]);
+    }
+}
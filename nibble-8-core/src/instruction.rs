@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     Cls,
     Ret,
@@ -34,4 +34,175 @@ pub enum Instruction {
     Bcd(u8),
     DumpRegs(u8),
     FillRegs(u8),
+    // SUPER-CHIP 1.1
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    LoadBigFont(u8),
+    SaveFlags(u8),
+    LoadFlags(u8),
+    // XO-CHIP
+    ScrollUp(u8),
+    SaveRange(u8, u8),
+    LoadRange(u8, u8),
+    LoadILong(u16),
+    SelectPlanes(u8),
+    LoadAudioPattern,
+    SetPitch(u8),
+}
+
+impl Instruction {
+    /// Encodes the instruction back into big-endian opcode bytes, four for `LoadILong` and
+    /// two for everything else.
+    pub fn encode(&self) -> Vec<u8> {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: u8, kk: u8| op | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, kk: u8| xkk(0xF000, x, kk);
+
+        let opcode = match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipEq(x, kk) => xkk(0x3000, x, kk),
+            Instruction::SkipNotEq(x, kk) => xkk(0x4000, x, kk),
+            Instruction::SkipRegEq(x, y) => xy(0x5000, x, y, 0x0),
+            Instruction::Load(x, kk) => xkk(0x6000, x, kk),
+            Instruction::Add(x, kk) => xkk(0x7000, x, kk),
+            Instruction::LoadReg(x, y) => xy(0x8000, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8000, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8000, x, y, 0x2),
+            Instruction::Xor(x, y) => xy(0x8000, x, y, 0x3),
+            Instruction::AddReg(x, y) => xy(0x8000, x, y, 0x4),
+            Instruction::SubReg(x, y) => xy(0x8000, x, y, 0x5),
+            Instruction::Shr(x, y) => xy(0x8000, x, y, 0x6),
+            Instruction::Subn(x, y) => xy(0x8000, x, y, 0x7),
+            Instruction::Shl(x, y) => xy(0x8000, x, y, 0xE),
+            Instruction::SkipRegNotEq(x, y) => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI(nnn) => 0xA000 | nnn,
+            Instruction::JumpOffset(nnn) => 0xB000 | nnn,
+            Instruction::Rand(x, kk) => xkk(0xC000, x, kk),
+            Instruction::Draw(x, y, n) => xy(0xD000, x, y, n as u16),
+            Instruction::SkipIfPressed(x) => xkk(0xE000, x, 0x9E),
+            Instruction::SkipIfNotPressed(x) => xkk(0xE000, x, 0xA1),
+            Instruction::LoadRegFromDelay(x) => fx(x, 0x07),
+            Instruction::WaitForKey(x) => fx(x, 0x0A),
+            Instruction::LoadDelayFromReg(x) => fx(x, 0x15),
+            Instruction::LoadSoundFromReg(x) => fx(x, 0x18),
+            Instruction::AddIndex(x) => fx(x, 0x1E),
+            Instruction::LoadFont(x) => fx(x, 0x29),
+            Instruction::Bcd(x) => fx(x, 0x33),
+            Instruction::DumpRegs(x) => fx(x, 0x55),
+            Instruction::FillRegs(x) => fx(x, 0x65),
+            Instruction::ScrollDown(n) => 0x00C0 | n as u16,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::LoadBigFont(x) => fx(x, 0x30),
+            Instruction::SaveFlags(x) => fx(x, 0x75),
+            Instruction::LoadFlags(x) => fx(x, 0x85),
+            Instruction::ScrollUp(n) => 0x00D0 | n as u16,
+            Instruction::SaveRange(x, y) => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xy(0x5000, x, y, 0x3),
+            Instruction::LoadILong(nnnn) => {
+                return [0xF0, 0x00, (nnnn >> 8) as u8, nnnn as u8].to_vec();
+            }
+            Instruction::SelectPlanes(n) => fx(n, 0x01),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::SetPitch(x) => fx(x, 0x3A),
+        };
+
+        opcode.to_be_bytes().to_vec()
+    }
+
+    /// The variant's name, e.g. `"Draw"` for `Draw(x, y, n)`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Cls => "Cls",
+            Instruction::Ret => "Ret",
+            Instruction::Jump(_) => "Jump",
+            Instruction::Call(_) => "Call",
+            Instruction::SkipEq(_, _) => "SkipEq",
+            Instruction::SkipNotEq(_, _) => "SkipNotEq",
+            Instruction::SkipRegEq(_, _) => "SkipRegEq",
+            Instruction::Load(_, _) => "Load",
+            Instruction::Add(_, _) => "Add",
+            Instruction::LoadReg(_, _) => "LoadReg",
+            Instruction::Or(_, _) => "Or",
+            Instruction::And(_, _) => "And",
+            Instruction::Xor(_, _) => "Xor",
+            Instruction::AddReg(_, _) => "AddReg",
+            Instruction::SubReg(_, _) => "SubReg",
+            Instruction::Shr(_, _) => "Shr",
+            Instruction::Subn(_, _) => "Subn",
+            Instruction::Shl(_, _) => "Shl",
+            Instruction::SkipRegNotEq(_, _) => "SkipRegNotEq",
+            Instruction::LoadI(_) => "LoadI",
+            Instruction::JumpOffset(_) => "JumpOffset",
+            Instruction::Rand(_, _) => "Rand",
+            Instruction::Draw(_, _, _) => "Draw",
+            Instruction::SkipIfPressed(_) => "SkipIfPressed",
+            Instruction::SkipIfNotPressed(_) => "SkipIfNotPressed",
+            Instruction::LoadRegFromDelay(_) => "LoadRegFromDelay",
+            Instruction::WaitForKey(_) => "WaitForKey",
+            Instruction::LoadDelayFromReg(_) => "LoadDelayFromReg",
+            Instruction::LoadSoundFromReg(_) => "LoadSoundFromReg",
+            Instruction::AddIndex(_) => "AddIndex",
+            Instruction::LoadFont(_) => "LoadFont",
+            Instruction::Bcd(_) => "Bcd",
+            Instruction::DumpRegs(_) => "DumpRegs",
+            Instruction::FillRegs(_) => "FillRegs",
+            Instruction::ScrollDown(_) => "ScrollDown",
+            Instruction::ScrollRight => "ScrollRight",
+            Instruction::ScrollLeft => "ScrollLeft",
+            Instruction::Exit => "Exit",
+            Instruction::LowRes => "LowRes",
+            Instruction::HighRes => "HighRes",
+            Instruction::LoadBigFont(_) => "LoadBigFont",
+            Instruction::SaveFlags(_) => "SaveFlags",
+            Instruction::LoadFlags(_) => "LoadFlags",
+            Instruction::ScrollUp(_) => "ScrollUp",
+            Instruction::SaveRange(_, _) => "SaveRange",
+            Instruction::LoadRange(_, _) => "LoadRange",
+            Instruction::LoadILong(_) => "LoadILong",
+            Instruction::SelectPlanes(_) => "SelectPlanes",
+            Instruction::LoadAudioPattern => "LoadAudioPattern",
+            Instruction::SetPitch(_) => "SetPitch",
+        }
+    }
+
+    /// Whether the instruction was added by SUPER-CHIP 1.1.
+    pub fn is_superchip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Exit
+                | Instruction::LowRes
+                | Instruction::HighRes
+                | Instruction::LoadBigFont(_)
+                | Instruction::SaveFlags(_)
+                | Instruction::LoadFlags(_)
+        )
+    }
+
+    /// Whether the instruction was added by XO-CHIP.
+    pub fn is_xochip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp(_)
+                | Instruction::SaveRange(_, _)
+                | Instruction::LoadRange(_, _)
+                | Instruction::LoadILong(_)
+                | Instruction::SelectPlanes(_)
+                | Instruction::LoadAudioPattern
+                | Instruction::SetPitch(_)
+        )
+    }
 }
@@ -1,7 +1,32 @@
+pub mod args;
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod condition;
 pub mod cpu;
-mod decoder;
-mod instruction;
+pub mod database;
+pub mod debugger;
+pub mod decoder;
+pub mod disasm;
+pub mod error;
+pub mod filter;
+pub mod instruction;
 pub mod memory;
+pub mod movie;
+pub mod platform;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod scheduler;
+pub mod state;
+pub mod timing;
+#[cfg(feature = "trace")]
+pub mod trace;
 
-pub use cpu::Cpu;
+pub use cpu::{AddressPolicy, Cpu, StepOutcome};
+pub use error::EmulationError;
+pub use instruction::Instruction;
 pub use memory::Bus;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use state::MachineState;
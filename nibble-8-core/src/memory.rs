@@ -1,9 +1,22 @@
+#[cfg(feature = "trace")]
+use crate::trace::{DisplayWrite, MemoryWrite, WriteLog};
+use crate::{database, platform::Platform, state::BusState};
+use std::{fs, io, path::Path};
+
 pub const RAM_SIZE: u16 = 4096;
+pub const XO_RAM_SIZE: usize = 0x10000;
 pub const FONT_BASE: u16 = 0x050;
+pub const BIG_FONT_BASE: u16 = 0x0A0;
 pub const ROM_START: u16 = 0x200;
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 pub const KEY_COUNT: usize = 16;
+pub const RPL_FLAG_COUNT: usize = 16;
+pub const PLANE_COUNT: usize = 2;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
 
 // 5x16
 pub const FONTSET: [u8; 80] = [
@@ -25,8 +38,78 @@ pub const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// 10x16, SUPER-CHIP only shipped 0-9 but A-F are commonly provided too
+pub const BIG_FONTSET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// Sized for hi-res, in lo-res only the first SCREEN_WIDTH * SCREEN_HEIGHT cells are used.
+// Every cell is a bitmask of the planes that are lit, bit 0 is plane 1.
 struct Display {
-    display_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    display_buffer: [u8; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+    hires: bool,
+    planes: u8,
+}
+
+impl Display {
+    fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    // Moves the selected planes by (dx, dy), pixels scrolled in from the edge are blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let planes = self.planes;
+        let old = self.display_buffer;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let src = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    old[(src_y * width + src_x) as usize] & planes
+                } else {
+                    0
+                };
+
+                let index = (y * width + x) as usize;
+                self.display_buffer[index] = (self.display_buffer[index] & !planes) | src;
+            }
+        }
+    }
+}
+
+// XO-CHIP audio registers
+struct Audio {
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
 }
 
 struct Keypad {
@@ -49,56 +132,356 @@ impl Keypad {
     }
 }
 
+/// The kind of memory access a `Watchpoint` reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Fetching an instruction.
+    Execute,
+}
+
+/// Watches an inclusive range of addresses for the enabled kinds of access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: usize, access: Access) -> bool {
+        let enabled = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        enabled && (self.start..=self.end).contains(&addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: usize,
+    pub access: Access,
+    /// The byte that was read or fetched, or the one that was written.
+    pub value: u8,
+}
+
 pub struct Bus {
-    pub memory: [u8; RAM_SIZE as usize],
+    memory: Vec<u8>,
     display: Display,
+    audio: Audio,
     keypad: Keypad,
+    platform: Platform,
+    rpl_flags: [u8; RPL_FLAG_COUNT],
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    rom_sha1: Option<[u8; 20]>,
+    #[cfg(feature = "trace")]
+    write_log: Option<WriteLog>,
 }
 
 impl Bus {
     pub fn new() -> Self {
+        Self::with_platform(Platform::Chip8)
+    }
+
+    pub fn with_platform(platform: Platform) -> Self {
         let mut bus = Self {
-            memory: [0; RAM_SIZE as usize],
+            memory: vec![0; platform.memory_size()],
             display: Display {
-                display_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+                display_buffer: [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+                hires: false,
+                planes: 0b01,
+            },
+            audio: Audio {
+                pattern: [0; AUDIO_PATTERN_SIZE],
+                pitch: DEFAULT_PITCH,
             },
             keypad: Keypad::new(),
+            platform,
+            rpl_flags: [0; RPL_FLAG_COUNT],
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            rom_sha1: None,
+            #[cfg(feature = "trace")]
+            write_log: None,
         };
 
         for (i, &byte) in FONTSET.iter().enumerate() {
             bus.memory[FONT_BASE as usize + i] = byte;
         }
 
+        if platform.has_superchip_opcodes() {
+            for (i, &byte) in BIG_FONTSET.iter().enumerate() {
+                bus.memory[BIG_FONT_BASE as usize + i] = byte;
+            }
+        }
+
         bus
     }
 
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn snapshot(&self) -> BusState {
+        BusState {
+            platform: self.platform,
+            memory: self.memory.clone(),
+            display: self.display.display_buffer.to_vec(),
+            hires: self.display.hires,
+            planes: self.display.planes,
+            keys: self.keypad.keys,
+            rpl_flags: self.rpl_flags,
+            audio_pattern: self.audio.pattern,
+            pitch: self.audio.pitch,
+        }
+    }
+
+    /// Restores a snapshot, including its platform.
+    pub fn restore(&mut self, state: &BusState) {
+        self.platform = state.platform;
+        self.memory.clone_from(&state.memory);
+        self.display.display_buffer.fill(0);
+        for (pixel, &value) in self.display.display_buffer.iter_mut().zip(&state.display) {
+            *pixel = value;
+        }
+        self.display.hires = state.hires;
+        self.display.planes = state.planes;
+        self.keypad.keys = state.keys;
+        self.rpl_flags = state.rpl_flags;
+        self.audio.pattern = state.audio_pattern;
+        self.audio.pitch = state.pitch;
+    }
+
+    /// All of RAM, for inspection. Unlike `read` this doesn't trigger watchpoints.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Reads a byte on behalf of the ROM. `addr` must be within `memory()`.
+    pub fn read(&mut self, addr: usize) -> u8 {
+        let value = self.memory[addr];
+        self.watch(addr, Access::Read, value);
+        value
+    }
+
+    /// Like `read`, but for fetching instructions.
+    pub fn fetch(&mut self, addr: usize) -> u8 {
+        let value = self.memory[addr];
+        self.watch(addr, Access::Execute, value);
+        value
+    }
+
+    /// Writes a byte on behalf of the ROM. `addr` must be within `memory()`.
+    pub fn write(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.watch(addr, Access::Write, value);
+        #[cfg(feature = "trace")]
+        if let Some(log) = &mut self.write_log {
+            log.memory.push(MemoryWrite {
+                addr: addr as u16,
+                value,
+            });
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn start_write_log(&mut self) {
+        self.write_log = Some(WriteLog::default());
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn take_write_log(&mut self) -> WriteLog {
+        self.write_log.take().unwrap_or_default()
+    }
+
+    #[cfg(feature = "trace")]
+    fn log_display_write(&mut self, write: DisplayWrite) {
+        if let Some(log) = &mut self.write_log {
+            log.display.push(write);
+        }
+    }
+
+    fn watch(&mut self, addr: usize, access: Access, value: u8) {
+        if self.watch_hit.is_none()
+            && self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.matches(addr, access))
+        {
+            self.watch_hit = Some(WatchHit {
+                addr,
+                access,
+                value,
+            });
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Returns and clears the first watchpoint hit since the last call.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
     pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
-        let available_space = RAM_SIZE - ROM_START;
-        if rom.len() > available_space as usize {
+        let available_space = self.memory.len() - ROM_START as usize;
+        if rom.len() > available_space {
             return Err("The ROM is too big".to_string());
         }
 
         self.memory[ROM_START as usize..ROM_START as usize + rom.len()].copy_from_slice(rom);
+        self.rom_sha1 = Some(database::sha1(rom));
 
         Ok(())
     }
 
-    pub fn write_pixel(&mut self, x: u8, y: u8, value: u8) -> bool {
-        let index = (y as usize * SCREEN_WIDTH) + x as usize;
+    /// SHA-1 of the ROM last passed to `load_rom`, what `RomDatabase` and per-ROM settings are
+    /// keyed by.
+    pub fn rom_sha1(&self) -> Option<[u8; 20]> {
+        self.rom_sha1
+    }
+
+    /// XORs `value` (a plane bitmask) into the pixel, returns whether a lit pixel was turned off.
+    pub fn write_pixel(&mut self, x: usize, y: usize, value: u8) -> bool {
+        let index = (y * self.display.width()) + x;
         let old_pixel = self.display.display_buffer[index];
 
         self.display.display_buffer[index] ^= value;
+        #[cfg(feature = "trace")]
+        self.log_display_write(DisplayWrite::Pixel {
+            x: x as u8,
+            y: y as u8,
+            value: self.display.display_buffer[index],
+        });
 
-        old_pixel == 1 && self.display.display_buffer[index] == 0
+        old_pixel & value != 0
     }
 
     pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
-        let index = (y * SCREEN_WIDTH) + x;
+        let index = (y * self.display.width()) + x;
         self.display.display_buffer[index]
     }
 
+    /// The pixels of the current resolution row by row, each a bitmask of the lit planes.
+    pub fn display_buffer(&self) -> &[u8] {
+        &self.display.display_buffer[..self.display.width() * self.display.height()]
+    }
+
+    /// Clears the selected planes.
     pub fn clear_display(&mut self) {
+        let planes = self.display.planes;
+        for pixel in self.display.display_buffer.iter_mut() {
+            *pixel &= !planes;
+        }
+        #[cfg(feature = "trace")]
+        self.log_display_write(DisplayWrite::Clear { planes });
+    }
+
+    /// The planes that drawing, clearing and scrolling act on (XO-CHIP `FN01`).
+    pub fn selected_planes(&self) -> u8 {
+        self.display.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.display.planes = planes & ((1 << PLANE_COUNT) - 1);
+    }
+
+    /// Width of the display in the current resolution.
+    pub fn display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    /// Height of the display in the current resolution.
+    pub fn display_height(&self) -> usize {
+        self.display.height()
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.display.hires
+    }
+
+    /// Switches between 64x32 and 128x64. The display is cleared, like most SUPER-CHIP
+    /// interpreters do.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.display.hires = hires;
         self.display.display_buffer.fill(0);
+        #[cfg(feature = "trace")]
+        self.log_display_write(DisplayWrite::Resolution { hires });
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.display.scroll(dx, dy);
+        #[cfg(feature = "trace")]
+        self.log_display_write(DisplayWrite::Scroll {
+            dx: dx as i8,
+            dy: dy as i8,
+        });
+    }
+
+    /// The 1-bit, 128 sample XO-CHIP audio pattern loaded by `F002`.
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio.pattern
+    }
+
+    pub fn set_audio_pattern(&mut self, pattern: &[u8]) {
+        let len = pattern.len().min(AUDIO_PATTERN_SIZE);
+        self.audio.pattern[..len].copy_from_slice(&pattern[..len]);
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.audio.pitch
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.audio.pitch = pitch;
+    }
+
+    /// Rate at which the audio pattern is played back, in bits per second.
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.audio.pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// The HP-48 RPL user flags written by `FX75`. Frontends persist these between runs with
+    /// `save_rpl_flags` and `load_rpl_flags`.
+    pub fn rpl_flags(&self) -> &[u8; RPL_FLAG_COUNT] {
+        &self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: &[u8]) {
+        let len = flags.len().min(RPL_FLAG_COUNT);
+        self.rpl_flags[..len].copy_from_slice(&flags[..len]);
     }
 
     pub fn is_key_pressed(&self, key: u8) -> bool {
@@ -116,20 +499,64 @@ impl Default for Bus {
     }
 }
 
+/// The file next to a ROM that keeps its RPL flags between runs.
+pub fn rpl_flags_path(rom_path: &str) -> String {
+    format!("{}.rpl", rom_path)
+}
+
+/// Reads flags written by `save_rpl_flags`. Without a file every flag is clear, like on a
+/// calculator that never ran the ROM.
+pub fn load_rpl_flags(path: impl AsRef<Path>) -> io::Result<[u8; RPL_FLAG_COUNT]> {
+    let mut flags = [0; RPL_FLAG_COUNT];
+    match fs::read(path) {
+        Ok(saved) => {
+            let len = saved.len().min(RPL_FLAG_COUNT);
+            flags[..len].copy_from_slice(&saved[..len]);
+            Ok(flags)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(flags),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_rpl_flags(path: impl AsRef<Path>, flags: &[u8; RPL_FLAG_COUNT]) -> io::Result<()> {
+    fs::write(path, flags)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_rpl_flags_file() {
+        let dir = std::env::temp_dir().join(format!("nibble-8-rpl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = rpl_flags_path(dir.join("game.ch8").to_str().unwrap());
+        assert!(path.ends_with("game.ch8.rpl"));
+
+        assert_eq!(load_rpl_flags(&path).unwrap(), [0; RPL_FLAG_COUNT]);
+        let mut bus = Bus::new();
+        bus.set_rpl_flags(&[1, 2, 3]);
+        save_rpl_flags(&path, bus.rpl_flags()).unwrap();
+        let flags = load_rpl_flags(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // A new machine picks up where the last one left off
+        let mut bus = Bus::new();
+        bus.set_rpl_flags(&flags);
+        assert_eq!(&bus.rpl_flags()[..4], &[1, 2, 3, 0]);
+    }
+
     #[test]
     fn test_fontset_is_loaded() {
         let bus = Bus::new();
 
         // The first byte of the FONTSET (for '0') should be 0xF0
-        assert_eq!(bus.memory[FONT_BASE as usize], 0xF0);
+        assert_eq!(bus.memory()[FONT_BASE as usize], 0xF0);
 
         // The last byte (for 'F') should be 0x80
         let last_idx = FONT_BASE as usize + FONTSET.len() - 1;
-        assert_eq!(bus.memory[last_idx], 0x80);
+        assert_eq!(bus.memory()[last_idx], 0x80);
     }
 
     #[test]
@@ -158,4 +585,106 @@ mod tests {
 
     #[test]
     fn test_pixel_write_correctly() {}
+
+    #[test]
+    fn test_big_fontset_is_loaded_for_superchip() {
+        let bus = Bus::with_platform(Platform::SuperChip);
+        assert_eq!(
+            &bus.memory[BIG_FONT_BASE as usize..BIG_FONT_BASE as usize + BIG_FONTSET.len()],
+            BIG_FONTSET
+        );
+
+        let bus = Bus::new();
+        assert_eq!(bus.memory()[BIG_FONT_BASE as usize], 0);
+    }
+
+    #[test]
+    fn test_hires_switch() {
+        let mut bus = Bus::with_platform(Platform::SuperChip);
+        bus.write_pixel(1, 1, 1);
+
+        bus.set_hires(true);
+        assert_eq!((bus.display_width(), bus.display_height()), (128, 64));
+        assert_eq!(bus.get_pixel(1, 1), 0);
+
+        bus.write_pixel(127, 63, 1);
+        assert_eq!(bus.get_pixel(127, 63), 1);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut bus = Bus::with_platform(Platform::SuperChip);
+        bus.write_pixel(10, 10, 1);
+
+        bus.scroll_down(3);
+        assert_eq!(bus.get_pixel(10, 10), 0);
+        assert_eq!(bus.get_pixel(10, 13), 1);
+
+        bus.scroll_right(4);
+        assert_eq!(bus.get_pixel(14, 13), 1);
+
+        bus.scroll_left(4);
+        assert_eq!(bus.get_pixel(10, 13), 1);
+
+        // pixels scrolled off the edge are lost
+        bus.scroll_left(11);
+        bus.scroll_right(11);
+        assert_eq!(bus.get_pixel(10, 13), 0);
+    }
+
+    #[test]
+    fn test_xochip_has_64k_memory() {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        assert_eq!(bus.memory.len(), XO_RAM_SIZE);
+
+        // ROMs that wouldn't fit in 4K load fine
+        bus.load_rom(&[0; 8000]).unwrap();
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+
+        bus.write_pixel(3, 3, 0b11);
+        assert_eq!(bus.get_pixel(3, 3), 0b11);
+
+        // clearing and scrolling only touch the selected planes
+        bus.select_planes(0b10);
+        bus.scroll_right(1);
+        assert_eq!(bus.get_pixel(3, 3), 0b01);
+        assert_eq!(bus.get_pixel(4, 3), 0b10);
+
+        bus.clear_display();
+        assert_eq!(bus.get_pixel(3, 3), 0b01);
+        assert_eq!(bus.get_pixel(4, 3), 0);
+
+        // collisions are reported per plane
+        assert!(!bus.write_pixel(3, 3, 0b10));
+        assert!(bus.write_pixel(3, 3, 0b01));
+    }
+
+    #[test]
+    fn test_display_buffer() {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        bus.write_pixel(5, 1, 0b10);
+        assert_eq!(bus.display_buffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(bus.display_buffer()[SCREEN_WIDTH + 5], 0b10);
+
+        bus.set_hires(true);
+        bus.write_pixel(5, 1, 0b01);
+        assert_eq!(
+            bus.display_buffer().len(),
+            HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT
+        );
+        assert_eq!(bus.display_buffer()[HIRES_SCREEN_WIDTH + 5], 0b01);
+    }
+
+    #[test]
+    fn test_playback_rate() {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        assert_eq!(bus.playback_rate(), 4000.0);
+
+        bus.set_pitch(112);
+        assert_eq!(bus.playback_rate(), 8000.0);
+    }
 }
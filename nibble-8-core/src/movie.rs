@@ -0,0 +1,448 @@
+use std::fmt;
+
+use crate::{
+    Bus, Cpu, Platform, Quirks,
+    cpu::RngSource,
+    database::MAX_TICKRATE,
+    memory::KEY_COUNT,
+    quirks::IndexIncrement,
+    state::{Reader, StateError, Writer, crc32},
+    timing::TimingModel,
+};
+
+pub const MOVIE_MAGIC: [u8; 4] = *b"N8MV";
+pub const MOVIE_VERSION: u16 = 2;
+
+/// A recording of the keypad for every frame since power-on, together with everything else that
+/// decides how the ROM behaves. Playing it back on the same ROM reproduces the run exactly. This
+/// needs an RNG that supports `RngSource::export_state`, movies without one can't be played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    /// How long frames are, in machine cycles rather than `instructions_per_frame` with a model.
+    pub timing: TimingModel,
+    /// CRC-32 of the ROM the movie was recorded with.
+    pub rom_crc32: u32,
+    /// The RNG position at power-on, `None` if the recording RNG couldn't export it.
+    pub rng_state: Option<Vec<u8>>,
+    /// Keypad state per frame, bit N is set if key N was held.
+    pub frames: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data doesn't start with `MOVIE_MAGIC`.
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The data ended before the movie was complete.
+    Truncated,
+    /// A field holds a value that can't be played back.
+    Invalid(&'static str),
+    /// The ROM isn't the one the movie was recorded with.
+    RomMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// The movie was recorded with an RNG that couldn't export its state, so random numbers
+    /// wouldn't be reproduced.
+    MissingRngState,
+    /// The RNG refused the recorded state.
+    RngRejected,
+    LoadRom(String),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "Not a nibble-8 movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported movie version {}", version)
+            }
+            MovieError::Truncated => write!(f, "Movie is truncated"),
+            MovieError::Invalid(field) => write!(f, "Movie has an invalid {}", field),
+            MovieError::RomMismatch { expected, actual } => write!(
+                f,
+                "Movie was recorded with ROM {:08X}, this ROM is {:08X}",
+                expected, actual
+            ),
+            MovieError::MissingRngState => {
+                write!(
+                    f,
+                    "Movie has no RNG state, random numbers can't be reproduced"
+                )
+            }
+            MovieError::RngRejected => write!(f, "The RNG doesn't accept the movie's RNG state"),
+            MovieError::LoadRom(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        match err {
+            StateError::Invalid(field) => MovieError::Invalid(field),
+            _ => MovieError::Truncated,
+        }
+    }
+}
+
+impl Movie {
+    /// Encodes the movie as `MOVIE_MAGIC` and version followed by the header and one key mask
+    /// per frame. All numbers are little endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(&MOVIE_MAGIC);
+        out.u16(MOVIE_VERSION);
+        out.platform(self.platform);
+        encode_quirks(&mut out, self.quirks);
+        out.u32(self.instructions_per_frame);
+        out.u8(match self.timing {
+            TimingModel::None => 0,
+            TimingModel::CosmacVip => 1,
+        });
+        out.u32(self.rom_crc32);
+        match &self.rng_state {
+            Some(rng_state) => {
+                out.bool(true);
+                out.u32(rng_state.len() as u32);
+                out.bytes(rng_state);
+            }
+            None => out.bool(false),
+        }
+        out.u32(self.frames.len() as u32);
+        for &keys in &self.frames {
+            out.u16(keys);
+        }
+
+        out.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut input = Reader::new(bytes);
+        if input.bytes(4)? != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = input.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let platform = input.platform()?;
+        let quirks = decode_quirks(&mut input)?;
+        let instructions_per_frame = input.u32()?;
+        if !(1..=MAX_TICKRATE).contains(&instructions_per_frame) {
+            return Err(MovieError::Invalid("instructions per frame"));
+        }
+        let timing = match input.u8()? {
+            0 => TimingModel::None,
+            1 => TimingModel::CosmacVip,
+            _ => return Err(MovieError::Invalid("timing model")),
+        };
+        let rom_crc32 = input.u32()?;
+        let rng_state = match input.bool()? {
+            true => {
+                let len = input.u32()? as usize;
+                Some(input.bytes(len)?.to_vec())
+            }
+            false => None,
+        };
+        let frame_count = input.u32()? as usize;
+        let frames = input
+            .bytes(frame_count * 2)?
+            .chunks_exact(2)
+            .map(|keys| u16::from_le_bytes([keys[0], keys[1]]))
+            .collect();
+        if !input.is_empty() {
+            return Err(MovieError::Invalid("length"));
+        }
+
+        Ok(Self {
+            platform,
+            quirks,
+            instructions_per_frame,
+            timing,
+            rom_crc32,
+            rng_state,
+            frames,
+        })
+    }
+
+    /// Checks that `rom` is the one the movie was recorded with.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let actual = crc32(rom);
+        if actual != self.rom_crc32 {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_crc32,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Powers on a machine in the state the recording started from, with `rng` moved to the
+    /// recorded position and the recorded `TimingModel`. Frames have to be run by
+    /// `TimingModel::cycles_per_tick` if there is one.
+    pub fn power_on(&self, rom: &[u8], rng: Box<dyn RngSource>) -> Result<(Cpu, Bus), MovieError> {
+        self.check_rom(rom)?;
+        let rng_state = self.rng_state.as_ref().ok_or(MovieError::MissingRngState)?;
+
+        let mut bus = Bus::with_platform(self.platform);
+        bus.load_rom(rom).map_err(MovieError::LoadRom)?;
+        let mut cpu = Cpu::new(rng, self.quirks);
+        cpu.set_timing_model(self.timing);
+        if !cpu.import_rng_state(rng_state) {
+            return Err(MovieError::RngRejected);
+        }
+
+        Ok((cpu, bus))
+    }
+}
+
+fn encode_quirks(out: &mut Writer, quirks: Quirks) {
+    let flags = [
+        quirks.shift_uses_vy,
+        quirks.jump_uses_vx,
+        quirks.wrap_sprites,
+        quirks.vf_reset,
+        quirks.display_wait,
+    ];
+    out.u8(flags
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (bit, &set)| mask | ((set as u8) << bit)));
+    out.u8(match quirks.load_store {
+        IndexIncrement::Unchanged => 0,
+        IndexIncrement::ByX => 1,
+        IndexIncrement::ByXPlusOne => 2,
+    });
+}
+
+fn decode_quirks(input: &mut Reader) -> Result<Quirks, StateError> {
+    let flags = input.u8()?;
+    let load_store = match input.u8()? {
+        0 => IndexIncrement::Unchanged,
+        1 => IndexIncrement::ByX,
+        2 => IndexIncrement::ByXPlusOne,
+        _ => return Err(StateError::Invalid("quirks")),
+    };
+
+    Ok(Quirks {
+        shift_uses_vy: flags & 0b0001 != 0,
+        jump_uses_vx: flags & 0b0010 != 0,
+        wrap_sprites: flags & 0b0100 != 0,
+        vf_reset: flags & 0b1000 != 0,
+        display_wait: flags & 0b1_0000 != 0,
+        load_store,
+    })
+}
+
+fn key_mask(bus: &Bus) -> u16 {
+    (0..KEY_COUNT as u8).fold(0, |mask, key| {
+        mask | ((bus.is_key_pressed(key) as u16) << key)
+    })
+}
+
+/// Builds a `Movie` frame by frame.
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts a recording, `cpu` and `bus` must have just been powered on with `rom`.
+    pub fn new(cpu: &Cpu, bus: &Bus, rom: &[u8], instructions_per_frame: u32) -> Self {
+        Self {
+            movie: Movie {
+                platform: bus.platform(),
+                quirks: cpu.quirks(),
+                instructions_per_frame,
+                timing: cpu.timing_model(),
+                rom_crc32: crc32(rom),
+                rng_state: cpu.snapshot().rng_state,
+                frames: Vec::new(),
+            },
+        }
+    }
+
+    /// Records the keypad, call once per frame after the keys are set and before the frame runs.
+    pub fn record_frame(&mut self, bus: &Bus) {
+        self.movie.frames.push(key_mask(bus));
+    }
+
+    /// The number of frames recorded so far.
+    pub fn frames(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds a `Movie`'s keypad states into a `Bus`, in place of real input.
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, frame: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// The number of frames played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Sets the keypad for the next frame, call once per frame before it runs. Returns `false`
+    /// and leaves the keypad alone once the movie is over.
+    pub fn feed(&mut self, bus: &mut Bus) -> bool {
+        let Some(&keys) = self.movie.frames.get(self.frame) else {
+            return false;
+        };
+        for key in 0..KEY_COUNT as u8 {
+            bus.set_key(key, keys & (1 << key) != 0);
+        }
+        self.frame += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu::ThreadRngSource;
+
+    struct CountingRng {
+        value: u8,
+    }
+
+    impl RngSource for CountingRng {
+        fn next_byte(&mut self) -> u8 {
+            self.value = self.value.wrapping_add(1);
+            self.value
+        }
+
+        fn export_state(&self) -> Option<Vec<u8>> {
+            Some(vec![self.value])
+        }
+
+        fn import_state(&mut self, state: &[u8]) -> bool {
+            match state {
+                [value] => {
+                    self.value = *value;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    fn rom() -> Vec<u8> {
+        // Adds a random byte to V1 for every frame key 5 is held
+        assemble("loop:\nLD V0, 5\nSKNP V0\nCALL add\nJP loop\nadd:\nRND V2, 0xFF\nADD V1, V2\nRET")
+            .unwrap()
+            .bytes
+    }
+
+    fn run_frame(cpu: &mut Cpu, bus: &mut Bus) {
+        for _ in 0..4 {
+            cpu.step(bus).unwrap();
+        }
+    }
+
+    fn recorder() -> MovieRecorder {
+        let cpu = Cpu::new(Box::new(CountingRng { value: 0 }), Quirks::default());
+        MovieRecorder::new(&cpu, &Bus::new(), &rom(), 10)
+    }
+
+    #[test]
+    fn test_playback_reproduces_recording() {
+        let rom = rom();
+        let mut cpu = Cpu::new(Box::new(CountingRng { value: 40 }), Quirks::xochip());
+        cpu.set_timing_model(TimingModel::CosmacVip);
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        bus.load_rom(&rom).unwrap();
+
+        let mut recorder = MovieRecorder::new(&cpu, &bus, &rom, 4);
+        for frame in 0..30 {
+            bus.set_key(0x5, frame % 3 == 0);
+            recorder.record_frame(&bus);
+            run_frame(&mut cpu, &mut bus);
+        }
+        let expected = cpu.snapshot();
+
+        let movie = Movie::from_bytes(&recorder.finish().to_bytes()).unwrap();
+        assert_eq!(movie.frames.len(), 30);
+        assert_eq!(movie.platform, Platform::XoChip);
+        assert_eq!(movie.quirks, Quirks::xochip());
+        assert_eq!(movie.timing, TimingModel::CosmacVip);
+
+        let (mut cpu, mut bus) = movie
+            .power_on(&rom, Box::new(CountingRng { value: 0 }))
+            .unwrap();
+        assert_eq!(cpu.timing_model(), TimingModel::CosmacVip);
+        let mut player = MoviePlayer::new(movie);
+        while player.feed(&mut bus) {
+            run_frame(&mut cpu, &mut bus);
+        }
+
+        assert_eq!(player.frame(), 30);
+        assert_eq!(cpu.snapshot(), expected);
+        assert_ne!(cpu.v_registers()[1], 0);
+    }
+
+    #[test]
+    fn test_rejects_other_rom() {
+        let movie = recorder().finish();
+        let result = movie.power_on(&[0x00, 0xE0], Box::new(CountingRng { value: 0 }));
+        assert!(matches!(result, Err(MovieError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn test_rejects_missing_rng_state() {
+        let cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        let movie = MovieRecorder::new(&cpu, &Bus::new(), &rom(), 10).finish();
+        assert_eq!(movie.rng_state, None);
+
+        let result = movie.power_on(&rom(), Box::new(CountingRng { value: 0 }));
+        assert!(matches!(result, Err(MovieError::MissingRngState)));
+    }
+
+    #[test]
+    fn test_rejects_corrupted_data() {
+        let mut movie = recorder().finish();
+        movie.frames = vec![0x0020, 0x8001];
+        let bytes = movie.to_bytes();
+
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Truncated)
+        );
+        assert_eq!(Movie::from_bytes(b"N8ST"), Err(MovieError::BadMagic));
+
+        let mut bytes = bytes;
+        // Behind the timing model come the ROM CRC, the one byte RNG state and the two frames
+        let timing = bytes.len() - 4 - (1 + 4 + 1) - (4 + 2 * 2) - 1;
+        assert_eq!(bytes[timing], 0);
+        bytes[timing] = 7;
+        assert_eq!(
+            Movie::from_bytes(&bytes),
+            Err(MovieError::Invalid("timing model"))
+        );
+    }
+}
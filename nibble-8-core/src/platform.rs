@@ -0,0 +1,53 @@
+use crate::{
+    memory::{RAM_SIZE, XO_RAM_SIZE},
+    quirks::Quirks,
+};
+
+/// The machine a ROM was written for. Decides which opcodes are available and what the
+/// display and memory look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// The quirks ROMs written for this platform usually expect.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::modern(),
+            Platform::SuperChip => Quirks::superchip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    /// Size of the addressable RAM in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => RAM_SIZE as usize,
+            Platform::XoChip => XO_RAM_SIZE,
+        }
+    }
+
+    /// Whether the SUPER-CHIP 1.1 opcodes (scrolling, hi-res, big sprites, RPL flags) are available.
+    pub fn has_superchip_opcodes(self) -> bool {
+        !matches!(self, Platform::Chip8)
+    }
+
+    /// Whether the XO-CHIP opcodes (long `LoadI`, register ranges, bitplanes, audio) are available.
+    pub fn has_xochip_opcodes(self) -> bool {
+        matches!(self, Platform::XoChip)
+    }
+
+    /// Looks up a platform by name (`chip8`, `schip` or `xochip`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" | "xo" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/// How `DumpRegs` (FX55) and `FillRegs` (FX65) leave the index register behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// `I` is left untouched (SUPER-CHIP 1.1 and most modern interpreters).
+    Unchanged,
+    /// `I` is incremented by X (CHIP-48).
+    ByX,
+    /// `I` is incremented by X + 1 (original COSMAC VIP).
+    ByXPlusOne,
+}
+
+/// Toggles for the opcodes whose behavior differs between CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` copy VY into VX before shifting (COSMAC VIP) instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// What happens to `I` after `FX55`/`FX65`.
+    pub load_store: IndexIncrement,
+    /// `BNNN` jumps to `XNN + VX` (CHIP-48/SUPER-CHIP) instead of `NNN + V0`.
+    pub jump_uses_vx: bool,
+    /// Sprites that cross the edge of the screen wrap around instead of being clipped.
+    pub wrap_sprites: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0 (COSMAC VIP).
+    pub vf_reset: bool,
+    /// `DXYN` waits for the next 60 Hz tick before drawing, like the VIP waiting for vblank.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            wrap_sprites: false,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48.
+    pub const fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            wrap_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub const fn superchip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            wrap_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const fn xochip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            wrap_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// What most modern interpreters and ROMs written for them expect.
+    pub const fn modern() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            wrap_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// Looks up a preset by name (`vip`, `chip48`, `schip`, `xochip` or `modern`).
+    pub fn from_preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "cosmac_vip" => Some(Self::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Self::superchip()),
+            "xochip" | "xo-chip" | "xo" => Some(Self::xochip()),
+            "modern" => Some(Self::modern()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_preset() {
+        assert_eq!(Quirks::from_preset("VIP"), Some(Quirks::cosmac_vip()));
+        assert_eq!(Quirks::from_preset("chip-48"), Some(Quirks::chip48()));
+        assert_eq!(Quirks::from_preset("schip"), Some(Quirks::superchip()));
+        assert_eq!(Quirks::from_preset("modern"), Some(Quirks::default()));
+        assert_eq!(Quirks::from_preset("xo-chip"), Some(Quirks::xochip()));
+        assert_eq!(Quirks::from_preset("octo"), None);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+
+use crate::{Bus, Cpu, MachineState};
+
+// Equal stretches shorter than this are copied into the literal instead of starting a new run
+const MIN_SKIP: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Upper bound for the bytes kept by the history, the oldest frames are dropped first.
+    pub memory_budget: usize,
+    /// Capture a snapshot every this many frames.
+    pub interval: u32,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget: 16 * 1024 * 1024,
+            interval: 1,
+        }
+    }
+}
+
+struct Snapshot {
+    frame: u64,
+    bytes: Vec<u8>,
+}
+
+/// A bounded history of past machine states to step backwards through.
+///
+/// The newest snapshot is kept as `MachineState` bytes, every older one as a delta against the
+/// snapshot after it. Recording only has to diff against the previous head, rewinding applies a
+/// single delta and dropping the oldest frame needs no re-encoding.
+pub struct Rewind {
+    config: RewindConfig,
+    /// Frames recorded so far, the frame number of the current machine state.
+    frame: u64,
+    head: Option<Snapshot>,
+    /// Oldest first, each delta turns the next newer snapshot into this one.
+    history: VecDeque<Snapshot>,
+    history_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config: RewindConfig {
+                interval: config.interval.max(1),
+                ..config
+            },
+            frame: 0,
+            head: None,
+            history: VecDeque::new(),
+            history_bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    /// The frame number of the current machine state.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The oldest frame `rewind_to` can go back to.
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.history
+            .front()
+            .or(self.head.as_ref())
+            .map(|snapshot| snapshot.frame)
+    }
+
+    /// The number of snapshots kept.
+    pub fn len(&self) -> usize {
+        self.history.len() + self.head.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Bytes used by the snapshots.
+    pub fn memory_used(&self) -> usize {
+        self.history_bytes + self.head.as_ref().map_or(0, |head| head.bytes.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.head = None;
+        self.history.clear();
+        self.history_bytes = 0;
+    }
+
+    /// Call once at the end of every emulated frame, captures a snapshot every `interval` frames.
+    pub fn record_frame(&mut self, cpu: &Cpu, bus: &Bus) {
+        self.frame += 1;
+        if !self.frame.is_multiple_of(self.config.interval as u64) {
+            return;
+        }
+
+        let bytes = MachineState {
+            cpu: cpu.snapshot(),
+            bus: bus.snapshot(),
+        }
+        .to_bytes();
+
+        if let Some(head) = self.head.take() {
+            let delta = encode_delta(&head.bytes, &bytes);
+            self.history_bytes += delta.len();
+            self.history.push_back(Snapshot {
+                frame: head.frame,
+                bytes: delta,
+            });
+        }
+        self.head = Some(Snapshot {
+            frame: self.frame,
+            bytes,
+        });
+
+        while self.memory_used() > self.config.memory_budget {
+            match self.history.pop_front() {
+                Some(oldest) => self.history_bytes -= oldest.bytes.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the newest snapshot older than the current state and returns its frame, or
+    /// `None` if the history is exhausted.
+    pub fn rewind_one_frame(&mut self, cpu: &mut Cpu, bus: &mut Bus) -> Option<u64> {
+        let head_frame = self.head.as_ref()?.frame;
+        if head_frame < self.frame {
+            self.restore_head(cpu, bus);
+            return Some(head_frame);
+        }
+        if self.history.is_empty() {
+            return None;
+        }
+        self.pop_head();
+        self.restore_head(cpu, bus);
+        Some(self.frame)
+    }
+
+    /// Restores the newest snapshot at or before `frame` and returns its frame. Returns `None`
+    /// and leaves everything alone if `frame` is older than the history.
+    pub fn rewind_to(&mut self, frame: u64, cpu: &mut Cpu, bus: &mut Bus) -> Option<u64> {
+        if frame < self.oldest_frame()? {
+            return None;
+        }
+        while self.head.as_ref()?.frame > frame {
+            self.pop_head();
+        }
+        self.restore_head(cpu, bus);
+        Some(self.frame)
+    }
+
+    /// Replaces the head with the next older snapshot, the history must not be empty.
+    fn pop_head(&mut self) {
+        let older = self.history.pop_back().unwrap();
+        self.history_bytes -= older.bytes.len();
+        let head = self.head.as_mut().unwrap();
+        head.bytes = apply_delta(&head.bytes, &older.bytes);
+        head.frame = older.frame;
+    }
+
+    fn restore_head(&mut self, cpu: &mut Cpu, bus: &mut Bus) {
+        let head = self.head.as_ref().unwrap();
+        // Only ever holds bytes from `MachineState::to_bytes`
+        let state = MachineState::from_bytes(&head.bytes).expect("rewind snapshot is valid");
+        cpu.restore(&state.cpu);
+        bus.restore(&state.bus);
+        self.frame = head.frame;
+    }
+}
+
+/// Encodes `target` relative to `base` as its length, followed by `(offset, length, bytes)` runs
+/// of the bytes that differ. Bytes past the end of `base` count as 0.
+fn encode_delta(target: &[u8], base: &[u8]) -> Vec<u8> {
+    let differs = |i: usize| target[i] != base.get(i).copied().unwrap_or(0);
+
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(target.len() as u32).to_le_bytes());
+
+    let mut i = 0;
+    while i < target.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i + 1;
+        while end < target.len() {
+            if differs(end) {
+                end += 1;
+                continue;
+            }
+            let equal = (end..target.len().min(end + MIN_SKIP))
+                .take_while(|&j| !differs(j))
+                .count();
+            if end + equal == target.len() || equal == MIN_SKIP {
+                break;
+            }
+            end += equal;
+        }
+
+        delta.extend_from_slice(&(start as u32).to_le_bytes());
+        delta.extend_from_slice(&((end - start) as u32).to_le_bytes());
+        delta.extend_from_slice(&target[start..end]);
+        i = end;
+    }
+
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let u32_at = |pos: usize| u32::from_le_bytes(delta[pos..pos + 4].try_into().unwrap()) as usize;
+
+    let mut target = base.to_vec();
+    target.resize(u32_at(0), 0);
+
+    let mut pos = 4;
+    while pos < delta.len() {
+        let start = u32_at(pos);
+        let len = u32_at(pos + 4);
+        pos += 8;
+        target[start..start + len].copy_from_slice(&delta[pos..pos + len]);
+        pos += len;
+    }
+
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, asm::assemble, cpu::ThreadRngSource};
+
+    fn machine() -> (Cpu, Bus) {
+        // Counts V0 up and stores it at 0x300 every step
+        let program = assemble("LD I, 0x300\nloop:\nADD V0, 1\nLD [I], V0\nJP loop").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        (cpu, bus)
+    }
+
+    fn run_frames(rewind: &mut Rewind, cpu: &mut Cpu, bus: &mut Bus, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..3 {
+                cpu.step(bus).unwrap();
+            }
+            rewind.record_frame(cpu, bus);
+        }
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = [
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
+        ];
+        let mut target = base.to_vec();
+        target[1] = 0xFF;
+        target[3] = 0xFF;
+        target[18] = 0xFF;
+        target.extend_from_slice(&[0, 0, 7]);
+
+        let delta = encode_delta(&target, &base);
+        assert_eq!(apply_delta(&base, &delta), target);
+        assert_eq!(apply_delta(&target, &encode_delta(&base, &target)), base);
+        assert_eq!(encode_delta(&base, &base), 20u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_rewind_one_frame() {
+        let (mut cpu, mut bus) = machine();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        run_frames(&mut rewind, &mut cpu, &mut bus, 2);
+        let frame_2 = (cpu.snapshot(), bus.snapshot());
+        run_frames(&mut rewind, &mut cpu, &mut bus, 1);
+
+        assert_eq!(rewind.rewind_one_frame(&mut cpu, &mut bus), Some(2));
+        assert_eq!((cpu.snapshot(), bus.snapshot()), frame_2);
+        assert_eq!(rewind.rewind_one_frame(&mut cpu, &mut bus), Some(1));
+        assert_eq!(rewind.rewind_one_frame(&mut cpu, &mut bus), None);
+        assert_eq!(rewind.frame(), 1);
+        assert_eq!(cpu.v_registers()[0], 1);
+
+        // Recording continues from the restored frame
+        run_frames(&mut rewind, &mut cpu, &mut bus, 1);
+        assert_eq!(rewind.frame(), 2);
+        assert_eq!((cpu.snapshot(), bus.snapshot()), frame_2);
+    }
+
+    #[test]
+    fn test_rewind_to() {
+        let (mut cpu, mut bus) = machine();
+        let mut rewind = Rewind::new(RewindConfig {
+            interval: 4,
+            ..RewindConfig::default()
+        });
+        run_frames(&mut rewind, &mut cpu, &mut bus, 10);
+        assert_eq!(rewind.len(), 2);
+
+        assert_eq!(rewind.rewind_to(2, &mut cpu, &mut bus), None);
+        assert_eq!(rewind.rewind_to(7, &mut cpu, &mut bus), Some(4));
+        assert_eq!(cpu.v_registers()[0], 4);
+        assert_eq!(bus.memory()[0x300], 4);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_memory_budget_drops_oldest_frames() {
+        let (mut cpu, mut bus) = machine();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        run_frames(&mut rewind, &mut cpu, &mut bus, 1);
+        let state_size = rewind.memory_used();
+
+        let mut rewind = Rewind::new(RewindConfig {
+            memory_budget: state_size + 200,
+            interval: 1,
+        });
+        run_frames(&mut rewind, &mut cpu, &mut bus, 100);
+
+        assert!(rewind.memory_used() <= state_size + 200);
+        assert!(rewind.len() > 2);
+        assert!(rewind.oldest_frame().unwrap() > 1);
+        let oldest = rewind.oldest_frame().unwrap();
+        assert_eq!(rewind.rewind_to(0, &mut cpu, &mut bus), None);
+        assert_eq!(rewind.rewind_to(oldest, &mut cpu, &mut bus), Some(oldest));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::cpu::RngSource;
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+const PCG_DEFAULT_STREAM: u64 = 0xDA3E_39CB_94B9_5BDB;
+
+/// PCG32 (XSH RR), a small seedable generator whose position can be saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcg32Source {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32Source {
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, PCG_DEFAULT_STREAM)
+    }
+
+    /// Seeded from the thread RNG, for runs that should differ but still be saveable.
+    pub fn random() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// Generators with the same seed but different streams produce unrelated sequences.
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(PCG_MULTIPLIER)
+            .wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+}
+
+impl RngSource for Pcg32Source {
+    fn next_byte(&mut self) -> u8 {
+        (self.next_u32() >> 24) as u8
+    }
+
+    fn export_state(&self) -> Option<Vec<u8>> {
+        let mut state = self.state.to_le_bytes().to_vec();
+        state.extend_from_slice(&self.increment.to_le_bytes());
+        Some(state)
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> bool {
+        if state.len() != 16 || state[8] & 1 == 0 {
+            return false;
+        }
+        self.state = u64::from_le_bytes(state[..8].try_into().unwrap());
+        self.increment = u64::from_le_bytes(state[8..].try_into().unwrap());
+        true
+    }
+}
+
+/// The `CXNN` generator of the original COSMAC VIP interpreter.
+///
+/// The interpreter keeps its seed in the 16-bit register R9. For every random number it increments
+/// R9, reads the byte of its own code page (0x0100-0x01FF) addressed by the low byte of R9, adds
+/// the high byte of R9 to it and stores the sum back into the high byte. The sum is the result.
+/// The code page is part of the VIP ROM image, so it has to be supplied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VipRngSource {
+    seed: u16,
+    code_page: [u8; 256],
+}
+
+impl VipRngSource {
+    pub fn new(seed: u16, code_page: [u8; 256]) -> Self {
+        Self { seed, code_page }
+    }
+
+    /// The current value of R9.
+    pub fn seed(&self) -> u16 {
+        self.seed
+    }
+}
+
+impl RngSource for VipRngSource {
+    fn next_byte(&mut self) -> u8 {
+        let [low, high] = self.seed.wrapping_add(1).to_le_bytes();
+        let value = self.code_page[low as usize].wrapping_add(high);
+        self.seed = u16::from_le_bytes([low, value]);
+        value
+    }
+
+    fn export_state(&self) -> Option<Vec<u8>> {
+        Some(self.seed.to_le_bytes().to_vec())
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> bool {
+        match state {
+            [low, high] => {
+                self.seed = u16::from_le_bytes([*low, *high]);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Plays back a fixed list of bytes, starting over at the end. Meant for tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceRngSource {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl SequenceRngSource {
+    /// Panics if `bytes` is empty.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        assert!(
+            !bytes.is_empty(),
+            "SequenceRngSource needs at least one byte"
+        );
+        Self { bytes, position: 0 }
+    }
+
+    /// The index of the next byte to be returned.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl RngSource for SequenceRngSource {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.position];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn export_state(&self) -> Option<Vec<u8>> {
+        Some((self.position as u32).to_le_bytes().to_vec())
+    }
+
+    fn import_state(&mut self, state: &[u8]) -> bool {
+        let Ok(position) = <[u8; 4]>::try_from(state) else {
+            return false;
+        };
+        let position = u32::from_le_bytes(position) as usize;
+        if position >= self.bytes.len() {
+            return false;
+        }
+        self.position = position;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcg32_reference_output() {
+        // From the PCG reference implementation's pcg32-demo
+        let mut rng = Pcg32Source::with_stream(42, 54);
+        let output: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+        assert_eq!(
+            output,
+            [
+                0xA15C02B7, 0x7B47F409, 0xBA1D3330, 0x83D2F293, 0xBFA4784B, 0xCBED606E
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_and_import_resume_the_sequence() {
+        let sources: Vec<Box<dyn RngSource>> = vec![
+            Box::new(Pcg32Source::new(7)),
+            Box::new(VipRngSource::new(
+                0x1234,
+                std::array::from_fn(|i| i as u8 ^ 0x5A),
+            )),
+            Box::new(SequenceRngSource::new(vec![3, 1, 4, 1, 5])),
+        ];
+
+        for mut rng in sources {
+            rng.next_byte();
+            let state = rng.export_state().unwrap();
+            let expected: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+
+            assert!(rng.import_state(&state));
+            let resumed: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+            assert_eq!(resumed, expected);
+            assert!(!rng.import_state(&[0xFF; 3]));
+        }
+    }
+
+    #[test]
+    fn test_seeds_are_reproducible() {
+        let bytes = |seed| {
+            let mut rng = Pcg32Source::new(seed);
+            (0..16).map(|_| rng.next_byte()).collect::<Vec<_>>()
+        };
+        assert_eq!(bytes(1), bytes(1));
+        assert_ne!(bytes(1), bytes(2));
+    }
+
+    #[test]
+    fn test_vip_algorithm() {
+        let mut code_page = [0; 256];
+        code_page[0x01] = 0x10;
+        code_page[0x02] = 0xF5;
+        let mut rng = VipRngSource::new(0x0000, code_page);
+
+        assert_eq!(rng.next_byte(), 0x10);
+        assert_eq!(rng.seed(), 0x1001);
+        assert_eq!(rng.next_byte(), 0x05);
+        assert_eq!(rng.seed(), 0x0502);
+    }
+
+    #[test]
+    fn test_sequence_wraps_around() {
+        let mut rng = SequenceRngSource::new(vec![1, 2, 3]);
+        let bytes: Vec<u8> = (0..5).map(|_| rng.next_byte()).collect();
+        assert_eq!(bytes, [1, 2, 3, 1, 2]);
+        assert_eq!(rng.position(), 2);
+    }
+}
@@ -0,0 +1,412 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::audio::{SoundTick, TICK_RATE};
+use crate::{Bus, Cpu, EmulationError};
+
+/// Ticks `Scheduler::due_ticks` hands out at most per call at normal speed. Anything beyond that
+/// is dropped rather than caught up, e.g. after the window was dragged.
+const MAX_TICKS_PER_UPDATE: f64 = 8.0;
+
+/// A monotonic time source.
+pub trait Clock {
+    /// Time since an arbitrary, fixed origin.
+    fn now(&self) -> Duration;
+}
+
+/// Wall-clock time.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct FakeClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// What happened during a single `Scheduler::run_tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TickOutcome {
+    /// How many instructions were executed.
+    pub instructions: u32,
+    /// Machine cycles the instructions took, see `Cpu::set_timing_model`.
+    pub cycles: u64,
+    /// The display buffer changed and should be presented again.
+    pub redraw: bool,
+    /// What the buzzer played during the tick.
+    pub sound: SoundTick,
+    /// The last display change so far was followed by the program waiting, see
+    /// `StepOutcome::waiting`. The display likely shows a complete frame.
+    pub settled: bool,
+}
+
+/// Runs the `Cpu` at a fixed instruction rate next to the 60 Hz timers, independent of how often
+/// the frontend gets around to calling it.
+///
+/// The frontend asks `due_ticks` how many timer ticks the clock says are owed and then calls
+/// `run_tick` that many times, doing its own per-tick work (input, audio, rewind) in between.
+pub struct Scheduler {
+    clock: Box<dyn Clock>,
+    instructions_per_second: u32,
+    speed: f64,
+    paused: bool,
+    /// Ticks to hand out while paused, see `frame_advance`.
+    advance_ticks: u32,
+    last_time: Duration,
+    /// Fraction of a tick carried over to the next call.
+    tick_debt: f64,
+    /// Instructions owed, in 1/60ths.
+    instruction_debt: u64,
+    /// Run by machine cycles instead of instructions, see `set_cycles_per_tick`.
+    cycles_per_tick: Option<u32>,
+    /// Cycles owed, negative after the last instruction of a tick overran it.
+    cycle_debt: i64,
+    /// Ticks end early when PC gets here, see `set_stop_pc`.
+    stop_pc: Option<u16>,
+    /// `TickOutcome::settled` carried over from the previous tick.
+    display_settled: bool,
+}
+
+impl Scheduler {
+    pub fn new(clock: Box<dyn Clock>, instructions_per_second: u32) -> Self {
+        Self {
+            last_time: clock.now(),
+            clock,
+            instructions_per_second,
+            speed: 1.0,
+            paused: false,
+            advance_ticks: 0,
+            tick_debt: 0.0,
+            instruction_debt: 0,
+            cycles_per_tick: None,
+            cycle_debt: 0,
+            stop_pc: None,
+            display_settled: true,
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+    }
+
+    pub fn cycles_per_tick(&self) -> Option<u32> {
+        self.cycles_per_tick
+    }
+
+    /// With a budget, every tick runs instructions until their `StepOutcome::cycles` add up to it
+    /// and the instruction rate is ignored. This needs a `TimingModel` on the `Cpu`.
+    pub fn set_cycles_per_tick(&mut self, cycles_per_tick: Option<u32>) {
+        self.cycles_per_tick = cycles_per_tick;
+        self.cycle_debt = 0;
+    }
+
+    /// Ends ticks as soon as PC reaches `pc`, before the instruction there runs. Like a wait for
+    /// the vblank, every following tick then only counts the timers down.
+    pub fn set_stop_pc(&mut self, pc: Option<u16>) {
+        self.stop_pc = pc;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Scales emulated time, above 1 fast-forwards and below 1 plays in slow motion.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.advance_ticks = 0;
+    }
+
+    /// Pauses and lets exactly one more tick through.
+    pub fn frame_advance(&mut self) {
+        self.paused = true;
+        self.advance_ticks += 1;
+    }
+
+    /// The number of ticks to run now, going by the time passed since the last call.
+    pub fn due_ticks(&mut self) -> u32 {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_time);
+        self.last_time = now;
+
+        if self.paused {
+            self.tick_debt = 0.0;
+            return std::mem::take(&mut self.advance_ticks);
+        }
+
+        self.tick_debt += elapsed.as_secs_f64() * TICK_RATE as f64 * self.speed;
+        let ticks = self.tick_debt.floor();
+        self.tick_debt -= ticks;
+
+        ticks.min((MAX_TICKS_PER_UPDATE * self.speed.max(1.0)).ceil()) as u32
+    }
+
+    /// Real time until the next tick is due, for sleeping in between.
+    pub fn until_next_tick(&self) -> Duration {
+        let tick = 1.0 / TICK_RATE as f64;
+        if self.paused || self.speed <= 0.0 {
+            return Duration::from_secs_f64(tick);
+        }
+        Duration::from_secs_f64((1.0 - self.tick_debt) * tick / self.speed)
+    }
+
+    /// Runs the instructions that fit into one tick, then counts the timers down. A `DXYN` waiting
+    /// for the vblank ends the tick early.
+    pub fn run_tick(
+        &mut self,
+        cpu: &mut Cpu,
+        bus: &mut Bus,
+    ) -> Result<TickOutcome, EmulationError> {
+        let mut outcome = TickOutcome {
+            settled: self.display_settled,
+            ..TickOutcome::default()
+        };
+        let mut step = |cpu: &mut Cpu| -> Result<u32, EmulationError> {
+            let step = cpu.step(bus)?;
+            outcome.redraw |= step.redraw;
+            if step.redraw {
+                outcome.settled = false;
+            }
+            if step.waiting {
+                outcome.settled = true;
+            }
+            outcome.instructions += 1;
+            outcome.cycles += step.cycles as u64;
+            Ok(step.cycles)
+        };
+        let stop_pc = self.stop_pc;
+        let stopped =
+            |cpu: &Cpu| cpu.is_halted() || cpu.is_waiting_for_vblank() || stop_pc == Some(cpu.pc());
+
+        match self.cycles_per_tick {
+            Some(budget) => {
+                self.cycle_debt += budget as i64;
+                while self.cycle_debt > 0 && !stopped(cpu) {
+                    // Steps without a cost still have to end the tick eventually
+                    self.cycle_debt -= step(cpu)?.max(1) as i64;
+                }
+                // What the wait skipped isn't made up for later
+                if stopped(cpu) {
+                    self.cycle_debt = self.cycle_debt.min(0);
+                }
+            }
+            None => {
+                self.instruction_debt += self.instructions_per_second as u64;
+                let instructions = self.instruction_debt / TICK_RATE as u64;
+                self.instruction_debt %= TICK_RATE as u64;
+
+                for _ in 0..instructions {
+                    if stopped(cpu) {
+                        break;
+                    }
+                    step(cpu)?;
+                }
+            }
+        }
+
+        outcome.sound = SoundTick::of(cpu, bus);
+        self.display_settled = outcome.settled;
+        cpu.decrease_timers();
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, asm::assemble, cpu::ThreadRngSource, timing::TimingModel};
+
+    fn machine() -> (Cpu, Bus) {
+        let program = assemble("LD V0, 30\nLD DT, V0\nloop:\nJP loop").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        (cpu, bus)
+    }
+
+    fn scheduler(instructions_per_second: u32) -> (Scheduler, FakeClock) {
+        let clock = FakeClock::new();
+        let scheduler = Scheduler::new(Box::new(clock.clone()), instructions_per_second);
+        (scheduler, clock)
+    }
+
+    #[test]
+    fn test_ticks_follow_the_clock() {
+        let (mut scheduler, clock) = scheduler(600);
+        assert_eq!(scheduler.due_ticks(), 0);
+
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(scheduler.due_ticks(), 3);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.due_ticks(), 0);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.due_ticks(), 1);
+
+        // Too far behind, the rest is dropped
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(scheduler.due_ticks(), 8);
+        assert_eq!(scheduler.due_ticks(), 0);
+    }
+
+    #[test]
+    fn test_instruction_rate_and_timers() {
+        let (mut scheduler, _) = scheduler(700);
+        let (mut cpu, mut bus) = machine();
+
+        let mut instructions = 0;
+        for _ in 0..60 {
+            instructions += scheduler.run_tick(&mut cpu, &mut bus).unwrap().instructions;
+        }
+        assert_eq!(instructions, 700);
+
+        let (mut cpu, mut bus) = machine();
+        scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(cpu.delay_timer(), 29);
+    }
+
+    #[test]
+    fn test_cycle_budget() {
+        let (mut scheduler, _) = scheduler(600);
+        scheduler.set_cycles_per_tick(Some(1000));
+        let (mut cpu, mut bus) = machine();
+        cpu.set_timing_model(TimingModel::CosmacVip);
+
+        // LD and LD DT take 46 and 50, then the 52 cycle jumps run over the budget by 32
+        let outcome = scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(outcome.instructions, 2 + 18);
+        assert_eq!(outcome.cycles, 1032);
+        // Which the next tick has less of
+        let outcome = scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(outcome.instructions, 19);
+        assert_eq!(outcome.cycles, 988);
+    }
+
+    #[test]
+    fn test_display_wait_ends_the_tick() {
+        let program = assemble("loop:\nDRW V0, V0, 1\nJP loop").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::cosmac_vip());
+        let (mut scheduler, _) = scheduler(600);
+
+        let outcome = scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(outcome.instructions, 1);
+        assert!(!outcome.redraw);
+        // One sprite per tick from here on
+        let outcome = scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(outcome.instructions, 3);
+        assert!(outcome.redraw);
+    }
+
+    #[test]
+    fn test_settled_after_draw_and_wait() {
+        let program = assemble("DRW V0, V0, 1\nDRW V0, V0, 2\nLD V1, DT\nLD V2, 3").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+        let (mut scheduler, _) = scheduler(60);
+
+        let settled: Vec<bool> = (0..4)
+            .map(|_| scheduler.run_tick(&mut cpu, &mut bus).unwrap().settled)
+            .collect();
+        // Still settled after the wait, until the next draw
+        assert_eq!(settled, [false, false, true, true]);
+    }
+
+    #[test]
+    fn test_stop_pc() {
+        let (mut scheduler, _) = scheduler(600);
+        scheduler.set_stop_pc(Some(0x204));
+        let (mut cpu, mut bus) = machine();
+
+        let outcome = scheduler.run_tick(&mut cpu, &mut bus).unwrap();
+        assert_eq!(outcome.instructions, 2);
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(
+            scheduler.run_tick(&mut cpu, &mut bus).unwrap().instructions,
+            0
+        );
+    }
+
+    #[test]
+    fn test_speed() {
+        let (mut scheduler, clock) = scheduler(600);
+        scheduler.set_speed(2.0);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(scheduler.due_ticks(), 6);
+
+        scheduler.set_speed(0.5);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(scheduler.due_ticks(), 1);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(scheduler.due_ticks(), 2);
+    }
+
+    #[test]
+    fn test_pause_and_frame_advance() {
+        let (mut scheduler, clock) = scheduler(600);
+        scheduler.set_paused(true);
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(scheduler.due_ticks(), 0);
+
+        scheduler.frame_advance();
+        scheduler.frame_advance();
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(scheduler.due_ticks(), 2);
+        assert_eq!(scheduler.due_ticks(), 0);
+
+        // No catching up on the time spent paused
+        scheduler.set_paused(false);
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(scheduler.due_ticks(), 1);
+    }
+}
@@ -0,0 +1,520 @@
+use std::fmt;
+
+use crate::{
+    memory::{
+        AUDIO_PATTERN_SIZE, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, KEY_COUNT, RPL_FLAG_COUNT,
+    },
+    platform::Platform,
+};
+
+pub const STATE_MAGIC: [u8; 4] = *b"N8ST";
+pub const STATE_VERSION: u16 = 2;
+
+// magic + version + payload length + checksum
+const HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub v_registers: [u8; 16],
+    pub pc: u16,
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub halted: bool,
+    /// A `DXYN` is waiting for the vblank, see `Quirks::display_wait`.
+    pub waiting_for_vblank: bool,
+    /// The vblank a `DXYN` waited for has passed and the next one draws at once.
+    pub vblank_passed: bool,
+    /// Exported by the `RngSource`, `None` if it doesn't support it.
+    pub rng_state: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusState {
+    pub platform: Platform,
+    pub memory: Vec<u8>,
+    pub display: Vec<u8>,
+    pub hires: bool,
+    pub planes: u8,
+    pub keys: [bool; KEY_COUNT],
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+/// A full snapshot of the machine, see `Cpu::snapshot` and `Bus::snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub cpu: CpuState,
+    pub bus: BusState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with `STATE_MAGIC`.
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    /// The data ended before the state was complete.
+    Truncated,
+    /// A field holds a value that can't be restored.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a nibble-8 save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            StateError::ChecksumMismatch => write!(f, "Save state checksum mismatch"),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(field) => write!(f, "Save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl MachineState {
+    /// Encodes the state as `STATE_MAGIC`, version, payload length and CRC-32 of the payload,
+    /// followed by the payload. All numbers are little endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Writer::default();
+        self.cpu.encode(&mut payload);
+        self.bus.encode(&mut payload);
+        let payload = payload.bytes;
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+        bytes.extend_from_slice(&STATE_MAGIC);
+        bytes.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut header = Reader::new(bytes);
+        if header.bytes(4)? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = header.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let payload_len = header.u32()? as usize;
+        let checksum = header.u32()?;
+        let payload = header.bytes(payload_len)?;
+        if crc32(payload) != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        let mut payload = Reader::new(payload);
+        let state = Self {
+            cpu: CpuState::decode(&mut payload)?,
+            bus: BusState::decode(&mut payload)?,
+        };
+        // Anything left over means the layout isn't the one this version writes
+        if !payload.is_empty() || !header.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        Ok(state)
+    }
+}
+
+impl CpuState {
+    fn encode(&self, out: &mut Writer) {
+        out.bytes(&self.v_registers);
+        out.u16(self.pc);
+        out.u16(self.i);
+        out.u8(self.delay_timer);
+        out.u8(self.sound_timer);
+        for &addr in &self.stack {
+            out.u16(addr);
+        }
+        out.u8(self.sp);
+        out.bool(self.halted);
+        out.bool(self.waiting_for_vblank);
+        out.bool(self.vblank_passed);
+        match &self.rng_state {
+            Some(rng_state) => {
+                out.bool(true);
+                out.u32(rng_state.len() as u32);
+                out.bytes(rng_state);
+            }
+            None => out.bool(false),
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, StateError> {
+        let mut state = Self {
+            v_registers: input.array()?,
+            pc: input.u16()?,
+            i: input.u16()?,
+            delay_timer: input.u8()?,
+            sound_timer: input.u8()?,
+            stack: [0; 16],
+            sp: 0,
+            halted: false,
+            waiting_for_vblank: false,
+            vblank_passed: false,
+            rng_state: None,
+        };
+        for addr in state.stack.iter_mut() {
+            *addr = input.u16()?;
+        }
+        state.sp = input.u8()?;
+        if state.sp as usize >= state.stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        state.halted = input.bool()?;
+        state.waiting_for_vblank = input.bool()?;
+        state.vblank_passed = input.bool()?;
+        if input.bool()? {
+            let len = input.u32()? as usize;
+            state.rng_state = Some(input.bytes(len)?.to_vec());
+        }
+
+        Ok(state)
+    }
+}
+
+impl BusState {
+    fn encode(&self, out: &mut Writer) {
+        out.platform(self.platform);
+        out.bytes(&self.memory);
+        out.bytes(&self.display);
+        out.bool(self.hires);
+        out.u8(self.planes);
+        let keys = self
+            .keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |mask, (key, &pressed)| {
+                mask | ((pressed as u16) << key)
+            });
+        out.u16(keys);
+        out.bytes(&self.rpl_flags);
+        out.bytes(&self.audio_pattern);
+        out.u8(self.pitch);
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, StateError> {
+        let platform = input.platform()?;
+        let memory = input.bytes(platform.memory_size())?.to_vec();
+        let display = input
+            .bytes(HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT)?
+            .to_vec();
+        let hires = input.bool()?;
+        let planes = input.u8()?;
+        let key_mask = input.u16()?;
+
+        Ok(Self {
+            platform,
+            memory,
+            display,
+            hires,
+            planes,
+            keys: std::array::from_fn(|key| key_mask & (1 << key) != 0),
+            rpl_flags: input.array()?,
+            audio_pattern: input.array()?,
+            pitch: input.u8()?,
+        })
+    }
+}
+
+/// Little endian encoder shared by the binary formats.
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub(crate) fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Whether everything has been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn platform(&mut self) -> Result<Platform, StateError> {
+        match self.u8()? {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::SuperChip),
+            2 => Ok(Platform::XoChip),
+            _ => Err(StateError::Invalid("platform")),
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3), the same one zip and PNG use.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bus, Cpu, Quirks, cpu::RngSource};
+
+    struct CountingRng {
+        value: u8,
+    }
+
+    impl RngSource for CountingRng {
+        fn next_byte(&mut self) -> u8 {
+            self.value = self.value.wrapping_add(1);
+            self.value
+        }
+
+        fn export_state(&self) -> Option<Vec<u8>> {
+            Some(vec![self.value])
+        }
+
+        fn import_state(&mut self, state: &[u8]) -> bool {
+            match state {
+                [value] => {
+                    self.value = *value;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    fn running_machine() -> (Cpu, Bus) {
+        let mut cpu = Cpu::new(Box::new(CountingRng { value: 0 }), Quirks::default());
+        let mut bus = Bus::with_platform(Platform::SuperChip);
+        // V0 = 5, I = font 5, draw it, then keep calling a subroutine that sets V1 = rand
+        bus.load_rom(&[
+            0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x22, 0x0A, 0x12, 0x06, 0xC1, 0xFF, 0x00, 0xEE,
+        ])
+        .unwrap();
+        bus.set_key(0x3, true);
+        for _ in 0..6 {
+            cpu.step(&mut bus).unwrap();
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (cpu, bus) = running_machine();
+        let state = MachineState {
+            cpu: cpu.snapshot(),
+            bus: bus.snapshot(),
+        };
+
+        let decoded = MachineState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(decoded, state);
+
+        let mut restored_cpu = Cpu::new(Box::new(CountingRng { value: 0 }), Quirks::default());
+        let mut restored_bus = Bus::new();
+        restored_cpu.restore(&decoded.cpu);
+        restored_bus.restore(&decoded.bus);
+
+        assert_eq!(restored_cpu.snapshot(), cpu.snapshot());
+        assert_eq!(restored_bus.snapshot(), bus.snapshot());
+        assert_eq!(restored_bus.platform(), Platform::SuperChip);
+        assert!(restored_bus.is_key_pressed(0x3));
+    }
+
+    #[test]
+    fn test_restore_is_deterministic() {
+        let (mut cpu, mut bus) = running_machine();
+        let state = MachineState {
+            cpu: cpu.snapshot(),
+            bus: bus.snapshot(),
+        };
+
+        for _ in 0..20 {
+            cpu.step(&mut bus).unwrap();
+        }
+        let expected = cpu.snapshot();
+
+        cpu.restore(&state.cpu);
+        bus.restore(&state.bus);
+        for _ in 0..20 {
+            cpu.step(&mut bus).unwrap();
+        }
+        assert_eq!(cpu.snapshot(), expected);
+    }
+
+    #[test]
+    fn test_restore_during_display_wait() {
+        // Draws a sprite in every frame
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        };
+        let machine = || {
+            let mut bus = Bus::new();
+            bus.load_rom(&[0xD0, 0x15, 0x12, 0x00]).unwrap();
+            (Cpu::new(Box::new(CountingRng { value: 0 }), quirks), bus)
+        };
+        let run_ticks = |cpu: &mut Cpu, bus: &mut Bus, ticks: usize| {
+            for _ in 0..ticks {
+                for _ in 0..10 {
+                    if cpu.is_waiting_for_vblank() {
+                        break;
+                    }
+                    cpu.step(bus).unwrap();
+                }
+                cpu.decrease_timers();
+            }
+        };
+
+        let (mut cpu, mut bus) = machine();
+        run_ticks(&mut cpu, &mut bus, 3);
+        let state = MachineState::from_bytes(
+            &MachineState {
+                cpu: cpu.snapshot(),
+                bus: bus.snapshot(),
+            }
+            .to_bytes(),
+        )
+        .unwrap();
+        assert!(state.cpu.vblank_passed);
+        run_ticks(&mut cpu, &mut bus, 5);
+
+        let (mut restored_cpu, mut restored_bus) = machine();
+        restored_cpu.restore(&state.cpu);
+        restored_bus.restore(&state.bus);
+        run_ticks(&mut restored_cpu, &mut restored_bus, 5);
+
+        assert_eq!(restored_cpu.snapshot(), cpu.snapshot());
+        assert_eq!(restored_bus.snapshot(), bus.snapshot());
+    }
+
+    #[test]
+    fn test_rejects_corrupted_data() {
+        let (cpu, bus) = running_machine();
+        let mut bytes = MachineState {
+            cpu: cpu.snapshot(),
+            bus: bus.snapshot(),
+        }
+        .to_bytes();
+
+        // A payload with a byte too many, under a matching length and checksum
+        let mut padded = bytes.clone();
+        padded.push(0);
+        let payload_len = (padded.len() - 14) as u32;
+        padded[6..10].copy_from_slice(&payload_len.to_le_bytes());
+        let checksum = crc32(&padded[14..]);
+        padded[10..14].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            MachineState::from_bytes(&padded),
+            Err(StateError::Invalid("length"))
+        );
+
+        assert_eq!(
+            MachineState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert_eq!(
+            MachineState::from_bytes(&bytes),
+            Err(StateError::ChecksumMismatch)
+        );
+
+        bytes[4] = 99;
+        assert_eq!(
+            MachineState::from_bytes(&bytes),
+            Err(StateError::UnsupportedVersion(99))
+        );
+
+        assert_eq!(
+            MachineState::from_bytes(b"nope, not a state"),
+            Err(StateError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
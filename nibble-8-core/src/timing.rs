@@ -0,0 +1,221 @@
+use crate::{Bus, Cpu, instruction::Instruction};
+
+/// VIP machine cycles in 1/60 s: 1.7609 MHz with 8 clocks per machine cycle. On the real
+/// machine the display DMA and the interrupt routine take part of this, which isn't modeled.
+pub const VIP_CYCLES_PER_TICK: u32 = 3668;
+
+/// Fetching and decoding an instruction, paid by every instruction.
+const FETCH: u32 = 40;
+/// Extra cost of a skip that is taken.
+const SKIP_TAKEN: u32 = 4;
+/// `BNNN` crossing into the next page.
+const PAGE_CROSSING: u32 = 2;
+/// Clearing the 256 byte display buffer.
+const CLS: u32 = 3078;
+const DRAW_SETUP: u32 = 26;
+const DRAW_ROW: u32 = 34;
+/// A row that isn't byte aligned is written into two display bytes...
+const DRAW_ROW_SPLIT: u32 = 24;
+/// ...after shifting it one bit at a time.
+const DRAW_SHIFT_BIT: u32 = 4;
+/// `FX33` subtracts powers of ten, once per unit of every digit.
+const BCD_BASE: u32 = 84;
+const BCD_PER_UNIT: u32 = 16;
+/// `FX55`/`FX65` per register.
+const LOAD_STORE_BASE: u32 = 14;
+const LOAD_STORE_PER_REGISTER: u32 = 14;
+/// Anything the VIP interpreter doesn't have.
+const NOT_ON_VIP: u32 = 44;
+
+/// How `StepOutcome::cycles` is counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimingModel {
+    /// No cycle counting, every step reports 0 cycles.
+    #[default]
+    None,
+    /// Machine cycles of the instruction routines in the original COSMAC VIP interpreter.
+    CosmacVip,
+}
+
+impl TimingModel {
+    /// The cycle budget of a 60 Hz tick, see `Scheduler::set_cycles_per_tick`. `None` runs at an
+    /// instruction rate instead.
+    pub fn cycles_per_tick(self) -> Option<u32> {
+        match self {
+            TimingModel::None => None,
+            TimingModel::CosmacVip => Some(VIP_CYCLES_PER_TICK),
+        }
+    }
+}
+
+/// The cycles `instruction` takes on the VIP, given the machine state before it runs.
+pub(crate) fn vip_cycles(instruction: &Instruction, cpu: &Cpu, bus: &Bus) -> u32 {
+    let v = |x: u8| cpu.v_registers()[x as usize];
+    let skip = |taken: bool| if taken { SKIP_TAKEN } else { 0 };
+
+    let execute = match *instruction {
+        Instruction::Cls => CLS,
+        Instruction::Ret => 10,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipEq(x, kk) => 10 + skip(v(x) == kk),
+        Instruction::SkipNotEq(x, kk) => 10 + skip(v(x) != kk),
+        Instruction::SkipRegEq(x, y) => 14 + skip(v(x) == v(y)),
+        Instruction::SkipRegNotEq(x, y) => 14 + skip(v(x) != v(y)),
+        Instruction::Load(..) => 6,
+        Instruction::Add(..) => 10,
+        Instruction::LoadReg(..)
+        | Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddReg(..)
+        | Instruction::SubReg(..)
+        | Instruction::Shr(..)
+        | Instruction::Subn(..)
+        | Instruction::Shl(..) => 44,
+        Instruction::LoadI(_) => 12,
+        Instruction::JumpOffset(nnn) => {
+            let target = nnn + v(cpu.jump_offset_register(nnn)) as u16;
+            22 + if target >> 8 != nnn >> 8 {
+                PAGE_CROSSING
+            } else {
+                0
+            }
+        }
+        Instruction::Rand(..) => 36,
+        Instruction::Draw(x, _, n) => {
+            let rows = if n == 0 { 16 } else { n as u32 };
+            let shift = (v(x) % 8) as u32;
+            let row = if shift == 0 {
+                DRAW_ROW
+            } else {
+                DRAW_ROW + DRAW_ROW_SPLIT + DRAW_SHIFT_BIT * shift
+            };
+            DRAW_SETUP + row * rows
+        }
+        Instruction::SkipIfPressed(x) => 14 + skip(bus.is_key_pressed(v(x) & 0x0F)),
+        Instruction::SkipIfNotPressed(x) => 14 + skip(!bus.is_key_pressed(v(x) & 0x0F)),
+        Instruction::LoadRegFromDelay(_)
+        | Instruction::LoadDelayFromReg(_)
+        | Instruction::LoadSoundFromReg(_)
+        | Instruction::WaitForKey(_) => 10,
+        Instruction::AddIndex(_) => 16,
+        Instruction::LoadFont(_) => 20,
+        Instruction::Bcd(x) => {
+            let value = v(x) as u32;
+            let units = value / 100 + value / 10 % 10 + value % 10;
+            BCD_BASE + BCD_PER_UNIT * units
+        }
+        Instruction::DumpRegs(x) | Instruction::FillRegs(x) => {
+            LOAD_STORE_BASE + LOAD_STORE_PER_REGISTER * (x as u32 + 1)
+        }
+        _ => NOT_ON_VIP,
+    };
+
+    FETCH + execute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, asm::assemble, cpu::ThreadRngSource, memory::ROM_START};
+
+    fn machine(source: &str) -> (Cpu, Bus) {
+        let program = assemble(source).unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::cosmac_vip());
+        cpu.set_timing_model(TimingModel::CosmacVip);
+        (cpu, bus)
+    }
+
+    fn cycles(cpu: &mut Cpu, bus: &mut Bus) -> u32 {
+        cpu.step(bus).unwrap().cycles
+    }
+
+    #[test]
+    fn test_instruction_costs() {
+        let (mut cpu, mut bus) =
+            machine("LD V0, 5\nSE V0, 5\nCLS\nADD V0, V0\nLD V1, 123\nLD B, V1");
+
+        assert_eq!(cycles(&mut cpu, &mut bus), FETCH + 6);
+        assert_eq!(cycles(&mut cpu, &mut bus), FETCH + 10 + SKIP_TAKEN);
+        assert_eq!(cycles(&mut cpu, &mut bus), FETCH + 44);
+        assert_eq!(cycles(&mut cpu, &mut bus), FETCH + 6);
+        assert_eq!(
+            cycles(&mut cpu, &mut bus),
+            FETCH + BCD_BASE + BCD_PER_UNIT * 6
+        );
+    }
+
+    #[test]
+    fn test_draw_depends_on_alignment() {
+        let (mut cpu, mut bus) =
+            machine("LD V0, 8\nLD V1, 11\nLD I, 0\nDRW V0, V0, 5\nDRW V1, V0, 5");
+        // The fourth step is the draw waiting for the vblank
+        for _ in 0..4 {
+            cpu.step(&mut bus).unwrap();
+        }
+        cpu.decrease_timers();
+        assert_eq!(
+            cycles(&mut cpu, &mut bus),
+            FETCH + DRAW_SETUP + DRAW_ROW * 5
+        );
+
+        assert_eq!(cycles(&mut cpu, &mut bus), 0);
+        cpu.decrease_timers();
+        let unaligned_row = DRAW_ROW + DRAW_ROW_SPLIT + DRAW_SHIFT_BIT * 3;
+        assert_eq!(
+            cycles(&mut cpu, &mut bus),
+            FETCH + DRAW_SETUP + unaligned_row * 5
+        );
+    }
+
+    #[test]
+    fn test_jump_offset_page_crossing_follows_the_quirk() {
+        let program = assemble("LD V2, 0x10\nJP V0, 0x2F8").unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&program.bytes).unwrap();
+        let quirks = Quirks {
+            jump_uses_vx: true,
+            ..Quirks::cosmac_vip()
+        };
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), quirks);
+        cpu.set_timing_model(TimingModel::CosmacVip);
+
+        cycles(&mut cpu, &mut bus);
+        // V2 takes the jump to 0x308, across a page V0 wouldn't have crossed
+        assert_eq!(cycles(&mut cpu, &mut bus), FETCH + 22 + PAGE_CROSSING);
+        assert_eq!(cpu.pc(), 0x308);
+    }
+
+    #[test]
+    fn test_display_wait() {
+        let (mut cpu, mut bus) = machine("DRW V0, V0, 1\nDRW V0, V0, 1");
+
+        let outcome = cpu.step(&mut bus).unwrap();
+        assert!(!outcome.redraw);
+        assert!(outcome.waiting);
+        assert!(cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.pc(), ROM_START);
+        // Stays put until the timers tick
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc(), ROM_START);
+
+        cpu.decrease_timers();
+        assert!(!cpu.is_waiting_for_vblank());
+        assert!(cpu.step(&mut bus).unwrap().redraw);
+        assert_eq!(cpu.pc(), ROM_START + 2);
+
+        // Every draw waits for its own tick
+        assert!(!cpu.step(&mut bus).unwrap().redraw);
+        assert!(cpu.is_waiting_for_vblank());
+    }
+
+    #[test]
+    fn test_no_cycles_without_timing_model() {
+        let (mut cpu, mut bus) = machine("LD V0, 5");
+        cpu.set_timing_model(TimingModel::None);
+        assert_eq!(cycles(&mut cpu, &mut bus), 0);
+    }
+}
@@ -0,0 +1,565 @@
+//! Instruction-level tracing, only built with the `trace` feature.
+//!
+//! A `Tracer` installed with `Cpu::set_tracer` receives one `TraceEntry` per executed instruction
+//! that passes its `TraceFilter`.
+
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::{
+    decoder::{decode, decode_long, is_long},
+    instruction::Instruction,
+};
+
+const TRACE_MAGIC: &[u8; 4] = b"N8TR";
+const TRACE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayWrite {
+    /// A sprite pixel, `value` is the pixel's plane bitmask after the XOR.
+    Pixel {
+        x: u8,
+        y: u8,
+        value: u8,
+    },
+    Clear {
+        planes: u8,
+    },
+    Scroll {
+        dx: i8,
+        dy: i8,
+    },
+    Resolution {
+        hires: bool,
+    },
+}
+
+/// Writes made by the `Bus` while an instruction was traced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WriteLog {
+    pub memory: Vec<MemoryWrite>,
+    pub display: Vec<DisplayWrite>,
+}
+
+/// One executed instruction. Registers are shown as they are after it ran, except for
+/// `registers_before`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The second word of XO-CHIP's `F000 NNNN`.
+    pub operand: Option<u16>,
+    pub registers_before: [u8; 16],
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory_writes: Vec<MemoryWrite>,
+    pub display_writes: Vec<DisplayWrite>,
+}
+
+impl TraceEntry {
+    pub fn instruction(&self) -> Option<Instruction> {
+        match self.operand {
+            Some(operand) => decode_long(self.opcode, operand),
+            None => decode(self.opcode),
+        }
+    }
+
+    /// `(register, before, after)` for every V register the instruction changed.
+    pub fn register_deltas(&self) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+        self.registers_before
+            .iter()
+            .zip(&self.registers)
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(x, (&before, &after))| (x as u8, before, after))
+    }
+
+    /// A line of text with the machine state after the instruction, then a `;` comment with the
+    /// instruction and what it changed. Comparing only the text before `;` matches the full state
+    /// dumps of other emulators.
+    pub fn to_text(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .enumerate()
+            .map(|(x, value)| format!("V{:X}={:02X}", x, value))
+            .collect();
+        let mut line = format!(
+            "{:04X}: {:04X} {} I={:04X} SP={:X} DT={:02X} ST={:02X} ;",
+            self.pc,
+            self.opcode,
+            registers.join(" "),
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer
+        );
+
+        match self.instruction() {
+            Some(instruction) => line.push_str(&format!(" {}", instruction)),
+            None => line.push_str(" ???"),
+        }
+        for (x, before, after) in self.register_deltas() {
+            line.push_str(&format!(" | V{:X} {:02X}->{:02X}", x, before, after));
+        }
+        for write in &self.memory_writes {
+            line.push_str(&format!(" | [{:04X}]={:02X}", write.addr, write.value));
+        }
+        let pixels = self
+            .display_writes
+            .iter()
+            .filter(|write| matches!(write, DisplayWrite::Pixel { .. }))
+            .count();
+        if pixels > 0 {
+            line.push_str(&format!(" | {} pixels", pixels));
+        }
+        for write in &self.display_writes {
+            match write {
+                DisplayWrite::Pixel { .. } => {}
+                DisplayWrite::Clear { planes } => line.push_str(&format!(" | clear {}", planes)),
+                DisplayWrite::Scroll { dx, dy } => {
+                    line.push_str(&format!(" | scroll {},{}", dx, dy))
+                }
+                DisplayWrite::Resolution { hires } => {
+                    line.push_str(if *hires { " | hires" } else { " | lores" })
+                }
+            }
+        }
+
+        line
+    }
+}
+
+/// Which instructions get traced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub pc_range: Option<RangeInclusive<u16>>,
+    /// Instruction names as returned by `Instruction::name`, compared case-insensitively.
+    /// Empty means all instructions.
+    pub instructions: Vec<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instruction: Option<Instruction>) -> bool {
+        let in_range = self
+            .pc_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&pc));
+        let wanted = self.instructions.is_empty()
+            || instruction.is_some_and(|instruction| {
+                self.instructions
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(instruction.name()))
+            });
+        in_range && wanted
+    }
+}
+
+/// Parses `START-END` with decimal or `0x`-prefixed addresses into an inclusive range.
+pub fn parse_pc_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |text: &str| {
+        let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+        parsed.map_err(|_| format!("Invalid address '{}'", text))
+    };
+
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid range '{}', expected START-END", text))?;
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(format!("Invalid range '{}', START is after END", text));
+    }
+    Ok(start..=end)
+}
+
+pub trait TraceSink {
+    fn record(&mut self, entry: &TraceEntry);
+
+    /// Flushes buffered output and reports the first error `record` ran into.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes one `TraceEntry::to_text` line per entry.
+pub struct TextTraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+}
+
+impl<W: Write> TraceSink for TextTraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        if self.error.is_none()
+            && let Err(err) = writeln!(self.out, "{}", entry.to_text())
+        {
+            self.error = Some(err);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Writes the compact binary log that `read_binary_trace` reads back.
+pub struct BinaryTraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinaryTraceWriter<W> {
+    pub fn new(mut out: W) -> Self {
+        let error = out
+            .write_all(TRACE_MAGIC)
+            .and_then(|()| out.write_all(&[TRACE_VERSION]))
+            .err();
+        Self { out, error }
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        if self.error.is_none()
+            && let Err(err) = self.out.write_all(&encode_entry(entry))
+        {
+            self.error = Some(err);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+fn encode_entry(entry: &TraceEntry) -> Vec<u8> {
+    let mut out = Vec::with_capacity(48);
+    out.extend_from_slice(&entry.pc.to_be_bytes());
+    out.extend_from_slice(&entry.opcode.to_be_bytes());
+    match entry.operand {
+        Some(operand) => {
+            out.push(1);
+            out.extend_from_slice(&operand.to_be_bytes());
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&entry.registers_before);
+    out.extend_from_slice(&entry.registers);
+    out.extend_from_slice(&entry.i.to_be_bytes());
+    out.extend_from_slice(&[entry.sp, entry.delay_timer, entry.sound_timer]);
+
+    out.extend_from_slice(&(entry.memory_writes.len() as u16).to_be_bytes());
+    for write in &entry.memory_writes {
+        out.extend_from_slice(&write.addr.to_be_bytes());
+        out.push(write.value);
+    }
+
+    out.extend_from_slice(&(entry.display_writes.len() as u16).to_be_bytes());
+    for write in &entry.display_writes {
+        match *write {
+            DisplayWrite::Pixel { x, y, value } => out.extend_from_slice(&[0, x, y, value]),
+            DisplayWrite::Clear { planes } => out.extend_from_slice(&[1, planes]),
+            DisplayWrite::Scroll { dx, dy } => out.extend_from_slice(&[2, dx as u8, dy as u8]),
+            DisplayWrite::Resolution { hires } => out.extend_from_slice(&[3, hires as u8]),
+        }
+    }
+
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("The trace is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn registers(&mut self) -> Result<[u8; 16], String> {
+        Ok(self.bytes(16)?.try_into().unwrap())
+    }
+}
+
+/// Reads back a log written by `BinaryTraceWriter`.
+pub fn read_binary_trace(bytes: &[u8]) -> Result<Vec<TraceEntry>, String> {
+    let mut input = Reader { bytes, pos: 0 };
+    if input.bytes(TRACE_MAGIC.len()).ok() != Some(TRACE_MAGIC) {
+        return Err("Not a nibble-8 trace".to_string());
+    }
+    let version = input.u8()?;
+    if version != TRACE_VERSION {
+        return Err(format!("Unsupported trace version {}", version));
+    }
+
+    let mut entries = Vec::new();
+    while input.pos < bytes.len() {
+        let pc = input.u16()?;
+        let opcode = input.u16()?;
+        let operand = match input.u8()? {
+            0 => None,
+            _ => Some(input.u16()?),
+        };
+        let registers_before = input.registers()?;
+        let registers = input.registers()?;
+        let i = input.u16()?;
+        let sp = input.u8()?;
+        let delay_timer = input.u8()?;
+        let sound_timer = input.u8()?;
+
+        let mut memory_writes = Vec::new();
+        for _ in 0..input.u16()? {
+            memory_writes.push(MemoryWrite {
+                addr: input.u16()?,
+                value: input.u8()?,
+            });
+        }
+
+        let mut display_writes = Vec::new();
+        for _ in 0..input.u16()? {
+            display_writes.push(match input.u8()? {
+                0 => DisplayWrite::Pixel {
+                    x: input.u8()?,
+                    y: input.u8()?,
+                    value: input.u8()?,
+                },
+                1 => DisplayWrite::Clear {
+                    planes: input.u8()?,
+                },
+                2 => DisplayWrite::Scroll {
+                    dx: input.u8()? as i8,
+                    dy: input.u8()? as i8,
+                },
+                3 => DisplayWrite::Resolution {
+                    hires: input.u8()? != 0,
+                },
+                tag => return Err(format!("Unknown display write {}", tag)),
+            });
+        }
+
+        entries.push(TraceEntry {
+            pc,
+            opcode,
+            operand,
+            registers_before,
+            registers,
+            i,
+            sp,
+            delay_timer,
+            sound_timer,
+            memory_writes,
+            display_writes,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// A sink together with the filter deciding what reaches it.
+pub struct Tracer {
+    pub filter: TraceFilter,
+    sink: Box<dyn TraceSink>,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn TraceSink>, filter: TraceFilter) -> Self {
+        Self { filter, sink }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    pub(crate) fn record(&mut self, entry: &TraceEntry) {
+        self.sink.record(entry);
+    }
+
+    /// Whether `opcode` at `pc` should be traced, `operand` is only needed for `F000 NNNN`.
+    pub(crate) fn wants(&self, pc: u16, opcode: u16, operand: Option<u16>) -> bool {
+        let instruction = match operand {
+            Some(operand) if is_long(opcode) => decode_long(opcode, operand),
+            _ => decode(opcode),
+        };
+        self.filter.matches(pc, instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Bus, Cpu, Platform, Quirks, asm::assemble, cpu::ThreadRngSource, memory::ROM_START,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Lets the test look at the entries after handing the sink to the `Cpu`
+    struct SharedSink(Rc<RefCell<Vec<TraceEntry>>>);
+
+    impl TraceSink for SharedSink {
+        fn record(&mut self, entry: &TraceEntry) {
+            self.0.borrow_mut().push(entry.clone());
+        }
+    }
+
+    fn trace(source: &str, platform: Platform, filter: TraceFilter) -> Vec<TraceEntry> {
+        let program = assemble(source).unwrap();
+        let mut bus = Bus::with_platform(platform);
+        bus.load_rom(&program.bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::default());
+
+        let entries = Rc::new(RefCell::new(Vec::new()));
+        cpu.set_tracer(Some(Tracer::new(
+            Box::new(SharedSink(entries.clone())),
+            filter,
+        )));
+        while cpu.pc() < ROM_START + program.bytes.len() as u16 {
+            cpu.step(&mut bus).unwrap();
+        }
+
+        entries.take()
+    }
+
+    const PROGRAM: &str = "
+        LD V0, 0x42
+        LD I, 0x300
+        LD [I], V0
+        LD V1, 8
+        LD F, V0
+        DRW V1, V1, 1
+        CLS
+    ";
+
+    #[test]
+    fn test_records_state_and_writes() {
+        let entries = trace(PROGRAM, Platform::Chip8, TraceFilter::default());
+        assert_eq!(entries.len(), 7);
+
+        assert_eq!(entries[0].pc, 0x200);
+        assert_eq!(entries[0].opcode, 0x6042);
+        assert_eq!(entries[0].instruction(), Some(Instruction::Load(0, 0x42)));
+        assert_eq!(
+            entries[0].register_deltas().collect::<Vec<_>>(),
+            vec![(0, 0, 0x42)]
+        );
+
+        assert_eq!(entries[2].i, 0x300);
+        assert_eq!(
+            entries[2].memory_writes,
+            vec![MemoryWrite {
+                addr: 0x300,
+                value: 0x42
+            }]
+        );
+
+        // the font glyph for 2 starts with 0xF0, four lit pixels
+        assert_eq!(entries[5].display_writes.len(), 4);
+        assert_eq!(
+            entries[5].display_writes[0],
+            DisplayWrite::Pixel {
+                x: 8,
+                y: 8,
+                value: 1
+            }
+        );
+        assert_eq!(
+            entries[6].display_writes,
+            vec![DisplayWrite::Clear { planes: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let filter = TraceFilter {
+            pc_range: Some(0x202..=0x208),
+            instructions: vec!["load".to_string(), "Draw".to_string()],
+        };
+        let entries = trace(PROGRAM, Platform::Chip8, filter);
+
+        let pcs: Vec<u16> = entries.iter().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, vec![0x206]);
+    }
+
+    #[test]
+    fn test_long_instruction_operand() {
+        let entries = trace(
+            "LD I, LONG 0x1234\nLD V0, 1",
+            Platform::XoChip,
+            TraceFilter::default(),
+        );
+
+        assert_eq!(entries[0].operand, Some(0x1234));
+        assert_eq!(entries[0].i, 0x1234);
+        assert_eq!(entries[1].pc, 0x204);
+    }
+
+    #[test]
+    fn test_text_format() {
+        let entries = trace(PROGRAM, Platform::Chip8, TraceFilter::default());
+
+        let line = entries[2].to_text();
+        assert!(line.starts_with("0204: F055 V0=42 V1=00"));
+        assert!(line.contains("I=0300 SP=0 DT=00 ST=00 ; LD [I], V0 | [0300]=42"));
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let entries = trace(PROGRAM, Platform::Chip8, TraceFilter::default());
+
+        let mut writer = BinaryTraceWriter::new(Vec::new());
+        for entry in &entries {
+            writer.record(entry);
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(read_binary_trace(&writer.out).unwrap(), entries);
+        assert!(read_binary_trace(&writer.out[..writer.out.len() - 1]).is_err());
+        assert!(read_binary_trace(b"nope").is_err());
+    }
+
+    #[test]
+    fn test_parse_pc_range() {
+        assert_eq!(parse_pc_range("0x200-0x2FF"), Ok(0x200..=0x2FF));
+        assert_eq!(parse_pc_range("512-520"), Ok(512..=520));
+        assert!(parse_pc_range("0x300-0x200").is_err());
+        assert!(parse_pc_range("0x300").is_err());
+    }
+}
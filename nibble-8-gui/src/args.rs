@@ -0,0 +1,365 @@
+use nibble_8_core::args::{parse_number, parse_platform, parse_quirks, parse_tickrate};
+use nibble_8_core::audio::{BeeperConfig, Waveform};
+use nibble_8_core::capture::CaptureFormat;
+use nibble_8_core::filter::FilterMode;
+use nibble_8_core::rewind::RewindConfig;
+use nibble_8_core::{Platform, Quirks};
+
+use crate::palette::Palette;
+
+pub const USAGE: &str = "\
+Usage: nibble-8-gui <ROM> [options]
+
+Runs a ROM in a window.
+
+Options:
+  --platform <chip8|schip|xochip>  Machine to emulate (default: from the ROM
+                                   database, else chip8)
+  --quirks <preset>                vip, chip48, schip, xochip or modern
+                                   (default: from the ROM database, else the
+                                   platform's usual quirks)
+  --ipf <N>                        CPU speed in instructions per frame
+                                   (default: from the ROM database, else 10)
+  --rom-db <FILE>                  JSON file of local ROM database overrides
+  --scale <N>                      Window pixels per CHIP-8 pixel, 2-32 (default: 10)
+  --palette <name>                 mono, green, amber, octo or contrast (default:
+                                   from the ROM database, else mono)
+  --colors <RRGGBB,...>            Custom palette: background, foreground and
+                                   optionally the XO-CHIP plane 2 and both planes
+                                   colors
+  --filter <mode>                  Flicker reduction: off, decay, deflicker or sync
+                                   (default: off)
+  --decay <N>                      Phosphor decay over N frames, implies --filter decay
+  --rewind-memory <MB>             Memory the rewind history may use, 1-1024
+                                   (default: 32)
+  --rewind-interval <N>            Keep every Nth frame for rewinding, 1-60
+                                   (default: 1)
+  --capture-seconds <N>            Length of the clips F11 saves (default: 10)
+  --capture-format <gif|apng>      Format of the clips F11 saves (default: gif)
+  --keymap <FILE>                  Key bindings, rewritten by the rebinding screen
+                                   (default: keymap.toml)
+  --key-profile <name>             Key binding profile, default or numpad built in
+                                   (default: from the keymap file, else default)
+  --muted                          Run without sound
+  --waveform <square|sine|saw>     Waveform of the buzzer tone (default: square)
+  --volume <N>                     Buzzer volume in percent, 0-100 (default: 25)
+  --pitch <HZ>                     Buzzer tone frequency, 20-20000 (default: 440)
+  --paused                         Start paused, P resumes
+  -h, --help                       Print this help";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    pub rom_db: Option<String>,
+    pub scale: u32,
+    pub palette: Option<Palette>,
+    pub filter: FilterMode,
+    pub rewind: RewindConfig,
+    pub capture_seconds: u32,
+    pub capture_format: CaptureFormat,
+    pub keymap: String,
+    pub key_profile: Option<String>,
+    pub muted: bool,
+    pub beeper: BeeperConfig,
+    pub paused: bool,
+}
+
+/// Parses the command line, `Ok(None)` means help was requested.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut instructions_per_frame = None;
+    let mut rom_db = None;
+    let mut scale = 10;
+    let mut palette = None;
+    let mut filter = FilterMode::Off;
+    let mut rewind = RewindConfig {
+        memory_budget: 32 * 1024 * 1024,
+        interval: 1,
+    };
+    let mut capture_seconds = 10;
+    let mut capture_format = CaptureFormat::Gif;
+    let mut keymap = "keymap.toml".to_string();
+    let mut key_profile = None;
+    let mut muted = false;
+    let mut beeper = BeeperConfig::default();
+    let mut paused = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--platform" => platform = Some(parse_platform(&value("--platform")?)?),
+            "--quirks" => quirks = Some(parse_quirks(&value("--quirks")?)?),
+            "--ipf" => instructions_per_frame = Some(parse_tickrate(&value("--ipf")?)?),
+            "--rom-db" => rom_db = Some(value("--rom-db")?),
+            "--scale" => {
+                let text = value("--scale")?;
+                scale = parse_number(&text)?;
+                if !(2..=32).contains(&scale) {
+                    return Err(format!("Invalid scale '{}', expected 2-32", text));
+                }
+            }
+            "--palette" => {
+                let name = value("--palette")?;
+                palette = Some(
+                    Palette::from_name(&name)
+                        .ok_or_else(|| format!("Unknown palette '{}'", name))?,
+                );
+            }
+            "--colors" => palette = Some(Palette::parse(&value("--colors")?)?),
+            "--filter" => {
+                let name = value("--filter")?;
+                filter = FilterMode::from_name(&name)
+                    .ok_or_else(|| format!("Unknown filter '{}'", name))?;
+            }
+            "--decay" => {
+                let frames = parse_number(&value("--decay")?)?;
+                filter = FilterMode::Decay { frames };
+            }
+            "--rewind-memory" => {
+                let text = value("--rewind-memory")?;
+                let megabytes: usize = parse_number(&text)?;
+                if !(1..=1024).contains(&megabytes) {
+                    return Err(format!("Invalid rewind memory '{}', expected 1-1024", text));
+                }
+                rewind.memory_budget = megabytes * 1024 * 1024;
+            }
+            "--rewind-interval" => {
+                let text = value("--rewind-interval")?;
+                rewind.interval = parse_number(&text)?;
+                if !(1..=60).contains(&rewind.interval) {
+                    return Err(format!("Invalid rewind interval '{}', expected 1-60", text));
+                }
+            }
+            "--capture-seconds" => {
+                let text = value("--capture-seconds")?;
+                capture_seconds = parse_number(&text)?;
+                if !(1..=600).contains(&capture_seconds) {
+                    return Err(format!("Invalid capture length '{}', expected 1-600", text));
+                }
+            }
+            "--capture-format" => {
+                capture_format = match value("--capture-format")?.as_str() {
+                    "gif" => CaptureFormat::Gif,
+                    "apng" => CaptureFormat::Apng,
+                    other => return Err(format!("Unknown capture format '{}'", other)),
+                }
+            }
+            "--keymap" => keymap = value("--keymap")?,
+            "--key-profile" => key_profile = Some(value("--key-profile")?),
+            "--muted" => muted = true,
+            "--waveform" => {
+                let name = value("--waveform")?;
+                beeper.waveform = Waveform::from_name(&name)
+                    .ok_or_else(|| format!("Unknown waveform '{}'", name))?;
+            }
+            "--volume" => {
+                let text = value("--volume")?;
+                let percent: u32 = parse_number(&text)?;
+                if percent > 100 {
+                    return Err(format!("Invalid volume '{}', expected 0-100", text));
+                }
+                beeper.volume = percent as f32 / 100.0;
+            }
+            "--pitch" => {
+                let text = value("--pitch")?;
+                let hertz: u32 = parse_number(&text)?;
+                if !(20..=20000).contains(&hertz) {
+                    return Err(format!("Invalid pitch '{}', expected 20-20000", text));
+                }
+                beeper.frequency = hertz as f32;
+            }
+            "--paused" => paused = true,
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if rom_path.replace(path.to_string()).is_some() {
+                    return Err("Only one ROM can be given".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        platform,
+        quirks,
+        instructions_per_frame,
+        rom_db,
+        scale,
+        palette,
+        filter,
+        rewind,
+        capture_seconds,
+        capture_format,
+        keymap,
+        key_profile,
+        muted,
+        beeper,
+        paused,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Option<Options>, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse_args(&["snake.ch8"]).unwrap().unwrap();
+
+        assert_eq!(options.rom_path, "snake.ch8");
+        assert_eq!(options.platform, None);
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.instructions_per_frame, None);
+        assert_eq!(options.scale, 10);
+        assert_eq!(options.palette, None);
+        assert_eq!(options.filter, FilterMode::Off);
+        assert_eq!(options.rewind.memory_budget, 32 * 1024 * 1024);
+        assert_eq!(options.rewind.interval, 1);
+        assert_eq!(options.capture_seconds, 10);
+        assert_eq!(options.capture_format, CaptureFormat::Gif);
+        assert_eq!(options.keymap, "keymap.toml");
+        assert_eq!(options.key_profile, None);
+        assert!(!options.muted);
+        assert_eq!(options.beeper, BeeperConfig::default());
+        assert!(!options.paused);
+    }
+
+    #[test]
+    fn test_all_options() {
+        let options = parse_args(&[
+            "--platform",
+            "xochip",
+            "--quirks",
+            "vip",
+            "--ipf",
+            "1000",
+            "--scale",
+            "4",
+            "--palette",
+            "amber",
+            "--decay",
+            "8",
+            "--rewind-memory",
+            "64",
+            "--rewind-interval",
+            "2",
+            "--capture-seconds",
+            "30",
+            "--capture-format",
+            "apng",
+            "--keymap",
+            "keys.toml",
+            "--key-profile",
+            "numpad",
+            "--muted",
+            "--waveform",
+            "sine",
+            "--volume",
+            "50",
+            "--pitch",
+            "880",
+            "--paused",
+            "game.ch8",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(options.platform, Some(Platform::XoChip));
+        assert_eq!(options.quirks, Some(Quirks::cosmac_vip()));
+        assert_eq!(options.instructions_per_frame, Some(1000));
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.palette, Some(Palette::AMBER));
+        assert_eq!(options.filter, FilterMode::Decay { frames: 8 });
+        assert_eq!(
+            options.rewind,
+            RewindConfig {
+                memory_budget: 64 * 1024 * 1024,
+                interval: 2,
+            }
+        );
+        assert_eq!(options.capture_seconds, 30);
+        assert_eq!(options.capture_format, CaptureFormat::Apng);
+        assert_eq!(options.keymap, "keys.toml");
+        assert_eq!(options.key_profile.as_deref(), Some("numpad"));
+        assert!(options.muted);
+        assert_eq!(
+            options.beeper,
+            BeeperConfig {
+                waveform: Waveform::Sine,
+                volume: 0.5,
+                frequency: 880.0,
+                ..BeeperConfig::default()
+            }
+        );
+        assert!(options.paused);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_args(&[]), Err("Missing ROM path".to_string()));
+        assert_eq!(
+            parse_args(&["a.ch8", "--scale", "1"]),
+            Err("Invalid scale '1', expected 2-32".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--palette", "pink"]),
+            Err("Unknown palette 'pink'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--colors", "000000"]),
+            Err("Expected 2 to 4 colors, got 1".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--filter", "blur"]),
+            Err("Unknown filter 'blur'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--rewind-memory", "0"]),
+            Err("Invalid rewind memory '0', expected 1-1024".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--rewind-interval", "61"]),
+            Err("Invalid rewind interval '61', expected 1-60".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--waveform", "noise"]),
+            Err("Unknown waveform 'noise'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--volume", "101"]),
+            Err("Invalid volume '101', expected 0-100".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--pitch", "10"]),
+            Err("Invalid pitch '10', expected 20-20000".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--fullscreen"]),
+            Err("Unknown option '--fullscreen'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--ipf", "1000001"]),
+            Err("Invalid instructions per frame '1000001', expected 1-1000000".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--capture-seconds", "0"]),
+            Err("Invalid capture length '0', expected 1-600".to_string())
+        );
+        assert_eq!(parse_args(&["--help"]), Ok(None));
+    }
+}
@@ -0,0 +1,505 @@
+//! Keyboard bindings for the keypad and the hotkeys.
+//!
+//! The built-in profiles bind scancodes, so they cover the same physical keys on every keyboard
+//! layout. Key names in the config file are resolved through the current layout instead, so `A`
+//! means the key labeled A on an AZERTY keyboard too.
+//!
+//! ```toml
+//! # Used when neither --key-profile nor a ROM entry picks one
+//! profile = "mine"
+//!
+//! # Profiles start out as the built-in profile of the same name, or `default`
+//! [profiles.mine.keypad]
+//! 5 = "Up"
+//! 8 = "Down"
+//! [profiles.mine.hotkeys]
+//! pause = "Space"
+//!
+//! # Per ROM, by file name or SHA-1, applied on top of the profile
+//! [roms."snake.ch8"]
+//! profile = "numpad"
+//! keypad = { 0 = "Return" }
+//! ```
+
+use std::collections::BTreeMap;
+
+use sdl2::keyboard::Scancode;
+use serde::{Deserialize, Serialize};
+
+/// Something a hotkey triggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Quit,
+    Pause,
+    /// Runs a single frame while paused.
+    FrameAdvance,
+    /// Powers the machine off and on again.
+    Reset,
+    SaveState,
+    LoadState,
+    SelectSlot(usize),
+    /// Hold to run time backwards.
+    Rewind,
+    /// Hold to fast-forward.
+    Turbo,
+    /// Halve and double the speed, for slow motion.
+    Slower,
+    Faster,
+    /// Starts a movie recording from power-on, or stops and saves it.
+    RecordMovie,
+    /// Plays the saved movie from power-on.
+    PlayMovie,
+    /// Opens the rebinding screen.
+    Rebind,
+    /// Saves the display as a PNG.
+    Screenshot,
+    /// Saves the last few seconds as an animation.
+    SaveClip,
+}
+
+impl Action {
+    pub const ALL: [Action; 19] = [
+        Action::Quit,
+        Action::Pause,
+        Action::FrameAdvance,
+        Action::Reset,
+        Action::SaveState,
+        Action::LoadState,
+        Action::SelectSlot(0),
+        Action::SelectSlot(1),
+        Action::SelectSlot(2),
+        Action::SelectSlot(3),
+        Action::Rewind,
+        Action::Turbo,
+        Action::Slower,
+        Action::Faster,
+        Action::RecordMovie,
+        Action::PlayMovie,
+        Action::Rebind,
+        Action::Screenshot,
+        Action::SaveClip,
+    ];
+
+    /// The name used in the config file.
+    pub fn name(self) -> String {
+        match self {
+            Action::Quit => "quit".to_string(),
+            Action::Pause => "pause".to_string(),
+            Action::FrameAdvance => "frame_advance".to_string(),
+            Action::Reset => "reset".to_string(),
+            Action::SaveState => "save_state".to_string(),
+            Action::LoadState => "load_state".to_string(),
+            Action::SelectSlot(slot) => format!("slot{}", slot + 1),
+            Action::Rewind => "rewind".to_string(),
+            Action::Turbo => "turbo".to_string(),
+            Action::Slower => "slower".to_string(),
+            Action::Faster => "faster".to_string(),
+            Action::RecordMovie => "record_movie".to_string(),
+            Action::PlayMovie => "play_movie".to_string(),
+            Action::Rebind => "rebind".to_string(),
+            Action::Screenshot => "screenshot".to_string(),
+            Action::SaveClip => "save_clip".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// Which keys drive the keypad and the hotkeys. A key is bound to at most one of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keypad: [Option<Scancode>; 16],
+    hotkeys: BTreeMap<Action, Scancode>,
+}
+
+impl Keymap {
+    /// The 1234/QWER/ASDF/ZXCV block for the keypad.
+    pub fn default_profile() -> Self {
+        Self::with_keypad([
+            Scancode::X,
+            Scancode::Num1,
+            Scancode::Num2,
+            Scancode::Num3,
+            Scancode::Q,
+            Scancode::W,
+            Scancode::E,
+            Scancode::A,
+            Scancode::S,
+            Scancode::D,
+            Scancode::Z,
+            Scancode::C,
+            Scancode::Num4,
+            Scancode::R,
+            Scancode::F,
+            Scancode::V,
+        ])
+    }
+
+    /// The numeric keypad, 1-9 on the top three rows of the CHIP-8 keypad.
+    pub fn numpad_profile() -> Self {
+        Self::with_keypad([
+            Scancode::Kp0,
+            Scancode::Kp7,
+            Scancode::Kp8,
+            Scancode::Kp9,
+            Scancode::Kp4,
+            Scancode::Kp5,
+            Scancode::Kp6,
+            Scancode::Kp1,
+            Scancode::Kp2,
+            Scancode::Kp3,
+            Scancode::KpPeriod,
+            Scancode::KpEnter,
+            Scancode::KpDivide,
+            Scancode::KpMultiply,
+            Scancode::KpMinus,
+            Scancode::KpPlus,
+        ])
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default_profile()),
+            "numpad" => Some(Self::numpad_profile()),
+            _ => None,
+        }
+    }
+
+    fn with_keypad(keypad: [Scancode; 16]) -> Self {
+        let hotkeys = [
+            (Action::Quit, Scancode::Escape),
+            (Action::Pause, Scancode::P),
+            (Action::FrameAdvance, Scancode::Period),
+            (Action::Reset, Scancode::F6),
+            (Action::SaveState, Scancode::F5),
+            (Action::LoadState, Scancode::F9),
+            (Action::SelectSlot(0), Scancode::F1),
+            (Action::SelectSlot(1), Scancode::F2),
+            (Action::SelectSlot(2), Scancode::F3),
+            (Action::SelectSlot(3), Scancode::F4),
+            (Action::Rewind, Scancode::Backspace),
+            (Action::Turbo, Scancode::Tab),
+            (Action::Slower, Scancode::Minus),
+            (Action::Faster, Scancode::Equals),
+            (Action::RecordMovie, Scancode::F7),
+            (Action::PlayMovie, Scancode::F8),
+            (Action::Rebind, Scancode::F10),
+            (Action::SaveClip, Scancode::F11),
+            (Action::Screenshot, Scancode::F12),
+        ];
+        Self {
+            keypad: keypad.map(Some),
+            hotkeys: hotkeys.into_iter().collect(),
+        }
+    }
+
+    /// The keypad key `scancode` is bound to.
+    pub fn keypad_key(&self, scancode: Scancode) -> Option<u8> {
+        self.keypad
+            .iter()
+            .position(|&bound| bound == Some(scancode))
+            .map(|key| key as u8)
+    }
+
+    pub fn keypad_scancode(&self, key: u8) -> Option<Scancode> {
+        self.keypad[key as usize]
+    }
+
+    /// The hotkey action `scancode` is bound to.
+    pub fn action(&self, scancode: Scancode) -> Option<Action> {
+        self.hotkeys
+            .iter()
+            .find(|&(_, &bound)| bound == scancode)
+            .map(|(&action, _)| action)
+    }
+
+    pub fn scancode(&self, action: Action) -> Option<Scancode> {
+        self.hotkeys.get(&action).copied()
+    }
+
+    /// Binds keypad `key` to `scancode`, which loses whatever it was bound to before.
+    pub fn bind_keypad(&mut self, key: u8, scancode: Scancode) {
+        self.unbind(scancode);
+        self.keypad[key as usize] = Some(scancode);
+    }
+
+    /// Binds `action` to `scancode`, which loses whatever it was bound to before.
+    pub fn bind_action(&mut self, action: Action, scancode: Scancode) {
+        self.unbind(scancode);
+        self.hotkeys.insert(action, scancode);
+    }
+
+    fn unbind(&mut self, scancode: Scancode) {
+        for bound in &mut self.keypad {
+            if *bound == Some(scancode) {
+                *bound = None;
+            }
+        }
+        self.hotkeys.retain(|_, &mut bound| bound != scancode);
+    }
+
+    /// Applies the bindings of a config file section on top.
+    fn apply(
+        &mut self,
+        bindings: &Bindings,
+        resolve: &impl Fn(&str) -> Option<Scancode>,
+    ) -> Result<(), String> {
+        let scancode =
+            |name: &str| resolve(name).ok_or_else(|| format!("Unknown key name '{}'", name));
+
+        for (key, name) in &bindings.keypad {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| format!("Invalid keypad key '{}', expected 0-F", key))?;
+            self.bind_keypad(key, scancode(name)?);
+        }
+        for (action, name) in &bindings.hotkeys {
+            let action =
+                Action::from_name(action).ok_or_else(|| format!("Unknown action '{}'", action))?;
+            self.bind_action(action, scancode(name)?);
+        }
+        Ok(())
+    }
+
+    /// All bindings as a config file section.
+    pub fn to_bindings(&self, name: impl Fn(Scancode) -> String) -> Bindings {
+        Bindings {
+            keypad: self
+                .keypad
+                .iter()
+                .enumerate()
+                .filter_map(|(key, bound)| Some((format!("{:X}", key), name((*bound)?))))
+                .collect(),
+            hotkeys: self
+                .hotkeys
+                .iter()
+                .map(|(action, &scancode)| (action.name(), name(scancode)))
+                .collect(),
+        }
+    }
+}
+
+/// A profile or ROM section of the config file. Keys map to key names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    /// Keypad keys `0`-`F`.
+    pub keypad: BTreeMap<String, String>,
+    /// Action names, see `Action::name`.
+    pub hotkeys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomBindings {
+    pub profile: Option<String>,
+    #[serde(flatten)]
+    pub bindings: Bindings,
+}
+
+/// The keymap config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeymapConfig {
+    pub profile: Option<String>,
+    pub profiles: BTreeMap<String, Bindings>,
+    /// By ROM file name or SHA-1.
+    pub roms: BTreeMap<String, RomBindings>,
+}
+
+impl KeymapConfig {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("keymap configs serialize")
+    }
+
+    /// The profile to use: `requested` if given, else the one the ROM entry or the config picks.
+    pub fn profile_name(&self, requested: Option<&str>, rom_ids: &[&str]) -> String {
+        requested
+            .or_else(|| self.rom_entry(rom_ids)?.profile.as_deref())
+            .or(self.profile.as_deref())
+            .unwrap_or("default")
+            .to_string()
+    }
+
+    fn rom_entry(&self, rom_ids: &[&str]) -> Option<&RomBindings> {
+        rom_ids.iter().find_map(|id| self.roms.get(*id))
+    }
+
+    /// Stores a complete keymap where `keymap` would pick it up again: in the ROM's entry if it has
+    /// one, else as the profile.
+    pub fn store(
+        &mut self,
+        keymap: &Keymap,
+        requested: Option<&str>,
+        rom_ids: &[&str],
+        name: impl Fn(Scancode) -> String,
+    ) {
+        let bindings = keymap.to_bindings(name);
+        let profile = self.profile_name(requested, rom_ids);
+        let rom_id = rom_ids.iter().find(|id| self.roms.contains_key(**id));
+        match rom_id {
+            Some(id) => self.roms.get_mut(*id).unwrap().bindings = bindings,
+            None => {
+                self.profiles.insert(profile, bindings);
+            }
+        }
+    }
+
+    /// Builds the keymap for a ROM identified by any of `rom_ids`. `resolve` turns key names into
+    /// scancodes.
+    pub fn keymap(
+        &self,
+        requested: Option<&str>,
+        rom_ids: &[&str],
+        resolve: impl Fn(&str) -> Option<Scancode>,
+    ) -> Result<Keymap, String> {
+        let name = self.profile_name(requested, rom_ids);
+        let user = self.profiles.get(&name);
+        let mut keymap = match (Keymap::builtin(&name), user) {
+            (Some(builtin), _) => builtin,
+            (None, Some(_)) => Keymap::default_profile(),
+            (None, None) => return Err(format!("Unknown key profile '{}'", name)),
+        };
+
+        if let Some(bindings) = user {
+            keymap.apply(bindings, &resolve)?;
+        }
+        if let Some(rom) = self.rom_entry(rom_ids) {
+            keymap.apply(&rom.bindings, &resolve)?;
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the layout lookup, which needs SDL.
+    fn resolve(name: &str) -> Option<Scancode> {
+        match name {
+            "Up" => Some(Scancode::Up),
+            "Down" => Some(Scancode::Down),
+            "Space" => Some(Scancode::Space),
+            "P" => Some(Scancode::P),
+            _ => None,
+        }
+    }
+
+    const CONFIG: &str = r#"
+        profile = "arrows"
+
+        [profiles.arrows.keypad]
+        5 = "Up"
+        8 = "Down"
+
+        [profiles.arrows.hotkeys]
+        pause = "Space"
+
+        [roms."snake.ch8"]
+        profile = "numpad"
+        keypad = { 5 = "Up" }
+    "#;
+
+    #[test]
+    fn test_profiles() {
+        let config = KeymapConfig::from_toml(CONFIG).unwrap();
+
+        let keymap = config.keymap(None, &["tetris.ch8"], resolve).unwrap();
+        assert_eq!(keymap.keypad_key(Scancode::Up), Some(0x5));
+        assert_eq!(keymap.keypad_key(Scancode::W), None);
+        assert_eq!(keymap.keypad_key(Scancode::X), Some(0x0));
+        assert_eq!(keymap.action(Scancode::Space), Some(Action::Pause));
+        assert_eq!(keymap.action(Scancode::P), None);
+
+        let keymap = config.keymap(Some("default"), &[], resolve).unwrap();
+        assert_eq!(keymap, Keymap::default_profile());
+    }
+
+    #[test]
+    fn test_rom_entries() {
+        let config = KeymapConfig::from_toml(CONFIG).unwrap();
+        let keymap = config
+            .keymap(None, &["0123", "snake.ch8"], resolve)
+            .unwrap();
+
+        assert_eq!(keymap.keypad_key(Scancode::Kp7), Some(0x1));
+        assert_eq!(keymap.keypad_key(Scancode::Up), Some(0x5));
+        assert_eq!(keymap.keypad_key(Scancode::Kp5), None);
+    }
+
+    #[test]
+    fn test_binding_steals_the_key() {
+        let mut keymap = Keymap::default_profile();
+        keymap.bind_keypad(0x5, Scancode::P);
+        assert_eq!(keymap.keypad_key(Scancode::P), Some(0x5));
+        assert_eq!(keymap.action(Scancode::P), None);
+        assert_eq!(keymap.scancode(Action::Pause), None);
+
+        keymap.bind_action(Action::Pause, Scancode::X);
+        assert_eq!(keymap.keypad_scancode(0x0), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut keymap = Keymap::default_profile();
+        keymap.bind_keypad(0x5, Scancode::Up);
+        keymap.bind_action(Action::Rewind, Scancode::Down);
+
+        let mut config = KeymapConfig::default();
+        config.profiles.insert(
+            "mine".to_string(),
+            keymap.to_bindings(|scancode| scancode.name().to_string()),
+        );
+        let config = KeymapConfig::from_toml(&config.to_toml()).unwrap();
+
+        let resolved = config
+            .keymap(Some("mine"), &[], Scancode::from_name)
+            .unwrap();
+        assert_eq!(resolved, keymap);
+    }
+
+    #[test]
+    fn test_store_prefers_the_rom_entry() {
+        let mut config = KeymapConfig::from_toml(CONFIG).unwrap();
+        let mut keymap = Keymap::numpad_profile();
+        keymap.bind_keypad(0x0, Scancode::Space);
+        let name = |scancode: Scancode| scancode.name().to_string();
+
+        config.store(&keymap, None, &["snake.ch8"], name);
+        let resolve = |key: &str| Scancode::from_name(key);
+        assert_eq!(
+            config.keymap(None, &["snake.ch8"], resolve),
+            Ok(keymap.clone())
+        );
+
+        config.store(&keymap, None, &["tetris.ch8"], name);
+        assert_eq!(config.profiles["arrows"], keymap.to_bindings(name));
+    }
+
+    #[test]
+    fn test_errors() {
+        let config = KeymapConfig::from_toml("[profiles.x.keypad]\nG = \"Up\"").unwrap();
+        assert_eq!(
+            config.keymap(Some("x"), &[], resolve),
+            Err("Invalid keypad key 'G', expected 0-F".to_string())
+        );
+        let config = KeymapConfig::from_toml("[profiles.x.hotkeys]\nfly = \"Up\"").unwrap();
+        assert_eq!(
+            config.keymap(Some("x"), &[], resolve),
+            Err("Unknown action 'fly'".to_string())
+        );
+        assert_eq!(
+            config.keymap(Some("nope"), &[], resolve),
+            Err("Unknown key profile 'nope'".to_string())
+        );
+        assert!(KeymapConfig::from_toml("profile = [").is_err());
+    }
+}
@@ -1,120 +1,662 @@
 extern crate sdl2;
 
-use nibble_8_core::cpu::ThreadRngSource;
-use nibble_8_core::memory::{SCREEN_HEIGHT, SCREEN_WIDTH};
-use nibble_8_core::{Bus, Cpu};
+mod args;
+mod keymap;
+mod palette;
+mod rebind;
+
+use args::USAGE;
+use keymap::{Action, Keymap, KeymapConfig};
+use nibble_8_core::audio::{AudioSink, Beeper, BeeperConfig, NullSink, SoundTick};
+use nibble_8_core::capture::{
+    CaptureError, CaptureFormat, FRAME_RATE, Frame, FrameExporter, FrameHistory,
+};
+use nibble_8_core::database::{self, RomDatabase, RomProfile};
+use nibble_8_core::filter::DisplayFilter;
+use nibble_8_core::memory::{
+    HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, KEY_COUNT, RPL_FLAG_COUNT, SCREEN_HEIGHT,
+    SCREEN_WIDTH, load_rpl_flags, rpl_flags_path, save_rpl_flags,
+};
+use nibble_8_core::movie::{Movie, MoviePlayer, MovieRecorder};
+use nibble_8_core::rewind::Rewind;
+use nibble_8_core::rng::Pcg32Source;
+use nibble_8_core::scheduler::{Scheduler, SystemClock};
+use nibble_8_core::{Bus, Cpu, MachineState};
+use palette::Palette;
+use rebind::Rebinding;
+use sdl2::Sdl;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
-use sdl2::pixels::Color;
+use sdl2::keyboard::{KeyboardState, Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use std::fs::read;
-use std::time::Duration;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::fs::{read, read_to_string, write};
+use std::path::Path;
+use std::process::ExitCode;
+
+const SAMPLE_RATE: i32 = 44100;
+/// Speed while `Action::Turbo` is held.
+const FAST_FORWARD_SPEED: f64 = 4.0;
+
+pub fn main() -> ExitCode {
+    let options = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom_vec = match read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.rom_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut rom_database = RomDatabase::bundled();
+    if let Some(path) = &options.rom_db
+        && let Err(err) = read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                rom_database
+                    .load_overrides(&json)
+                    .map_err(|err| err.to_string())
+            })
+    {
+        eprintln!("error: Failed to read '{}': {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    let profile = rom_database.rom_profile(&rom_vec).with_overrides(
+        options.platform,
+        options.quirks,
+        options.instructions_per_frame,
+    );
+    if let Some(title) = &profile.title {
+        println!("{}", title);
+    }
+    for (action, key) in &profile.key_hints {
+        println!("  {}: {:X}", action, key);
+    }
+
+    let (mut cpu, mut bus) = match power_on(&rom_vec, &profile) {
+        Ok(machine) => machine,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Saved whenever the ROM changes them. Movies start with the flags clear and what they do to
+    // them isn't saved, until a reset goes back to the saved ones.
+    let rpl_path = rpl_flags_path(&options.rom_path);
+    let mut saving_rpl_flags = true;
+    let mut rpl_flags = load_rpl_flags(&rpl_path).unwrap_or_else(|err| {
+        eprintln!("Failed to read '{}': {}", rpl_path, err);
+        [0; RPL_FLAG_COUNT]
+    });
+    bus.set_rpl_flags(&rpl_flags);
+    let instructions_per_frame = profile.tickrate;
+    let palette = options
+        .palette
+        .or_else(|| Palette::from_profile(&profile))
+        .unwrap_or(Palette::MONO);
 
-pub fn main() {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let window = video_subsystem
-        .window("Nibble-8", 640, 320)
+        .window(
+            "Nibble-8",
+            SCREEN_WIDTH as u32 * options.scale,
+            SCREEN_HEIGHT as u32 * options.scale,
+        )
         .position_centered()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            HIRES_SCREEN_WIDTH as u32,
+            HIRES_SCREEN_HEIGHT as u32,
+        )
+        .unwrap();
+
+    let mut audio: Box<dyn AudioSink> = if options.muted {
+        Box::new(NullSink)
+    } else {
+        match open_audio(&sdl_context, options.beeper) {
+            Ok(sink) => Box::new(sink),
+            Err(err) => {
+                eprintln!("Audio disabled: {}", err);
+                Box::new(NullSink)
+            }
+        }
+    };
 
-    let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()));
-    let mut bus = Bus::new();
-    let rom_vec = read("./roms/mySnake.ch8").expect("Failed to read ROM file");
-    bus.load_rom(&rom_vec).unwrap();
+    let mut keymap_config = if Path::new(&options.keymap).exists() {
+        match read_to_string(&options.keymap)
+            .map_err(|err| err.to_string())
+            .and_then(|text| KeymapConfig::from_toml(&text))
+        {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("error: Failed to read '{}': {}", options.keymap, err);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        KeymapConfig::default()
+    };
+    let rom_sha1 = database::sha1_hex(&bus.rom_sha1().expect("the ROM is loaded"));
+    let rom_file_name = Path::new(&options.rom_path)
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let rom_ids = [rom_sha1.as_str(), rom_file_name.as_str()];
+    let mut keymap =
+        match keymap_config.keymap(options.key_profile.as_deref(), &rom_ids, scancode_from_name) {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                eprintln!("error: {}: {}", options.keymap, err);
+                return ExitCode::FAILURE;
+            }
+        };
 
-    canvas.set_draw_color(Color::RGB(0, 255, 255));
+    canvas.set_draw_color(palette.background());
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut fault = None;
+    let mut save_slot = 0;
+    let mut rewind = Rewind::new(options.rewind);
+    let mut rewinding = false;
+    let mut recorder: Option<MovieRecorder> = None;
+    let mut player: Option<MoviePlayer> = None;
+    let mut rebinding: Option<Rebinding> = None;
+    let mut filter = DisplayFilter::new(options.filter);
+    filter.reset(&bus);
+    let mut history = FrameHistory::new(options.capture_seconds);
+    let exporter = FrameExporter::new(options.scale, palette.rgb());
+    let mut scheduler = Scheduler::new(Box::new(SystemClock::new()), instructions_per_frame * 60);
+    scheduler.set_paused(options.paused);
+    let mut speed: f64 = 1.0;
     'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    scancode: Some(Scancode::Escape),
-                    ..
-                } => break 'running,
+        let mut frame_needs_redraw = false;
+        // The keyboard state can't be read while polling events
+        let mut resync_keypad = false;
 
+        for event in event_pump.poll_iter() {
+            let (scancode, repeat) = match event {
+                Event::Quit { .. } => break 'running,
                 Event::KeyDown {
-                    scancode: Some(k),
-                    repeat: false,
+                    scancode: Some(scancode),
+                    repeat,
                     ..
+                } => (scancode, repeat),
+                Event::KeyUp {
+                    scancode: Some(k), ..
                 } => {
-                    if let Some(chip8_key) = map_keycode_to_chip8(k) {
+                    if player.is_none()
+                        && rebinding.is_none()
+                        && let Some(chip8_key) = keymap.keypad_key(k)
+                    {
+                        bus.set_key(chip8_key, false);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if let Some(screen) = &mut rebinding {
+                frame_needs_redraw = true;
+                if repeat || !screen.press(scancode) {
+                    continue;
+                }
+                if let Some(rebound) = rebinding.take().and_then(Rebinding::finish) {
+                    keymap_config.store(
+                        &rebound,
+                        options.key_profile.as_deref(),
+                        &rom_ids,
+                        scancode_name,
+                    );
+                    match write(&options.keymap, keymap_config.to_toml()) {
+                        Ok(()) => println!("Saved the key bindings to {}", options.keymap),
+                        Err(err) => eprintln!("Failed to save the key bindings: {}", err),
+                    }
+                    keymap = rebound;
+                }
+                resync_keypad = true;
+                continue;
+            }
+
+            let action = match keymap.action(scancode) {
+                Some(Action::FrameAdvance) => {
+                    scheduler.frame_advance();
+                    continue;
+                }
+                Some(_) if repeat => continue,
+                Some(action) => action,
+                None => {
+                    if player.is_none()
+                        && !repeat
+                        && let Some(chip8_key) = keymap.keypad_key(scancode)
+                    {
                         bus.set_key(chip8_key, true);
                     }
+                    continue;
                 }
+            };
 
-                Event::KeyUp {
-                    scancode: Some(k), ..
-                } => {
-                    if let Some(chip8_key) = map_keycode_to_chip8(k) {
-                        bus.set_key(chip8_key, false);
+            match action {
+                Action::Quit => break 'running,
+
+                Action::SaveState => match quick_save(&cpu, &bus, &options.rom_path, save_slot) {
+                    Ok(()) => println!("Saved state to slot {}", save_slot + 1),
+                    Err(err) => eprintln!("Failed to save slot {}: {}", save_slot + 1, err),
+                },
+
+                // Loading a state would desync the movie being recorded or played, like rewinding
+                Action::LoadState if recorder.is_some() || player.is_some() => {
+                    eprintln!("Can't load a state while a movie is recording or playing");
+                }
+
+                Action::LoadState => {
+                    match quick_load(&mut cpu, &mut bus, &options.rom_path, save_slot) {
+                        Ok(()) => {
+                            println!("Loaded state from slot {}", save_slot + 1);
+                            fault = None;
+                            filter.reset(&bus);
+                            frame_needs_redraw = true;
+                        }
+                        Err(err) => eprintln!("Failed to load slot {}: {}", save_slot + 1, err),
+                    }
+                }
+
+                Action::SelectSlot(slot) => {
+                    save_slot = slot;
+                    println!("Selected save slot {}", save_slot + 1);
+                }
+
+                Action::Reset => {
+                    // The ROM already loaded once, so it fits
+                    (cpu, bus) = power_on(&rom_vec, &profile).unwrap();
+                    bus.set_rpl_flags(&rpl_flags);
+                    saving_rpl_flags = true;
+                    scheduler.set_instructions_per_second(instructions_per_frame * 60);
+                    scheduler.set_cycles_per_tick(None);
+                    if recorder.take().is_some() {
+                        println!("Discarded the movie being recorded");
+                    }
+                    player = None;
+                    rewind.clear();
+                    fault = None;
+                    filter.reset(&bus);
+                    frame_needs_redraw = true;
+                    resync_keypad = true;
+                    println!("Reset");
+                }
+
+                Action::RecordMovie => match recorder.take() {
+                    Some(recorder) => {
+                        let movie = recorder.finish();
+                        match write(movie_path(&options.rom_path), movie.to_bytes()) {
+                            Ok(()) => println!("Saved {} frame movie", movie.frames.len()),
+                            Err(err) => eprintln!("Failed to save the movie: {}", err),
+                        }
+                    }
+                    None => {
+                        (cpu, bus) = power_on(&rom_vec, &profile).unwrap();
+                        scheduler.set_instructions_per_second(instructions_per_frame * 60);
+                        scheduler.set_cycles_per_tick(None);
+                        saving_rpl_flags = false;
+                        recorder = Some(MovieRecorder::new(
+                            &cpu,
+                            &bus,
+                            &rom_vec,
+                            instructions_per_frame,
+                        ));
+                        player = None;
+                        rewind.clear();
+                        fault = None;
+                        filter.reset(&bus);
+                        frame_needs_redraw = true;
+                        println!("Recording a movie from power-on");
+                    }
+                },
+
+                Action::PlayMovie => match load_movie(&rom_vec, &options.rom_path) {
+                    Ok((machine, movie)) => {
+                        (cpu, bus) = machine;
+                        scheduler.set_instructions_per_second(movie.instructions_per_frame * 60);
+                        scheduler.set_cycles_per_tick(movie.timing.cycles_per_tick());
+                        saving_rpl_flags = false;
+                        player = Some(MoviePlayer::new(movie));
+                        recorder = None;
+                        rewind.clear();
+                        fault = None;
+                        filter.reset(&bus);
+                        frame_needs_redraw = true;
+                        println!("Playing the movie");
+                    }
+                    Err(err) => eprintln!("Failed to play the movie: {}", err),
+                },
+
+                Action::Pause => scheduler.set_paused(!scheduler.is_paused()),
+
+                Action::Slower | Action::Faster => {
+                    let factor = if action == Action::Faster { 2.0 } else { 0.5 };
+                    speed = (speed * factor).clamp(0.125, 8.0);
+                    println!("Speed {}x", speed);
+                }
+
+                Action::Screenshot => {
+                    let image = exporter.png(&Frame::capture(&bus));
+                    match save_capture(image, &options.rom_path, "png") {
+                        Ok(path) => println!("Saved a screenshot to {}", path),
+                        Err(err) => eprintln!("Failed to save the screenshot: {}", err),
+                    }
+                }
+
+                Action::SaveClip => {
+                    let (image, extension) = match options.capture_format {
+                        CaptureFormat::Apng => (exporter.apng(&history), "apng"),
+                        _ => (exporter.gif(&history), "gif"),
+                    };
+                    match save_capture(image, &options.rom_path, extension) {
+                        Ok(path) => println!(
+                            "Saved the last {:.1} seconds to {}",
+                            history.ticks() as f64 / FRAME_RATE as f64,
+                            path
+                        ),
+                        Err(err) => eprintln!("Failed to save the clip: {}", err),
                     }
                 }
 
-                _ => {}
+                Action::Rebind => {
+                    rebinding = Some(Rebinding::new(&keymap));
+                    // Keys held now would otherwise stay pressed
+                    for key in 0..KEY_COUNT as u8 {
+                        bus.set_key(key, false);
+                    }
+                    frame_needs_redraw = true;
+                }
+
+                // Held rather than pressed, see below
+                Action::Rewind | Action::Turbo | Action::FrameAdvance => {}
             }
         }
 
-        let mut frame_needs_redraw = false;
+        if resync_keypad {
+            sync_keypad(&mut bus, &event_pump.keyboard_state(), &keymap);
+        }
 
-        for _ in 0..10 {
-            let opcode = cpu.fetch(&bus);
-            if cpu.execute(opcode, &mut bus) {
-                frame_needs_redraw = true;
+        let held = |action: Action| {
+            rebinding.is_none()
+                && keymap.scancode(action).is_some_and(|scancode| {
+                    event_pump.keyboard_state().is_scancode_pressed(scancode)
+                })
+        };
+
+        // Rewinding would desync the movie being recorded or played
+        let rewind_held = recorder.is_none() && player.is_none() && held(Action::Rewind);
+        if rewind_held != rewinding {
+            rewinding = rewind_held;
+            // Redraw to show or hide the indicator
+            frame_needs_redraw = true;
+            if !rewinding {
+                // The restored state holds the keys of the past, not the ones held now
+                sync_keypad(&mut bus, &event_pump.keyboard_state(), &keymap);
             }
         }
 
-        cpu.decrease_timers();
+        scheduler.set_speed(if held(Action::Turbo) {
+            speed * FAST_FORWARD_SPEED
+        } else {
+            speed
+        });
 
-        if frame_needs_redraw {
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
-            canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-            for y in 0..SCREEN_HEIGHT {
-                for x in 0..SCREEN_WIDTH {
-                    if bus.get_pixel(x, y) == 1 {
-                        let rect = Rect::new((x * 10) as i32, (y * 10) as i32, 10, 10);
-                        canvas.fill_rect(rect).unwrap();
+        let mut due_ticks = scheduler.due_ticks();
+        if rebinding.is_some() {
+            // The machine stands still on the rebinding screen
+            due_ticks = 0;
+        }
+        for _ in 0..due_ticks {
+            if rewinding {
+                if rewind.rewind_one_frame(&mut cpu, &mut bus).is_some() {
+                    fault = None;
+                    filter.reset(&bus);
+                    frame_needs_redraw = true;
+                }
+                audio.tick(SoundTick::default());
+                continue;
+            }
+
+            if let Some(movie) = &mut player
+                && !movie.feed(&mut bus)
+            {
+                println!("Movie finished after {} frames", movie.frame());
+                player = None;
+                scheduler.set_instructions_per_second(instructions_per_frame * 60);
+                sync_keypad(&mut bus, &event_pump.keyboard_state(), &keymap);
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.record_frame(&bus);
+            }
+
+            // A halted machine has nothing left to draw
+            let mut settled = true;
+            if fault.is_none() {
+                match scheduler.run_tick(&mut cpu, &mut bus) {
+                    Ok(outcome) => {
+                        settled = outcome.settled;
+                        // Once per timer tick, so that the tone follows the timer
+                        audio.tick(outcome.sound);
+                    }
+                    Err(err) => {
+                        eprintln!("Emulation halted: {}", err);
+                        fault = Some(err);
+                        audio.tick(SoundTick::default());
                     }
                 }
+            } else {
+                audio.tick(SoundTick::of(&cpu, &bus));
+                cpu.decrease_timers();
+            }
+            frame_needs_redraw |= filter.push(&bus, settled);
+            history.push(&bus);
+            rewind.record_frame(&cpu, &bus);
+        }
+
+        if saving_rpl_flags && bus.rpl_flags() != &rpl_flags {
+            rpl_flags = *bus.rpl_flags();
+            if let Err(err) = save_rpl_flags(&rpl_path, &rpl_flags) {
+                eprintln!("Failed to save the RPL flags: {}", err);
+            }
+        }
+
+        let title = if let Some(screen) = &rebinding {
+            format!("Nibble-8 - {}", screen.prompt(scancode_name))
+        } else if rewinding {
+            "Nibble-8 - rewinding".to_string()
+        } else if scheduler.is_paused() {
+            "Nibble-8 - paused".to_string()
+        } else if recorder.is_some() {
+            "Nibble-8 - recording movie".to_string()
+        } else if player.is_some() {
+            "Nibble-8 - playing movie".to_string()
+        } else if let Some(err) = &fault {
+            format!("Nibble-8 - halted: {}", err)
+        } else if cpu.is_halted() {
+            "Nibble-8 - exited".to_string()
+        } else if scheduler.speed() != 1.0 {
+            format!("Nibble-8 - {}x", scheduler.speed())
+        } else {
+            "Nibble-8".to_string()
+        };
+        if canvas.window().title() != title {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+
+        if frame_needs_redraw {
+            // The texture fits hi-res, lo-res only uses its top left corner
+            let width = filter.width();
+            let height = filter.output().len() / width;
+            let area = Rect::new(0, 0, width as u32, height as u32);
+            texture
+                .with_lock(area, |pixels, pitch| {
+                    palette.render(filter.output(), width, pixels, pitch)
+                })
+                .unwrap();
+            canvas.copy(&texture, area, None).unwrap();
+
+            if rewinding {
+                draw_rewind_indicator(&mut canvas);
+            }
+            if let Some(screen) = &rebinding {
+                screen.draw(&mut canvas, palette);
             }
             canvas.present();
         }
 
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        ::std::thread::sleep(scheduler.until_next_tick());
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Two red "<<" arrows in the top left corner.
+fn draw_rewind_indicator(canvas: &mut Canvas<Window>) {
+    canvas.set_draw_color(Color::RGB(255, 0, 0));
+    for arrow in 0..2 {
+        let tip = 8 + arrow * 12;
+        for column in 0..12 {
+            let half_height = column / 2;
+            let rect = Rect::new(
+                tip + column,
+                16 - half_height,
+                1,
+                1 + 2 * half_height as u32,
+            );
+            canvas.fill_rect(rect).unwrap();
+        }
     }
 }
 
-fn map_keycode_to_chip8(k: Scancode) -> Option<u8> {
-    Some(match k {
-        Scancode::Num1 => 0x1,
-        Scancode::Num2 => 0x2,
-        Scancode::Num3 => 0x3,
-        Scancode::Num4 => 0xC,
-
-        Scancode::Q => 0x4,
-        Scancode::W => 0x5,
-        Scancode::E => 0x6,
-        Scancode::R => 0xD,
-
-        Scancode::A => 0x7,
-        Scancode::S => 0x8,
-        Scancode::D => 0x9,
-        Scancode::F => 0xE,
-
-        Scancode::Z => 0xA,
-        Scancode::X => 0x0,
-        Scancode::C => 0xB,
-        Scancode::V => 0xF,
-
-        _ => return None,
-    })
+struct BeeperCallback(Beeper);
+
+impl AudioCallback for BeeperCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+/// Hands the sound state to the SDL audio callback.
+struct SdlSink(AudioDevice<BeeperCallback>);
+
+impl AudioSink for SdlSink {
+    fn tick(&mut self, sound: SoundTick) {
+        self.0.lock().0.push_tick(sound);
+    }
+}
+
+fn open_audio(sdl_context: &Sdl, config: BeeperConfig) -> Result<SdlSink, String> {
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(512),
+    };
+    let device = sdl_context.audio()?.open_playback(None, &desired, |spec| {
+        BeeperCallback(Beeper::new(config, spec.freq as u32))
+    })?;
+    device.resume();
+    Ok(SdlSink(device))
+}
+
+/// Sets the CHIP-8 keypad to the keys currently held on the keyboard.
+fn sync_keypad(bus: &mut Bus, keyboard: &KeyboardState, keymap: &Keymap) {
+    for key in 0..KEY_COUNT as u8 {
+        let held = keymap
+            .keypad_scancode(key)
+            .is_some_and(|scancode| keyboard.is_scancode_pressed(scancode));
+        bus.set_key(key, held);
+    }
+}
+
+/// Resolves a key name through the current keyboard layout, falling back to scancode names.
+fn scancode_from_name(name: &str) -> Option<Scancode> {
+    Keycode::from_name(name)
+        .and_then(Scancode::from_keycode)
+        .or_else(|| Scancode::from_name(name))
+}
+
+/// The name of the key in the current keyboard layout, the inverse of `scancode_from_name`.
+fn scancode_name(scancode: Scancode) -> String {
+    Keycode::from_scancode(scancode)
+        .map(|keycode| keycode.name())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| scancode.name().to_string())
+}
+
+fn power_on(rom: &[u8], profile: &RomProfile) -> Result<(Cpu, Bus), String> {
+    // Seeded so that save states and movies capture the RNG
+    profile.power_on(rom, Box::new(Pcg32Source::random()))
+}
+
+fn movie_path(rom_path: &str) -> String {
+    format!("{}.n8m", rom_path)
+}
+
+fn load_movie(rom: &[u8], rom_path: &str) -> Result<((Cpu, Bus), Movie), String> {
+    let bytes = read(movie_path(rom_path)).map_err(|err| err.to_string())?;
+    let movie = Movie::from_bytes(&bytes).map_err(|err| err.to_string())?;
+    let machine = movie
+        .power_on(rom, Box::new(Pcg32Source::random()))
+        .map_err(|err| err.to_string())?;
+    Ok((machine, movie))
+}
+
+/// Writes a screenshot or clip next to the ROM, numbered so that earlier ones are kept.
+fn save_capture(
+    image: Result<Vec<u8>, CaptureError>,
+    rom_path: &str,
+    extension: &str,
+) -> Result<String, String> {
+    let image = image.map_err(|err| err.to_string())?;
+    let path = (1..)
+        .map(|number| format!("{}.{}.{}", rom_path, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+    write(&path, image).map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+fn state_path(rom_path: &str, slot: usize) -> String {
+    format!("{}.state{}", rom_path, slot + 1)
+}
+
+fn quick_save(cpu: &Cpu, bus: &Bus, rom_path: &str, slot: usize) -> Result<(), String> {
+    let state = MachineState {
+        cpu: cpu.snapshot(),
+        bus: bus.snapshot(),
+    };
+    write(state_path(rom_path, slot), state.to_bytes()).map_err(|err| err.to_string())
+}
+
+fn quick_load(cpu: &mut Cpu, bus: &mut Bus, rom_path: &str, slot: usize) -> Result<(), String> {
+    let bytes = read(state_path(rom_path, slot)).map_err(|err| err.to_string())?;
+    let state = MachineState::from_bytes(&bytes).map_err(|err| err.to_string())?;
+    cpu.restore(&state.cpu);
+    bus.restore(&state.bus);
+    Ok(())
 }
@@ -0,0 +1,218 @@
+use nibble_8_core::database::RomProfile;
+use nibble_8_core::filter::FULL;
+use sdl2::pixels::Color;
+
+/// Display colors indexed by the bitmask of lit planes: the background, plane 1, plane 2 and both
+/// planes. Plain CHIP-8 and SUPER-CHIP only use the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub const MONO: Palette = Palette {
+        colors: [
+            Color::RGB(0, 0, 0),
+            Color::RGB(255, 255, 255),
+            Color::RGB(85, 85, 85),
+            Color::RGB(170, 170, 170),
+        ],
+    };
+    /// Green phosphor monitor.
+    pub const GREEN: Palette = Palette {
+        colors: [
+            Color::RGB(0, 20, 0),
+            Color::RGB(51, 255, 51),
+            Color::RGB(20, 120, 20),
+            Color::RGB(170, 255, 170),
+        ],
+    };
+    /// Amber phosphor monitor.
+    pub const AMBER: Palette = Palette {
+        colors: [
+            Color::RGB(20, 10, 0),
+            Color::RGB(255, 176, 0),
+            Color::RGB(130, 80, 0),
+            Color::RGB(255, 220, 140),
+        ],
+    };
+    /// The defaults of the Octo IDE.
+    pub const OCTO: Palette = Palette {
+        colors: [
+            Color::RGB(0x99, 0x66, 0x00),
+            Color::RGB(0xFF, 0xCC, 0x00),
+            Color::RGB(0xFF, 0x66, 0x00),
+            Color::RGB(0x66, 0x22, 0x00),
+        ],
+    };
+    pub const HIGH_CONTRAST: Palette = Palette {
+        colors: [
+            Color::RGB(0, 0, 0),
+            Color::RGB(255, 255, 255),
+            Color::RGB(255, 255, 0),
+            Color::RGB(0, 255, 255),
+        ],
+    };
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mono" => Some(Self::MONO),
+            "green" => Some(Self::GREEN),
+            "amber" => Some(Self::AMBER),
+            "octo" => Some(Self::OCTO),
+            "contrast" => Some(Self::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    /// A palette from the background and foreground color, optionally followed by the plane 2 and
+    /// both planes colors. Plane 2 defaults to halfway between the first two, both planes to the
+    /// foreground. `None` if there are fewer than two colors.
+    pub fn from_colors(colors: &[Color]) -> Option<Self> {
+        let (&background, &foreground) = (colors.first()?, colors.get(1)?);
+        let blend = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+        let halfway = Color::RGB(
+            blend(background.r, foreground.r),
+            blend(background.g, foreground.g),
+            blend(background.b, foreground.b),
+        );
+        Some(Palette {
+            colors: [
+                background,
+                foreground,
+                colors.get(2).copied().unwrap_or(halfway),
+                colors.get(3).copied().unwrap_or(foreground),
+            ],
+        })
+    }
+
+    /// The ROM database colors, `None` if there are fewer than two.
+    pub fn from_profile(profile: &RomProfile) -> Option<Self> {
+        let colors: Vec<Color> = profile
+            .colors
+            .iter()
+            .map(|&[r, g, b]| Color::RGB(r, g, b))
+            .collect();
+        Self::from_colors(&colors)
+    }
+
+    /// Parses comma separated `RRGGBB` colors, see `from_colors`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let colors = text
+            .split(',')
+            .map(|color| {
+                let hex = color.trim().trim_start_matches('#');
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) if hex.len() == 6 => {
+                        Ok(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+                    }
+                    _ => Err(format!("Invalid color '{}', expected RRGGBB", color)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !(2..=4).contains(&colors.len()) {
+            return Err(format!("Expected 2 to 4 colors, got {}", colors.len()));
+        }
+        Ok(Self::from_colors(&colors).unwrap())
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    /// The colors as a `FrameExporter` palette.
+    pub fn rgb(&self) -> [[u8; 3]; 4] {
+        self.colors.map(|color| [color.r, color.g, color.b])
+    }
+
+    /// The color of a pixel with the given plane brightnesses, mixing the four colors.
+    pub fn blend(&self, [plane1, plane2]: [u8; 2]) -> Color {
+        let (a, b, full) = (plane1 as u32, plane2 as u32, FULL as u32);
+        let weights = [
+            (full - a) * (full - b),
+            a * (full - b),
+            (full - a) * b,
+            a * b,
+        ];
+        let channel = |value: fn(&Color) -> u8| {
+            let sum: u32 = (self.colors.iter())
+                .zip(weights)
+                .map(|(color, weight)| value(color) as u32 * weight)
+                .sum();
+            (sum / (full * full)) as u8
+        };
+        Color::RGB(channel(|c| c.r), channel(|c| c.g), channel(|c| c.b))
+    }
+
+    /// Converts `DisplayFilter` output of `width` pixels per row to RGB24 rows `pitch` bytes apart.
+    pub fn render(&self, display: &[[u8; 2]], width: usize, pixels: &mut [u8], pitch: usize) {
+        for (row, line) in display.chunks(width).enumerate() {
+            let out = &mut pixels[row * pitch..][..width * 3];
+            for (&planes, rgb) in line.iter().zip(out.chunks_exact_mut(3)) {
+                let color = self.blend(planes);
+                rgb.copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_colors() {
+        let palette = Palette::from_colors(&[Color::RGB(0, 0, 0), Color::RGB(200, 100, 50)]);
+        assert_eq!(
+            palette.unwrap().colors,
+            [
+                Color::RGB(0, 0, 0),
+                Color::RGB(200, 100, 50),
+                Color::RGB(100, 50, 25),
+                Color::RGB(200, 100, 50),
+            ]
+        );
+        assert_eq!(Palette::from_colors(&[Color::RGB(0, 0, 0)]), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Palette::parse("996600,#FFCC00, ff6600,662200"),
+            Ok(Palette::OCTO)
+        );
+        assert_eq!(
+            Palette::parse("000000"),
+            Err("Expected 2 to 4 colors, got 1".to_string())
+        );
+        assert_eq!(
+            Palette::parse("000000,fff"),
+            Err("Invalid color 'fff', expected RRGGBB".to_string())
+        );
+    }
+
+    #[test]
+    fn test_blend() {
+        let palette = Palette::from_colors(&[Color::RGB(0, 0, 0), Color::RGB(255, 200, 100)]);
+        let palette = palette.unwrap();
+        assert_eq!(palette.blend([FULL, 0]), Color::RGB(255, 200, 100));
+        assert_eq!(palette.blend([0, 0]), Color::RGB(0, 0, 0));
+        assert_eq!(palette.blend([51, 0]), Color::RGB(51, 40, 20));
+    }
+
+    #[test]
+    fn test_render() {
+        let display = [[0, 0], [FULL, 0], [0, FULL], [FULL, FULL]];
+        let mut pixels = [0xAA; 2 * 8];
+        Palette::OCTO.render(&display, 2, &mut pixels, 8);
+
+        assert_eq!(pixels[..6], [0x99, 0x66, 0x00, 0xFF, 0xCC, 0x00]);
+        // Padding at the end of a row is left alone
+        assert_eq!(pixels[6..8], [0xAA, 0xAA]);
+        assert_eq!(pixels[8..14], [0xFF, 0x66, 0x00, 0x66, 0x22, 0x00]);
+    }
+}
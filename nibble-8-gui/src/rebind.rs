@@ -0,0 +1,176 @@
+use nibble_8_core::memory::FONTSET;
+use sdl2::keyboard::Scancode;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::keymap::{Action, Keymap};
+use crate::palette::Palette;
+
+/// The keypad keys as laid out on the COSMAC VIP.
+const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Something the rebinding screen asks a key for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Keypad(u8),
+    Action(Action),
+}
+
+impl Target {
+    fn at(step: usize) -> Option<Self> {
+        match step.checked_sub(KEYPAD_LAYOUT.len()) {
+            None => Some(Target::Keypad(KEYPAD_LAYOUT[step])),
+            Some(index) => Action::ALL.get(index).map(|&action| Target::Action(action)),
+        }
+    }
+}
+
+/// Walks through the keypad keys and then the hotkeys, asking for a key for each. Escape keeps
+/// the current binding.
+pub struct Rebinding {
+    step: usize,
+    keymap: Keymap,
+    changed: bool,
+}
+
+impl Rebinding {
+    pub fn new(keymap: &Keymap) -> Self {
+        Self {
+            step: 0,
+            keymap: keymap.clone(),
+            changed: false,
+        }
+    }
+
+    /// Binds the current target to `scancode` and moves on. Returns true once everything has been
+    /// asked for.
+    pub fn press(&mut self, scancode: Scancode) -> bool {
+        let Some(target) = Target::at(self.step) else {
+            return true;
+        };
+        if scancode != Scancode::Escape && self.current(target) != Some(scancode) {
+            match target {
+                Target::Keypad(key) => self.keymap.bind_keypad(key, scancode),
+                Target::Action(action) => self.keymap.bind_action(action, scancode),
+            }
+            self.changed = true;
+        }
+        self.step += 1;
+        Target::at(self.step).is_none()
+    }
+
+    /// The new keymap, `None` if nothing changed.
+    pub fn finish(self) -> Option<Keymap> {
+        self.changed.then_some(self.keymap)
+    }
+
+    /// What to press next, for the window title.
+    pub fn prompt(&self, name: impl Fn(Scancode) -> String) -> String {
+        let Some(target) = Target::at(self.step) else {
+            return String::new();
+        };
+        let what = match target {
+            Target::Keypad(key) => format!("keypad {:X}", key),
+            Target::Action(action) => action.name().replace('_', " "),
+        };
+        let now = self.current(target).map_or("unbound".to_string(), name);
+        format!("press a key for {} (now {}), Esc keeps it", what, now)
+    }
+
+    fn current(&self, target: Target) -> Option<Scancode> {
+        match target {
+            Target::Keypad(key) => self.keymap.keypad_scancode(key),
+            Target::Action(action) => self.keymap.scancode(action),
+        }
+    }
+
+    /// Draws the keypad in the middle of the window with the key being bound highlighted.
+    pub fn draw(&self, canvas: &mut Canvas<Window>, palette: Palette) {
+        let (width, height) = canvas.output_size().unwrap();
+        // A cell is a 4x5 glyph with a border of one pixel around it
+        let pixel = (height / 40).max(1);
+        let cell = 6 * pixel;
+        let left = (width - 4 * cell) as i32 / 2;
+        let top = (height - 4 * cell) as i32 / 2;
+        let selected = match Target::at(self.step) {
+            Some(Target::Keypad(key)) => Some(key),
+            _ => None,
+        };
+
+        canvas.set_draw_color(palette.background());
+        canvas
+            .fill_rect(Rect::new(left, top, 4 * cell, 4 * cell))
+            .unwrap();
+        for (index, &key) in KEYPAD_LAYOUT.iter().enumerate() {
+            let x = left + (index % 4) as i32 * cell as i32;
+            let y = top + (index / 4) as i32 * cell as i32;
+            let (cell_color, glyph_color) = if selected == Some(key) {
+                (palette.foreground(), palette.background())
+            } else {
+                (palette.background(), palette.foreground())
+            };
+
+            canvas.set_draw_color(cell_color);
+            canvas.fill_rect(Rect::new(x, y, cell, cell)).unwrap();
+            canvas.set_draw_color(glyph_color);
+            let glyph = &FONTSET[key as usize * 5..][..5];
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..4 {
+                    if bits & (0x80 >> column) != 0 {
+                        let rect = Rect::new(
+                            x + (column + 1) * pixel as i32,
+                            y + (row as i32 + 1) * pixel as i32,
+                            pixel,
+                            pixel,
+                        );
+                        canvas.fill_rect(rect).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press_all(screen: &mut Rebinding, scancode: Scancode) -> usize {
+        let mut presses = 1;
+        while !screen.press(scancode) {
+            presses += 1;
+        }
+        presses
+    }
+
+    #[test]
+    fn test_escape_keeps_everything() {
+        let mut screen = Rebinding::new(&Keymap::default_profile());
+        assert_eq!(
+            press_all(&mut screen, Scancode::Escape),
+            16 + Action::ALL.len()
+        );
+        assert_eq!(screen.finish(), None);
+    }
+
+    #[test]
+    fn test_rebinding() {
+        let mut screen = Rebinding::new(&Keymap::default_profile());
+        assert_eq!(
+            screen.prompt(|scancode| scancode.name().to_string()),
+            "press a key for keypad 1 (now 1), Esc keeps it"
+        );
+        assert!(!screen.press(Scancode::Up));
+        // Pressing the bound key again keeps it too
+        assert!(!screen.press(Scancode::Num2));
+        press_all(&mut screen, Scancode::Escape);
+
+        let keymap = screen.finish().unwrap();
+        assert_eq!(keymap.keypad_key(Scancode::Up), Some(0x1));
+        assert_eq!(keymap.keypad_key(Scancode::Num1), None);
+        assert_eq!(keymap.keypad_key(Scancode::Num2), Some(0x2));
+    }
+}
@@ -0,0 +1,210 @@
+use nibble_8_core::args::{parse_number, parse_platform, parse_quirks, parse_tickrate};
+use nibble_8_core::filter::FilterMode;
+use nibble_8_core::{Platform, Quirks};
+use std::time::Duration;
+
+use crate::keys::{DEFAULT_KEYS, KeyMap};
+use crate::screen::Blocks;
+
+pub const USAGE: &str = "\
+Usage: nibble-8-tui <ROM> [options]
+
+Runs a ROM in the terminal.
+
+Options:
+  --platform <chip8|schip|xochip>  Machine to emulate (default: from the ROM
+                                   database, else chip8)
+  --quirks <preset>                vip, chip48, schip, xochip or modern
+                                   (default: from the ROM database, else the
+                                   platform's usual quirks)
+  --ipf <N>                        CPU speed in instructions per frame
+                                   (default: from the ROM database, else 10)
+  --rom-db <FILE>                  JSON file of local ROM database overrides
+  --blocks <half|braille>          Characters the display is drawn with, half
+                                   blocks show the XO-CHIP planes in color
+                                   (default: half)
+  --filter <mode>                  Flicker reduction: off, decay, deflicker or sync
+                                   (default: deflicker)
+  --keys <KEYS>                    16 keys for the keypad rows 123C 456D 789E A0BF
+                                   (default: 1234qwerasdfzxcv)
+  --key-timeout <MS>               Terminals only report key presses, a key counts
+                                   as released this long after its last press or
+                                   repeat (default: 200)
+  --no-panel                       Hide the registers and disassembly
+  --paused                         Start paused, F5 resumes
+  -h, --help                       Print this help
+
+Keys:
+  Esc quits, F5 pauses, F6 advances a frame and F8 resets.";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    pub rom_db: Option<String>,
+    pub blocks: Blocks,
+    pub filter: FilterMode,
+    pub keys: KeyMap,
+    pub key_timeout: Duration,
+    pub panel: bool,
+    pub paused: bool,
+}
+
+/// Parses the command line, `Ok(None)` means help was requested.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut instructions_per_frame = None;
+    let mut rom_db = None;
+    let mut blocks = Blocks::Half;
+    let mut filter = FilterMode::Deflicker;
+    let mut keys = KeyMap::parse(DEFAULT_KEYS)?;
+    let mut key_timeout = Duration::from_millis(200);
+    let mut panel = true;
+    let mut paused = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--platform" => platform = Some(parse_platform(&value("--platform")?)?),
+            "--quirks" => quirks = Some(parse_quirks(&value("--quirks")?)?),
+            "--ipf" => instructions_per_frame = Some(parse_tickrate(&value("--ipf")?)?),
+            "--rom-db" => rom_db = Some(value("--rom-db")?),
+            "--blocks" => {
+                let name = value("--blocks")?;
+                blocks = Blocks::from_name(&name)
+                    .ok_or_else(|| format!("Unknown block style '{}'", name))?;
+            }
+            "--filter" => {
+                let name = value("--filter")?;
+                filter = FilterMode::from_name(&name)
+                    .ok_or_else(|| format!("Unknown filter '{}'", name))?;
+            }
+            "--keys" => keys = KeyMap::parse(&value("--keys")?)?,
+            "--key-timeout" => {
+                let text = value("--key-timeout")?;
+                let millis: u64 = parse_number(&text)?;
+                if !(20..=2000).contains(&millis) {
+                    return Err(format!("Invalid key timeout '{}', expected 20-2000", text));
+                }
+                key_timeout = Duration::from_millis(millis);
+            }
+            "--no-panel" => panel = false,
+            "--paused" => paused = true,
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => {
+                if rom_path.replace(path.to_string()).is_some() {
+                    return Err("Only one ROM can be given".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Some(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        platform,
+        quirks,
+        instructions_per_frame,
+        rom_db,
+        blocks,
+        filter,
+        keys,
+        key_timeout,
+        panel,
+        paused,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Option<Options>, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse_args(&["snake.ch8"]).unwrap().unwrap();
+
+        assert_eq!(options.rom_path, "snake.ch8");
+        assert_eq!(options.platform, None);
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.instructions_per_frame, None);
+        assert_eq!(options.blocks, Blocks::Half);
+        assert_eq!(options.filter, FilterMode::Deflicker);
+        assert_eq!(options.keys.key('q'), Some(0x4));
+        assert_eq!(options.key_timeout, Duration::from_millis(200));
+        assert!(options.panel);
+        assert!(!options.paused);
+    }
+
+    #[test]
+    fn test_all_options() {
+        let options = parse_args(&[
+            "--platform",
+            "schip",
+            "--ipf",
+            "30",
+            "--blocks",
+            "braille",
+            "--filter",
+            "off",
+            "--keys",
+            "7890uiopjkl;m,./",
+            "--key-timeout",
+            "500",
+            "--no-panel",
+            "--paused",
+            "game.ch8",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(options.platform, Some(Platform::SuperChip));
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.instructions_per_frame, Some(30));
+        assert_eq!(options.blocks, Blocks::Braille);
+        assert_eq!(options.filter, FilterMode::Off);
+        assert_eq!(options.keys.key('u'), Some(0x4));
+        assert_eq!(options.keys.key('q'), None);
+        assert_eq!(options.key_timeout, Duration::from_millis(500));
+        assert!(!options.panel);
+        assert!(options.paused);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_args(&[]), Err("Missing ROM path".to_string()));
+        assert_eq!(
+            parse_args(&["a.ch8", "--blocks", "ascii"]),
+            Err("Unknown block style 'ascii'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--keys", "1234"]),
+            Err("Expected 16 keys, got 4".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--key-timeout", "5"]),
+            Err("Invalid key timeout '5', expected 20-2000".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--fullscreen"]),
+            Err("Unknown option '--fullscreen'".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.ch8", "--ipf", "1000001"]),
+            Err("Invalid instructions per frame '1000001', expected 1-1000000".to_string())
+        );
+        assert_eq!(parse_args(&["--help"]), Ok(None));
+    }
+}
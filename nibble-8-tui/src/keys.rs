@@ -0,0 +1,168 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use nibble_8_core::Bus;
+use nibble_8_core::memory::KEY_COUNT;
+use std::time::Duration;
+
+/// The keypad keys as laid out on the COSMAC VIP, the order `--keys` lists them in.
+const KEYPAD_LAYOUT: [u8; KEY_COUNT] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// The left hand block of a QWERTY keyboard.
+pub const DEFAULT_KEYS: &str = "1234qwerasdfzxcv";
+
+/// Which character types which keypad key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    /// Indexed by keypad key.
+    chars: [char; KEY_COUNT],
+}
+
+impl KeyMap {
+    /// Parses 16 characters in `KEYPAD_LAYOUT` order.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let typed: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if typed.len() != KEY_COUNT {
+            return Err(format!("Expected 16 keys, got {}", typed.len()));
+        }
+        let mut chars = [' '; KEY_COUNT];
+        for (index, &c) in typed.iter().enumerate() {
+            if typed[..index].contains(&c) {
+                return Err(format!("Key '{}' is given twice", c));
+            }
+            chars[KEYPAD_LAYOUT[index] as usize] = c;
+        }
+        Ok(Self { chars })
+    }
+
+    /// The keypad key `c` types, ignoring case so that Caps Lock doesn't get in the way.
+    pub fn key(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.chars
+            .iter()
+            .position(|&key| key == c)
+            .map(|key| key as u8)
+    }
+
+    /// The keypad key a key event types. Keys held with Ctrl or Alt are left to the shortcuts,
+    /// so that Ctrl+C quits even though C is a keypad key.
+    pub fn key_event(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<u8> {
+        match code {
+            KeyCode::Char(c)
+                if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                self.key(c)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The keypad as far as it can be told from the terminal.
+///
+/// Most terminals only send key presses, and auto-repeat while a key is held. A key is held from
+/// a press until `timeout` after the last press or repeat, which has to bridge the delay before
+/// auto-repeat kicks in. Terminals that report releases set no timeout.
+pub struct HeldKeys {
+    timeout: Option<Duration>,
+    /// When each key is released, `Duration::MAX` until a release is reported.
+    deadlines: [Option<Duration>; KEY_COUNT],
+}
+
+impl HeldKeys {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            deadlines: [None; KEY_COUNT],
+        }
+    }
+
+    /// A press or repeat of `key` at `now`, the time since some fixed start.
+    pub fn press(&mut self, key: u8, now: Duration) {
+        let deadline = self.timeout.map_or(Duration::MAX, |timeout| now + timeout);
+        self.deadlines[key as usize] = Some(deadline);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.deadlines[key as usize] = None;
+    }
+
+    pub fn release_all(&mut self) {
+        self.deadlines = [None; KEY_COUNT];
+    }
+
+    /// Releases the keys whose timeout ran out by `now`.
+    pub fn expire(&mut self, now: Duration) {
+        for deadline in &mut self.deadlines {
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                *deadline = None;
+            }
+        }
+    }
+
+    pub fn is_held(&self, key: u8) -> bool {
+        self.deadlines[key as usize].is_some()
+    }
+
+    pub fn apply(&self, bus: &mut Bus) {
+        for key in 0..KEY_COUNT as u8 {
+            bus.set_key(key, self.is_held(key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_map() {
+        let keys = KeyMap::parse(DEFAULT_KEYS).unwrap();
+        assert_eq!(keys.key('1'), Some(0x1));
+        assert_eq!(keys.key('4'), Some(0xC));
+        assert_eq!(keys.key('x'), Some(0x0));
+        assert_eq!(keys.key('V'), Some(0xF));
+        assert_eq!(keys.key('p'), None);
+
+        assert_eq!(
+            keys.key_event(KeyCode::Char('C'), KeyModifiers::SHIFT),
+            Some(0xB)
+        );
+        assert_eq!(
+            keys.key_event(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            None
+        );
+        assert_eq!(keys.key_event(KeyCode::Char('x'), KeyModifiers::ALT), None);
+
+        assert_eq!(
+            KeyMap::parse("1234qwerasdfzxcq"),
+            Err("Key 'q' is given twice".to_string())
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let ms = Duration::from_millis;
+        let mut keys = HeldKeys::new(Some(ms(200)));
+        keys.press(0x5, ms(0));
+        keys.expire(ms(150));
+        assert!(keys.is_held(0x5));
+
+        // Auto-repeat keeps the key held
+        keys.press(0x5, ms(180));
+        keys.expire(ms(300));
+        assert!(keys.is_held(0x5));
+        keys.expire(ms(380));
+        assert!(!keys.is_held(0x5));
+    }
+
+    #[test]
+    fn test_reported_releases() {
+        let mut keys = HeldKeys::new(None);
+        keys.press(0xA, Duration::ZERO);
+        keys.expire(Duration::from_secs(60));
+        assert!(keys.is_held(0xA));
+        keys.release(0xA);
+        assert!(!keys.is_held(0xA));
+    }
+}
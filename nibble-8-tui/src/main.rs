@@ -0,0 +1,272 @@
+mod args;
+mod keys;
+mod panel;
+mod screen;
+
+use args::{Options, USAGE};
+use crossterm::cursor::{Hide, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::execute;
+use crossterm::terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+    supports_keyboard_enhancement,
+};
+use keys::HeldKeys;
+use nibble_8_core::database::{RomDatabase, RomProfile};
+use nibble_8_core::filter::DisplayFilter;
+use nibble_8_core::memory::{load_rpl_flags, rpl_flags_path, save_rpl_flags};
+use nibble_8_core::rng::Pcg32Source;
+use nibble_8_core::scheduler::{Scheduler, SystemClock};
+use nibble_8_core::{Bus, Cpu, EmulationError};
+use screen::Screen;
+use std::fs::{read, read_to_string};
+use std::io::{self, Stdout, stdout};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+pub fn main() -> ExitCode {
+    let options = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let rom = match read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", options.rom_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut rom_database = RomDatabase::bundled();
+    if let Some(path) = &options.rom_db
+        && let Err(err) = read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                rom_database
+                    .load_overrides(&json)
+                    .map_err(|err| err.to_string())
+            })
+    {
+        eprintln!("error: Failed to read '{}': {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    let profile = rom_database.rom_profile(&rom).with_overrides(
+        options.platform,
+        options.quirks,
+        options.instructions_per_frame,
+    );
+
+    let (cpu, mut bus) = match profile.power_on(&rom, Box::new(Pcg32Source::random())) {
+        Ok(machine) => machine,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let rpl_path = rpl_flags_path(&options.rom_path);
+    let rpl_flags = match load_rpl_flags(&rpl_path) {
+        Ok(flags) => flags,
+        Err(err) => {
+            eprintln!("error: Failed to read '{}': {}", rpl_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    bus.set_rpl_flags(&rpl_flags);
+
+    let result = Terminal::enter().and_then(|mut terminal| {
+        run(
+            &mut terminal,
+            (cpu, bus),
+            &rom,
+            &profile,
+            &options,
+            &rpl_path,
+        )
+        // Dropping the terminal restores it before anything is printed
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Raw mode on the alternate screen for as long as it lives.
+struct Terminal {
+    out: Stdout,
+    /// The terminal reports key releases, see `HeldKeys`.
+    reports_releases: bool,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut terminal = Terminal {
+            out: stdout(),
+            reports_releases: false,
+        };
+        execute!(
+            terminal.out,
+            EnterAlternateScreen,
+            Hide,
+            Clear(ClearType::All)
+        )?;
+        if supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                terminal.out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            terminal.reports_releases = true;
+        }
+        Ok(terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn run(
+    terminal: &mut Terminal,
+    (mut cpu, mut bus): (Cpu, Bus),
+    rom: &[u8],
+    profile: &RomProfile,
+    options: &Options,
+    rpl_path: &str,
+) -> io::Result<()> {
+    let mut scheduler = Scheduler::new(Box::new(SystemClock::new()), profile.tickrate * 60);
+    scheduler.set_paused(options.paused);
+    let mut filter = DisplayFilter::new(options.filter);
+    filter.reset(&bus);
+    let mut held = HeldKeys::new((!terminal.reports_releases).then_some(options.key_timeout));
+    let mut screen = Screen::default();
+    let mut fault: Option<EmulationError> = None;
+    // What the file has, saved again whenever the ROM changes them
+    let mut rpl_flags = *bus.rpl_flags();
+    let mut rpl_error: Option<io::Error> = None;
+    let start = Instant::now();
+
+    loop {
+        let mut timeout = scheduler.until_next_tick();
+        while event::poll(timeout)? {
+            timeout = Duration::ZERO;
+            let event = event::read()?;
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind,
+                ..
+            }) = event
+            else {
+                if let Event::Resize(..) = event {
+                    execute!(terminal.out, Clear(ClearType::All))?;
+                    screen.invalidate();
+                }
+                continue;
+            };
+
+            if let Some(key) = options.keys.key_event(code, modifiers) {
+                match kind {
+                    KeyEventKind::Press | KeyEventKind::Repeat => held.press(key, start.elapsed()),
+                    KeyEventKind::Release => held.release(key),
+                }
+                continue;
+            }
+            if kind != KeyEventKind::Press {
+                continue;
+            }
+            match code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::F(5) => scheduler.set_paused(!scheduler.is_paused()),
+                KeyCode::F(6) => scheduler.frame_advance(),
+                KeyCode::F(8) => {
+                    (cpu, bus) = profile
+                        .power_on(rom, Box::new(Pcg32Source::random()))
+                        .map_err(io::Error::other)?;
+                    bus.set_rpl_flags(&rpl_flags);
+                    fault = None;
+                    filter.reset(&bus);
+                    held.release_all();
+                }
+                _ => {}
+            }
+        }
+
+        held.expire(start.elapsed());
+        held.apply(&mut bus);
+        for _ in 0..scheduler.due_ticks() {
+            // A halted machine has nothing left to draw
+            let mut settled = true;
+            if fault.is_none() {
+                match scheduler.run_tick(&mut cpu, &mut bus) {
+                    Ok(outcome) => settled = outcome.settled,
+                    Err(err) => fault = Some(err),
+                }
+            } else {
+                cpu.decrease_timers();
+            }
+            filter.push(&bus, settled);
+        }
+        if bus.rpl_flags() != &rpl_flags {
+            rpl_flags = *bus.rpl_flags();
+            rpl_error = save_rpl_flags(rpl_path, &rpl_flags).err();
+        }
+
+        let planes: Vec<u8> = (filter.output().iter())
+            .map(|&[plane1, plane2]| (plane1 > 0) as u8 | ((plane2 > 0) as u8) << 1)
+            .collect();
+        let display = screen::render(&planes, filter.width(), options.blocks);
+        let panel = if options.panel {
+            panel::lines(&cpu, &bus)
+        } else {
+            Vec::new()
+        };
+        screen.draw(
+            &mut terminal.out,
+            display,
+            &status(&scheduler, &cpu, &fault, &rpl_error, profile),
+            &panel,
+        )?;
+    }
+}
+
+/// The line under the display.
+fn status(
+    scheduler: &Scheduler,
+    cpu: &Cpu,
+    fault: &Option<EmulationError>,
+    rpl_error: &Option<io::Error>,
+    profile: &RomProfile,
+) -> String {
+    if let Some(err) = fault {
+        format!("halted: {}", err)
+    } else if let Some(err) = rpl_error {
+        format!("can't save the RPL flags: {}", err)
+    } else if cpu.is_halted() {
+        "exited, F8 resets".to_string()
+    } else if scheduler.is_paused() {
+        "paused".to_string()
+    } else {
+        profile.title.clone().unwrap_or_default()
+    }
+}
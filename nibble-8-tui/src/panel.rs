@@ -0,0 +1,112 @@
+use nibble_8_core::disasm::decode_at;
+use nibble_8_core::memory::ROM_START;
+use nibble_8_core::{Bus, Cpu};
+
+/// Disassembly lines shown before and after the PC.
+const LINES_BEFORE_PC: usize = 4;
+const LINES_AFTER_PC: usize = 10;
+
+/// The registers, timers, stack and the code around the PC, one string per line.
+pub fn lines(cpu: &Cpu, bus: &Bus) -> Vec<String> {
+    let mut lines = vec![
+        format!("PC {:#05X}  I {:#05X}", cpu.pc(), cpu.i()),
+        format!(
+            "DT {:3}  ST {:3}  SP {}",
+            cpu.delay_timer(),
+            cpu.sound_timer(),
+            cpu.sp()
+        ),
+    ];
+    for (row, values) in cpu.v_registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(offset, value)| format!("V{:X} {:02X}", row * 4 + offset, value))
+            .collect();
+        lines.push(registers.join("  "));
+    }
+    // Innermost first, the rest is cut off by the panel width
+    let stack: Vec<String> = (cpu.stack().iter().rev())
+        .map(|addr| format!("{:#05X}", addr))
+        .collect();
+    lines.push(format!("Stack {}", stack.join(" ")));
+    lines.push(String::new());
+    lines.extend(disassembly(bus, cpu.pc(), LINES_BEFORE_PC, LINES_AFTER_PC));
+    lines
+}
+
+/// Disassembles from `before` instructions ahead of `pc` to `after` instructions past it.
+///
+/// Going backwards is guesswork since instructions can be two or four bytes long and data can be
+/// anywhere, so this starts `before` words back and makes sure to land on `pc`.
+fn disassembly(bus: &Bus, pc: u16, before: usize, after: usize) -> Vec<String> {
+    let memory = bus.memory();
+    let rom = &memory[ROM_START as usize..];
+    let mut addr = pc.saturating_sub(2 * before as u16);
+    let mut lines = Vec::new();
+    while lines.len() <= before + after {
+        let Some(word) = memory.get(addr as usize..addr as usize + 2) else {
+            break;
+        };
+        let (text, len) = match decode_at(rom, addr, bus.platform()) {
+            Some((instruction, len)) => (instruction.to_string(), len),
+            None => (
+                format!("DW {:#06X}", u16::from_be_bytes([word[0], word[1]])),
+                2,
+            ),
+        };
+        let marker = if addr == pc { '>' } else { ' ' };
+        lines.push(format!("{} {:#05X}  {}", marker, addr, text));
+
+        let next = addr.wrapping_add(len);
+        addr = if addr < pc && next > pc { pc } else { next };
+        if addr == 0 {
+            break;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nibble_8_core::asm::assemble;
+    use nibble_8_core::cpu::ThreadRngSource;
+    use nibble_8_core::{Platform, Quirks};
+
+    fn machine(source: &str, steps: usize) -> (Cpu, Bus) {
+        let mut bus = Bus::with_platform(Platform::XoChip);
+        bus.load_rom(&assemble(source).unwrap().bytes).unwrap();
+        let mut cpu = Cpu::new(Box::new(ThreadRngSource::new()), Quirks::xochip());
+        for _ in 0..steps {
+            cpu.step(&mut bus).unwrap();
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_registers() {
+        let (cpu, bus) = machine("LD V3, 0x2A\nLD DT, V3\nCALL sub\nsub:\nJP sub", 3);
+        let lines = lines(&cpu, &bus);
+
+        assert_eq!(lines[0], "PC 0x206  I 0x000");
+        assert_eq!(lines[1], "DT  42  ST   0  SP 1");
+        assert_eq!(lines[2], "V0 00  V1 00  V2 00  V3 2A");
+        assert_eq!(lines[6], "Stack 0x206");
+    }
+
+    #[test]
+    fn test_disassembly() {
+        let (cpu, bus) = machine("CLS\nLD I, long 0x1234\nCLS\nloop:\nJP loop", 2);
+        assert_eq!(
+            disassembly(&bus, cpu.pc(), 2, 1),
+            [
+                // Two words back is the four byte instruction before the PC
+                "  0x202  LD I, LONG 0x1234",
+                "> 0x206  CLS",
+                "  0x208  JP 0x208",
+                "  0x20A  DW 0x0000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use crossterm::cursor::MoveTo;
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{QueueableCommand, queue};
+use std::io::{self, Write};
+
+/// Colors by the bitmask of lit planes, like the mono palette of the GUI.
+const PLANE_COLORS: [Color; 4] = [Color::Black, Color::White, Color::DarkGrey, Color::Grey];
+/// Columns between the display and the panel.
+const PANEL_GAP: u16 = 2;
+/// Panel lines are padded to this width to overwrite what was there before.
+const PANEL_WIDTH: usize = 32;
+
+/// How display pixels are packed into characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Blocks {
+    /// `▀` with the top pixel in the foreground color and the bottom one in the background, 1x2
+    /// pixels per character in all four plane colors.
+    #[default]
+    Half,
+    /// Braille dots, 2x4 pixels per character. Every lit plane looks the same.
+    Braille,
+}
+
+impl Blocks {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "half" => Some(Blocks::Half),
+            "braille" => Some(Blocks::Braille),
+            _ => None,
+        }
+    }
+
+    /// Pixels per character horizontally and vertically.
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Blocks::Half => (1, 2),
+            Blocks::Braille => (2, 4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub symbol: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+/// The display as rows of characters. `planes` holds a bitmask of the lit planes per pixel, row
+/// by row, `width` pixels per row.
+pub fn render(planes: &[u8], width: usize, blocks: Blocks) -> Vec<Vec<Cell>> {
+    let height = planes.len() / width;
+    let (cell_width, cell_height) = blocks.cell_size();
+    let pixel = |x: usize, y: usize| planes[y * width + x] as usize;
+
+    (0..height.div_ceil(cell_height))
+        .map(|row| {
+            (0..width.div_ceil(cell_width))
+                .map(|column| {
+                    let (x, y) = (column * cell_width, row * cell_height);
+                    match blocks {
+                        Blocks::Half => Cell {
+                            symbol: '▀',
+                            fg: PLANE_COLORS[pixel(x, y)],
+                            bg: PLANE_COLORS[if y + 1 < height { pixel(x, y + 1) } else { 0 }],
+                        },
+                        Blocks::Braille => {
+                            // Dots 1-3 and 4-6 run down the columns, 7 and 8 are the bottom row
+                            const DOTS: [[u32; 2]; 4] =
+                                [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                            let mut bits = 0;
+                            for (dy, row_dots) in DOTS.iter().enumerate() {
+                                for (dx, dot) in row_dots.iter().enumerate() {
+                                    if x + dx < width
+                                        && y + dy < height
+                                        && pixel(x + dx, y + dy) != 0
+                                    {
+                                        bits |= dot;
+                                    }
+                                }
+                            }
+                            Cell {
+                                symbol: char::from_u32(0x2800 + bits).unwrap(),
+                                fg: PLANE_COLORS[1],
+                                bg: PLANE_COLORS[0],
+                            }
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// What is on the terminal, so that only changes have to be sent. Over SSH a full redraw every
+/// frame is easily too much.
+#[derive(Default)]
+pub struct Screen {
+    display: Vec<Vec<Cell>>,
+    status: String,
+    panel: Vec<String>,
+}
+
+impl Screen {
+    /// Forgets what is on the terminal so that the next `draw` writes everything, after the
+    /// terminal was resized for example.
+    pub fn invalidate(&mut self) {
+        *self = Screen::default();
+    }
+
+    /// Writes the display with the status line under it and the panel to the right of it.
+    pub fn draw(
+        &mut self,
+        out: &mut impl Write,
+        display: Vec<Vec<Cell>>,
+        status: &str,
+        panel: &[String],
+    ) -> io::Result<()> {
+        let resized = display.len() != self.display.len()
+            || display.first().map(Vec::len) != self.display.first().map(Vec::len);
+        if resized {
+            out.queue(ResetColor)?.queue(Clear(ClearType::All))?;
+            self.invalidate();
+        }
+
+        let (mut colors, mut cursor) = (None, None);
+        for (y, row) in display.iter().enumerate() {
+            let old_row = self.display.get(y);
+            for (x, &cell) in row.iter().enumerate() {
+                if old_row.and_then(|old| old.get(x)) == Some(&cell) {
+                    continue;
+                }
+                // Runs of changed cells only need the cursor moved once
+                if cursor != Some((x, y)) {
+                    out.queue(MoveTo(x as u16, y as u16))?;
+                }
+                cursor = Some((x + 1, y));
+                if colors != Some((cell.fg, cell.bg)) {
+                    queue!(
+                        out,
+                        SetForegroundColor(cell.fg),
+                        SetBackgroundColor(cell.bg)
+                    )?;
+                    colors = Some((cell.fg, cell.bg));
+                }
+                out.queue(Print(cell.symbol))?;
+            }
+        }
+        out.queue(ResetColor)?;
+
+        let width = display.first().map_or(0, Vec::len);
+        let height = display.len() as u16;
+        if status != self.status {
+            queue!(
+                out,
+                MoveTo(0, height),
+                Print(format!("{:<1$}", status, width))
+            )?;
+            self.status = status.to_string();
+        }
+
+        let left = width as u16 + PANEL_GAP;
+        let rows = panel.len().max(self.panel.len());
+        for row in 0..rows {
+            let line = panel.get(row).map_or("", String::as_str);
+            if self.panel.get(row).map(String::as_str) == Some(line) {
+                continue;
+            }
+            let line: String = line.chars().take(PANEL_WIDTH).collect();
+            queue!(
+                out,
+                MoveTo(left, row as u16),
+                Print(format!("{:<1$}", line, PANEL_WIDTH))
+            )?;
+        }
+        self.panel = panel.to_vec();
+        self.display = display;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(display: &[Vec<Cell>]) -> Vec<String> {
+        display
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.symbol).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        #[rustfmt::skip]
+        let planes = [
+            1, 0, 2,
+            3, 0, 0,
+            0, 1, 0,
+        ];
+        let display = render(&planes, 3, Blocks::Half);
+
+        assert_eq!(display.len(), 2);
+        let colors: Vec<(Color, Color)> =
+            display[0].iter().map(|cell| (cell.fg, cell.bg)).collect();
+        assert_eq!(
+            colors,
+            [
+                (Color::White, Color::Grey),
+                (Color::Black, Color::Black),
+                (Color::DarkGrey, Color::Black),
+            ]
+        );
+        // The odd row at the bottom is padded with background
+        assert_eq!(
+            (display[1][1].fg, display[1][1].bg),
+            (Color::White, Color::Black)
+        );
+    }
+
+    #[test]
+    fn test_braille() {
+        #[rustfmt::skip]
+        let planes = [
+            1, 0, 0, 0,
+            0, 2, 0, 0,
+            0, 0, 0, 0,
+            3, 1, 0, 0,
+        ];
+        assert_eq!(symbols(&render(&planes, 4, Blocks::Braille)), ["⣑⠀"]);
+    }
+
+    #[test]
+    fn test_draw_sends_changes_only() {
+        let mut screen = Screen::default();
+        let mut out = Vec::new();
+        let display = render(&[0; 8], 4, Blocks::Half);
+        screen.draw(&mut out, display.clone(), "", &[]).unwrap();
+        assert!(!out.is_empty());
+
+        out.clear();
+        screen.draw(&mut out, display, "", &[]).unwrap();
+        // Only the color reset is left
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "\x1b[0m");
+
+        out.clear();
+        let mut planes = [0; 8];
+        planes[3] = 1;
+        screen
+            .draw(&mut out, render(&planes, 4, Blocks::Half), "", &[])
+            .unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert_eq!(written.matches('▀').count(), 1);
+        assert!(written.contains("\x1b[1;4H"));
+    }
+}